@@ -1,33 +1,233 @@
 use bevy::{ecs::system::{Command, CommandQueue}, prelude::*};
+use rand::{thread_rng, Rng};
+use strum::{EnumIter, IntoEnumIterator};
 
-use crate::{map::SpawnSettlementCommand, pops::{PopLanguage, PopSeekMigrationCommand}, prelude::*, probability::logistic};
+use crate::{buildings::building_options, character::{Character, PopLeader}, decision::FamineReliefCommand, diplomacy::diplomatic_options, fortification::fortification_options, treasury::taxation_options, map::SpawnSettlementCommand, migration::PopSeekMigrationCommand, pops::{Hunger, Polity, PopLanguage}, prelude::*, probability::logistic, war::war_options};
 
+// how many scored options are kept around for the debug view
+const DEBUG_OPTIONS: usize = 3;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, EnumIter)]
+pub enum Value {
+    Security,
+    Prosperity,
+    Expansion,
+    Tradition,
+}
+
+// what a polity cares about, the same situation scores differently for different personalities
+#[derive(Debug, Clone)]
 pub struct ValueAgent {
+    pub security: f32,
+    pub prosperity: f32,
+    pub expansion: f32,
+    pub tradition: f32,
+}
 
+impl Default for ValueAgent {
+    fn default() -> Self {
+        Self {
+            security: 1.0,
+            prosperity: 1.0,
+            expansion: 1.0,
+            tradition: 1.0,
+        }
+    }
 }
 
-pub trait Agent {
-    fn think(&self, world: &mut World) -> Vec<Box<dyn Command>>;
+impl ValueAgent {
+    pub fn random() -> Self {
+        let mut rng = thread_rng();
+        Self {
+            security: rng.gen_range(0.5..1.5),
+            prosperity: rng.gen_range(0.5..1.5),
+            expansion: rng.gen_range(0.5..1.5),
+            tradition: rng.gen_range(0.5..1.5),
+        }
+    }
+
+    pub fn weight(&self, value: Value) -> f32 {
+        match value {
+            Value::Security => self.security,
+            Value::Prosperity => self.prosperity,
+            Value::Expansion => self.expansion,
+            Value::Tradition => self.tradition,
+        }
+    }
+
+    // pops think along the lines of the polity they belong to
+    // and then along the lines of whoever speaks for them
+    pub fn of_pop(world: &World, pop: PopRef) -> Self {
+        let agent = Self::of_polity(world, *pop.get::<PolityRef>(world));
+        match pop.try_get::<PopLeader>(world) {
+            Some(leader) => agent.with_traits(&leader.0.get::<Character>(world).traits),
+            None => agent,
+        }
+    }
+
+    // rulers bend the polity's values toward their own
+    pub fn of_polity(world: &World, polity: PolityRef) -> Self {
+        let agent = polity.try_get::<ValueAgent>(world).cloned().unwrap_or_default();
+        match polity.ruler(world) {
+            Some(ruler) => agent.with_traits(&ruler.get::<Character>(world).traits),
+            None => agent,
+        }
+    }
 }
 
+// one reason an option is good or bad, in terms of one value
+pub struct Consideration {
+    pub value: Value,
+    pub score: f32,
+    pub reason: String,
+}
 
-impl Agent for PopRef {
+pub struct AgentOption {
+    pub name: &'static str,
+    pub considerations: Vec<Consideration>,
+    pub commands: Vec<Box<dyn Command>>,
+}
+
+impl AgentOption {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            considerations: Vec::new(),
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn consider(mut self, value: Value, score: f32, reason: String) -> Self {
+        self.considerations.push(Consideration { value, score, reason });
+        self
+    }
+
+    pub fn command(mut self, command: Box<dyn Command>) -> Self {
+        self.commands.push(command);
+        self
+    }
+
+    pub fn utility(&self, agent: &ValueAgent) -> f32 {
+        self.considerations.iter().map(|c| agent.weight(c.value) * c.score).sum()
+    }
+
+    pub fn explain(&self, agent: &ValueAgent) -> String {
+        let reasons = self
+            .considerations
+            .iter()
+            .map(|c| format!("{:?} {:+.2} ({})", c.value, agent.weight(c.value) * c.score, c.reason))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{} {:.2}: {}", self.name, self.utility(agent), reasons)
+    }
+}
+
+// the best few options from the last time this entity thought, best first
+#[derive(Default)]
+pub struct AgentDebug(pub Vec<String>);
+
+pub trait Agent: GameRef {
+    fn agent(&self, world: &World) -> ValueAgent;
+    fn options(&self, world: &World) -> Vec<AgentOption>;
+
+    // score everything, note down why and go with the best
     fn think(&self, world: &mut World) -> Vec<Box<dyn Command>> {
-        let migration_factor = self.get_factor(world, FactorType::PopPressure);
-        if migration_factor > 1.0 && individual_event(logistic(migration_factor)) {
-            // bad example
-            // println!("try migrate {:?} {:?}", self, self.get::<ProvinceRef>(world).get::<MapCoordinate>(world));
-            // println!("move pops");
-            vec![
-                Box::new(PopSeekMigrationCommand {
+        let agent = self.agent(world);
+        let mut options = self
+            .options(world)
+            .into_iter()
+            .map(|option| (option.utility(&agent), option))
+            .collect::<Vec<_>>();
+        options.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap());
+        let debug = options.iter().take(DEBUG_OPTIONS).map(|(_, option)| option.explain(&agent)).collect();
+        world.entity_mut(self.entity()).insert(AgentDebug(debug));
+        options.into_iter().next().map(|(_, option)| option.commands).unwrap_or_default()
+    }
+}
+
+impl Agent for PopRef {
+    fn agent(&self, world: &World) -> ValueAgent {
+        ValueAgent::of_pop(world, *self)
+    }
+
+    fn options(&self, world: &World) -> Vec<AgentOption> {
+        let pressure = self.get_factor(world, FactorType::PopPressure);
+        let hunger = self.get::<Hunger>(world).shortfall;
+        vec![
+            AgentOption::new("Migrate")
+                .consider(Value::Expansion, logistic(pressure - 1.0), format!("pressure {:.2}", pressure))
+                .consider(Value::Prosperity, hunger, format!("{:.0}% hungry", hunger * 100.0))
+                .consider(Value::Security, -0.2, "the road is dangerous".to_owned())
+                .consider(Value::Tradition, -0.2, "leaving home".to_owned())
+                .command(Box::new(PopSeekMigrationCommand {
                     pop: *self,
-                    pressure: migration_factor,
-                })
-            ]
+                    pressure,
+                })),
+            AgentOption::new("Stay")
+                .consider(Value::Tradition, 0.3, "the land of our fathers".to_owned())
+                .consider(Value::Prosperity, -hunger, format!("{:.0}% hungry", hunger * 100.0)),
+        ]
+    }
+}
+
+impl Agent for PolityRef {
+    fn agent(&self, world: &World) -> ValueAgent {
+        ValueAgent::of_polity(world, *self)
+    }
+
+    fn options(&self, world: &World) -> Vec<AgentOption> {
+        let pops = self.pops(world);
+        let total = pops.iter().map(|p| p.get::<Pop>(world).size).sum::<isize>().max(1) as f32;
+        let hungriest = pops
+            .iter()
+            .map(|p| (*p, p.get::<Hunger>(world).shortfall))
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+        let crowded = pops
+            .iter()
+            .map(|p| (*p, p.get_factor(world, FactorType::PopPressure)))
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+        let hungry_share = pops
+            .iter()
+            .map(|p| p.get::<Hunger>(world).shortfall * p.get::<Pop>(world).size as f32)
+            .sum::<f32>() / total;
+
+        let mut options = vec![
+            AgentOption::new("Hold")
+                .consider(Value::Tradition, 0.2, "keep things as they are".to_owned())
+                .consider(Value::Security, 0.1, "no risks taken".to_owned()),
+        ];
+        if let Some((pop, shortfall)) = hungriest.filter(|(_, shortfall)| *shortfall > 0.0) {
+            options.push(
+                AgentOption::new("Share food")
+                    .consider(Value::Prosperity, shortfall, format!("{:.0}% of a pop hungry", shortfall * 100.0))
+                    .consider(Value::Security, hungry_share, format!("{:.0}% of the realm hungry", hungry_share * 100.0))
+                    .consider(Value::Tradition, -0.1, "granaries opened".to_owned())
+                    .command(Box::new(FamineReliefCommand {
+                        pop,
+                        polity: *self,
+                        share: 1.0,
+                        radius: None,
+                    }))
+            );
         }
-        else {
-            Vec::new()
+        if let Some((pop, pressure)) = crowded.filter(|(_, pressure)| *pressure > 0.0) {
+            options.push(
+                AgentOption::new("Found colony")
+                    .consider(Value::Expansion, logistic(pressure) - 0.3, format!("pressure {:.2}", pressure))
+                    .consider(Value::Security, -0.2, "settlers spread thin".to_owned())
+                    .command(Box::new(PopSeekMigrationCommand {
+                        pop,
+                        // the polity pushes them along
+                        pressure: pressure + 1.0,
+                    }))
+            );
         }
+        options.extend(diplomatic_options(world, *self));
+        options.extend(war_options(world, *self));
+        options.extend(fortification_options(world, *self));
+        options.extend(taxation_options(world, *self));
+        options.extend(building_options(world, *self));
+        options
     }
 }
 
@@ -35,6 +235,23 @@ pub struct PopThinkCommand(pub PopRef);
 
 impl Command for PopThinkCommand {
     fn write(self: Box<Self>, world: &mut World) {
+        if world.get_entity(self.0.entity()).is_none() {
+            return;
+        }
+        let cmds = self.0.think(world);
+        for command in cmds.into_iter() {
+            command.write(world);
+        }
+    }
+}
+
+pub struct PolityThinkCommand(pub PolityRef);
+
+impl Command for PolityThinkCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        if world.get_entity(self.0.entity()).is_none() {
+            return;
+        }
         let cmds = self.0.think(world);
         for command in cmds.into_iter() {
             command.write(world);
@@ -46,6 +263,7 @@ fn think_system(
     mut commands: Commands,
     date: Res<CurrentDate>,
     pop_q: Query<(Entity, &Pop)>,
+    polity_q: Query<Entity, With<Polity>>,
 ) {
     if !date.is_month {
         return;
@@ -56,6 +274,11 @@ fn think_system(
             commands.add(PopThinkCommand(PopRef(pop_ent)));
         }
     }
+    for polity_ent in polity_q.iter() {
+        if individual_event(0.1) {
+            commands.add(PolityThinkCommand(PolityRef(polity_ent)));
+        }
+    }
 }
 
 pub struct AgentPlugin;
@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use rand::{thread_rng, Rng};
+use strum::{EnumIter, IntoEnumIterator};
+
+use crate::prelude::*;
+use crate::buildings::Buildings;
+use crate::calendar::CALENDAR;
+use crate::notification::Notifications;
+use crate::pops::{AgeCohorts, FarmingPop, GoodType};
+use crate::settlement::Settlement;
+use crate::time::DayOfYear;
+use crate::urbanization::Occupations;
+
+// days it takes to get the seed in, and the crop in
+const SOWING_DAYS: usize = 20;
+const HARVEST_DAYS: usize = 20;
+// a full crew gets through the fields with days to spare
+const FIELD_WORK_PACE: f32 = 1.5;
+// share of the working people still needed in the fields while the crop grows
+const OFF_SEASON_LABOR: f32 = 0.3;
+// what a drought or a late frost leaves of a month's growth
+const DROUGHT_YIELD: f32 = 0.7;
+const FROST_YIELD: f32 = 0.7;
+// rows of the map further north than these are temperate, then cold
+const TEMPERATE_LATITUDE: f32 = 50.0;
+const COLD_LATITUDE: f32 = 100.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, EnumIter)]
+pub enum Climate {
+    Mediterranean,
+    Temperate,
+    Cold,
+}
+
+impl Climate {
+    pub fn of(coord: MapCoordinate) -> Self {
+        let latitude = coord.y as f32 + 0.5 * coord.x as f32;
+        if latitude >= COLD_LATITUDE {
+            Climate::Cold
+        } else if latitude >= TEMPERATE_LATITUDE {
+            Climate::Temperate
+        } else {
+            Climate::Mediterranean
+        }
+    }
+
+    // wheat doesn't ripen in short northern summers
+    pub fn staple(&self) -> GoodType {
+        match self {
+            Climate::Cold => GoodType::Barley,
+            _ => GoodType::Wheat,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Climate::Mediterranean => "the warm south",
+            Climate::Temperate => "the temperate lands",
+            Climate::Cold => "the cold north",
+        }
+    }
+
+    // monthly chances while a crop is in the ground
+    fn drought_chance(&self) -> f32 {
+        match self {
+            Climate::Mediterranean => 0.05,
+            Climate::Temperate => 0.02,
+            Climate::Cold => 0.01,
+        }
+    }
+
+    fn frost_chance(&self) -> f32 {
+        match self {
+            Climate::Mediterranean => 0.0,
+            Climate::Temperate => 0.02,
+            Climate::Cold => 0.05,
+        }
+    }
+}
+
+// when a crop goes in and comes out of the ground
+#[derive(Debug, Clone, Copy)]
+pub struct CropSeason {
+    pub sow: DayOfYear,
+    pub harvest: DayOfYear,
+}
+
+impl CropSeason {
+    // southern wheat and barley are sown in autumn and brought in before the summer heat,
+    // further north barley goes in come spring, and in the cold everything waits for the thaw
+    pub fn of(crop: GoodType, climate: Climate) -> Self {
+        let (sow, harvest) = match (crop, climate) {
+            (GoodType::Barley, Climate::Mediterranean) => ((15, 11), (1, 5)),
+            (GoodType::Barley, Climate::Temperate) => ((15, 3), (15, 7)),
+            (GoodType::Barley, Climate::Cold) => ((15, 5), (15, 8)),
+            (_, Climate::Mediterranean) => ((1, 11), (1, 6)),
+            (_, Climate::Temperate) => ((1, 10), (1, 8)),
+            (_, Climate::Cold) => ((1, 5), (1, 9)),
+        };
+        Self {
+            sow: DayOfYear { day: sow.0, month: sow.1 },
+            harvest: DayOfYear { day: harvest.0, month: harvest.1 },
+        }
+    }
+
+    pub fn is_sowing(&self, date: Date) -> bool {
+        date.days_after_doy(self.sow) < SOWING_DAYS
+    }
+
+    pub fn is_harvest(&self, date: Date) -> bool {
+        date.days_after_doy(self.harvest) < HARVEST_DAYS
+    }
+
+    // the day after the last of the crop comes in, when it's counted and stored
+    pub fn is_harvest_over(&self, date: Date) -> bool {
+        date.days_after_doy(self.harvest) == HARVEST_DAYS
+    }
+
+    // between the sowing and the harvest
+    pub fn is_growing(&self, date: Date) -> bool {
+        let length = Date { day: self.harvest.day, month: self.harvest.month, year: 0 }.days_after_doy(self.sow);
+        date.days_after_doy(self.sow) < length
+    }
+
+    pub fn days_to_harvest(&self, date: Date) -> usize {
+        (CALENDAR.days_in_year() - date.days_after_doy(self.harvest)) % CALENDAR.days_in_year()
+    }
+
+    pub fn stage(&self, date: Date) -> &'static str {
+        if self.is_sowing(date) {
+            "sowing"
+        } else if self.is_harvest(date) {
+            "harvest"
+        } else if self.is_growing(date) {
+            "growing"
+        } else {
+            "fallow"
+        }
+    }
+
+    // share of the working people needed in the fields
+    pub fn labor_demand(&self, date: Date) -> f32 {
+        if self.is_sowing(date) || self.is_harvest(date) {
+            1.0
+        } else {
+            OFF_SEASON_LABOR
+        }
+    }
+
+    // share of the working people who can be spared from the fields
+    pub fn spare_labor(&self, date: Date) -> f32 {
+        1.0 + OFF_SEASON_LABOR - self.labor_demand(date)
+    }
+}
+
+// the old still help out in the fields, the young mostly get in the way, and those in a trade don't farm
+pub fn farm_workers(ages: &AgeCohorts, occupations: &Occupations) -> f32 {
+    (ages.working() as f32 + ages.elderly() as f32 * 0.5) * occupations.farmers()
+}
+
+// a farming pop's land through the year, measured in what one farmer can work
+#[derive(Debug, Clone, Copy)]
+pub struct Fields {
+    pub climate: Climate,
+    pub sown: f32,
+    pub reaped: f32,
+    // how kind the weather has been to the crop, 1.0 an ordinary year
+    pub weather: f32,
+}
+
+impl PopRef {
+    pub fn spare_labor(&self, world: &World) -> f32 {
+        let today = world.get_resource::<CurrentDate>().unwrap().date;
+        match (self.try_get::<FarmingPop>(world), self.try_get::<Fields>(world)) {
+            (Some(farming_pop), Some(fields)) => fields.season(farming_pop.good).spare_labor(today),
+            _ => 1.0,
+        }
+    }
+}
+
+impl Fields {
+    pub fn new(climate: Climate) -> Self {
+        Self {
+            climate,
+            sown: 0.0,
+            reaped: 0.0,
+            weather: 1.0,
+        }
+    }
+
+    // settlers starting out find the fields already in
+    pub fn sown(climate: Climate, workers: f32) -> Self {
+        Self {
+            sown: workers,
+            ..Self::new(climate)
+        }
+    }
+
+    pub fn season(&self, crop: GoodType) -> CropSeason {
+        CropSeason::of(crop, self.climate)
+    }
+
+    // the part of the crop that goes with people leaving
+    pub fn take_share(&mut self, share: f32) -> Fields {
+        let taken = Fields {
+            sown: self.sown * share,
+            reaped: self.reaped * share,
+            ..*self
+        };
+        self.sown -= taken.sown;
+        self.reaped -= taken.reaped;
+        taken
+    }
+
+    pub fn merge(&mut self, other: Fields) {
+        let sown = self.sown + other.sown;
+        if sown > 0.0 {
+            self.weather = (self.weather * self.sown + other.weather * other.sown) / sown;
+        }
+        self.sown = sown;
+        self.reaped += other.reaped;
+    }
+}
+
+// seed goes in and the crop comes out as fast as the people at home can manage,
+// so levies raised or works started at the wrong time leave fields bare or crops rotting
+fn field_work_system(
+    date: Res<CurrentDate>,
+    settlement_query: Query<(&Settlement, &Buildings)>,
+    mut pop_query: Query<(&Pop, &SettlementRef, &FarmingPop, &AgeCohorts, &Occupations, &mut Fields)>,
+) {
+    if !date.is_day {
+        return;
+    }
+    for (pop, settlement, farming_pop, ages, occupations, mut fields) in pop_query.iter_mut() {
+        let season = fields.season(farming_pop.good);
+        // each pop gives its share of the builders to whatever's going up in the settlement
+        let builders = settlement_query
+            .get(settlement.entity())
+            .ok()
+            .and_then(|(settlement, buildings)| {
+                let share = pop.size as f32 / settlement.population.max(1) as f32;
+                buildings.construction.map(|(kind, _)| kind.builders() * share)
+            })
+            .unwrap_or(0.0);
+        let workers = (farm_workers(ages, occupations) - builders).max(0.0);
+        let daily = |days: usize| workers * FIELD_WORK_PACE / days as f32;
+        if date.date.is_day_of_year(season.sow) {
+            fields.sown = 0.0;
+            fields.reaped = 0.0;
+            fields.weather = 1.0;
+        }
+        if season.is_sowing(date.date) {
+            fields.sown = (fields.sown + daily(SOWING_DAYS)).min(workers.max(fields.sown));
+        } else if season.is_harvest(date.date) {
+            fields.reaped = (fields.reaped + daily(HARVEST_DAYS)).min(fields.sown);
+        }
+    }
+}
+
+// each month the weather is rolled for every climate, and crops in the ground grow well or poorly with it
+fn weather_system(
+    date: Res<CurrentDate>,
+    mut notifications: ResMut<Notifications>,
+    mut pop_query: Query<(&FarmingPop, &mut Fields)>,
+) {
+    if !date.is_month {
+        return;
+    }
+    let mut rng = thread_rng();
+    let weather = Climate::iter()
+        .map(|climate| {
+            let mut growth = rng.gen_range(0.9..1.1);
+            if individual_event(climate.drought_chance()) {
+                growth *= DROUGHT_YIELD;
+                notifications.push(date.date, format!("Drought parches {}", climate.name()));
+            }
+            if individual_event(climate.frost_chance()) {
+                growth *= FROST_YIELD;
+                notifications.push(date.date, format!("A hard frost strikes {}", climate.name()));
+            }
+            (climate, growth)
+        })
+        .collect::<HashMap<_, _>>();
+    for (farming_pop, mut fields) in pop_query.iter_mut() {
+        if fields.season(farming_pop.good).is_growing(date.date) {
+            fields.weather *= weather[&fields.climate];
+        }
+    }
+}
+
+pub struct AgriculturePlugin;
+
+impl Plugin for AgriculturePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_system_to_day(field_work_system.system())
+            .add_system_to_day(weather_system.system());
+    }
+}
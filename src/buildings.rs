@@ -0,0 +1,394 @@
+use bevy::{ecs::system::Command, prelude::*};
+use strum::{EnumIter, IntoEnumIterator};
+
+use crate::prelude::*;
+use crate::agent::{AgentOption, Value};
+use crate::agriculture::Fields;
+use crate::decision::PlayerPolity;
+use crate::factor::FST;
+use crate::formula::FormulaSystem;
+use crate::fortification::{coastal, pay_for_works, Fortifications};
+use crate::notification::notify;
+use crate::pops::{FarmingPop, GoodStorage, MONTHLY_CALORIE_NEED};
+use crate::province::ProvinceMap;
+use crate::religion::Religion;
+use crate::settlement::{Settlement, SettlementPops};
+use crate::tag::Selected;
+use crate::technology::{Knowledge, Technology};
+use crate::ui::InfoTag;
+use crate::urbanization::SettlementTier;
+
+// monthly share of food stores lost to rot and rats, and with a granary
+const SPOILAGE: f32 = 0.015;
+const GRANARY_SPOILAGE: f32 = 0.004;
+// months of unpaid upkeep before a building falls into ruin
+const NEGLECT_MONTHS: usize = 12;
+// share of the builders' food that goes on keeping a building up each month
+const UPKEEP_SHARE: f32 = 0.05;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, EnumIter)]
+pub enum BuildingKind {
+    Granary,
+    Temple,
+    Harbor,
+    Market,
+    IrrigationWorks,
+}
+
+impl BuildingKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BuildingKind::Granary => "granary",
+            BuildingKind::Temple => "temple",
+            BuildingKind::Harbor => "harbor",
+            BuildingKind::Market => "market",
+            BuildingKind::IrrigationWorks => "irrigation works",
+        }
+    }
+
+    pub fn months(&self) -> usize {
+        match self {
+            BuildingKind::Granary => 6,
+            BuildingKind::Market => 6,
+            BuildingKind::Temple => 12,
+            BuildingKind::Harbor => 12,
+            BuildingKind::IrrigationWorks => 18,
+        }
+    }
+
+    // people kept off the fields to build it, fed while they work
+    pub fn builders(&self) -> f32 {
+        match self {
+            BuildingKind::Granary => 20.0,
+            BuildingKind::Market => 15.0,
+            BuildingKind::Temple => 30.0,
+            BuildingKind::Harbor => 30.0,
+            BuildingKind::IrrigationWorks => 40.0,
+        }
+    }
+
+    pub fn monthly_cost(&self) -> f32 {
+        self.builders() * MONTHLY_CALORIE_NEED
+    }
+
+    pub fn upkeep(&self) -> f32 {
+        self.monthly_cost() * UPKEEP_SHARE
+    }
+
+    pub fn requires(&self) -> Option<Technology> {
+        match self {
+            BuildingKind::Harbor => Some(Technology::Sailing),
+            BuildingKind::IrrigationWorks => Some(Technology::Irrigation),
+            BuildingKind::Temple => Some(Technology::Masonry),
+            _ => None,
+        }
+    }
+
+    pub fn carrying_capacity(&self) -> f32 {
+        match self {
+            BuildingKind::IrrigationWorks => 40.0,
+            _ => 0.0,
+        }
+    }
+}
+
+// what a settlement has built, and what it's building
+#[derive(Debug, Default)]
+pub struct Buildings {
+    pub built: Vec<BuildingKind>,
+    // what's going up and the months of work left on it
+    pub construction: Option<(BuildingKind, usize)>,
+    // months in a row the upkeep went unpaid
+    pub neglect: usize,
+}
+
+impl Buildings {
+    pub fn has(&self, kind: BuildingKind) -> bool {
+        self.built.contains(&kind)
+    }
+
+    pub fn spoilage(&self) -> f32 {
+        if self.has(BuildingKind::Granary) { GRANARY_SPOILAGE } else { SPOILAGE }
+    }
+
+    // more goods change hands, and more tolls are taken, where there's somewhere to trade
+    pub fn trade(&self) -> f32 {
+        let mut trade = 1.0;
+        if self.has(BuildingKind::Market) {
+            trade += 0.5;
+        }
+        if self.has(BuildingKind::Harbor) {
+            trade += 0.25;
+        }
+        trade
+    }
+
+    // loyalty people feel with a temple to go to
+    pub fn calm(&self) -> f32 {
+        if self.has(BuildingKind::Temple) { 0.2 } else { 0.0 }
+    }
+
+    pub fn upkeep(&self) -> f32 {
+        self.built.iter().map(|kind| kind.upkeep()).sum()
+    }
+}
+
+impl SettlementRef {
+    pub fn can_build(&self, world: &World, kind: BuildingKind) -> bool {
+        let buildings = match self.try_get::<Buildings>(world) {
+            Some(buildings) => buildings,
+            None => return false,
+        };
+        if buildings.has(kind) || buildings.construction.is_some() {
+            return false;
+        }
+        if let Some(technology) = kind.requires() {
+            if !self.knows(world, technology) {
+                return false;
+            }
+        }
+        kind != BuildingKind::Harbor || coastal(world, *self.get::<MapCoordinate>(world))
+    }
+}
+
+pub struct StartBuildingCommand {
+    pub settlement: SettlementRef,
+    pub kind: BuildingKind,
+}
+
+impl Command for StartBuildingCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        if world.get_entity(self.settlement.entity()).is_none() || !self.settlement.can_build(world, self.kind) {
+            return;
+        }
+        self.settlement.get_mut::<Buildings>(world).construction = Some((self.kind, self.kind.months()));
+        let text = format!("{} starts building a {}", self.settlement.get::<Settlement>(world).name, self.kind.name());
+        notify(world, text);
+    }
+}
+
+// a month of work on whatever's going up, and of keeping up what's already there
+pub struct BuildingMonthCommand(pub SettlementRef);
+
+impl BuildingMonthCommand {
+    fn ruin(world: &mut World, settlement: SettlementRef) {
+        let kind = match settlement.get_mut::<Buildings>(world).built.pop() {
+            Some(kind) => kind,
+            None => return,
+        };
+        world
+            .get_resource::<FormulaSystem<FST>>()
+            .unwrap()
+            .add_factor(&settlement.fst(FactorType::SettlementCarryingCapacity), -kind.carrying_capacity());
+        let text = format!("the {} of {} falls into ruin", kind.name(), settlement.get::<Settlement>(world).name);
+        notify(world, text);
+    }
+}
+
+impl Command for BuildingMonthCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let settlement = self.0;
+        if world.get_entity(settlement.entity()).is_none() {
+            return;
+        }
+        let upkeep = settlement.get::<Buildings>(world).upkeep();
+        if upkeep > 0.0 {
+            if pay_for_works(world, settlement, upkeep, "upkeep").is_some() {
+                settlement.get_mut::<Buildings>(world).neglect = 0;
+            } else {
+                let neglect = {
+                    let mut buildings = settlement.get_mut::<Buildings>(world);
+                    buildings.neglect += 1;
+                    buildings.neglect
+                };
+                if neglect >= NEGLECT_MONTHS {
+                    settlement.get_mut::<Buildings>(world).neglect = 0;
+                    Self::ruin(world, settlement);
+                }
+            }
+        }
+
+        let (kind, months) = match settlement.get::<Buildings>(world).construction {
+            Some(construction) => construction,
+            None => return,
+        };
+        // work stops when nobody can feed the builders
+        if pay_for_works(world, settlement, kind.monthly_cost(), "construction").is_none() {
+            return;
+        }
+        if months > 1 {
+            settlement.get_mut::<Buildings>(world).construction = Some((kind, months - 1));
+            return;
+        }
+        {
+            let mut buildings = settlement.get_mut::<Buildings>(world);
+            buildings.construction = None;
+            buildings.built.push(kind);
+        }
+        world
+            .get_resource::<FormulaSystem<FST>>()
+            .unwrap()
+            .add_factor(&settlement.fst(FactorType::SettlementCarryingCapacity), kind.carrying_capacity());
+        let polity = *settlement.get::<PolityRef>(world);
+        let player = world.get_resource::<PlayerPolity>().unwrap().0;
+        if player == Some(polity) {
+            let text = format!("{} finishes its {}", settlement.get::<Settlement>(world).name, kind.name());
+            notify(world, text);
+        }
+    }
+}
+
+// the council picks, for each kind of building, the settlement that needs it most
+pub fn building_options(world: &World, polity: PolityRef) -> Vec<AgentOption> {
+    let settlements = polity.settlements(world);
+    let mut options = Vec::new();
+    for kind in BuildingKind::iter() {
+        let need = |settlement: SettlementRef| {
+            let population = settlement.get::<Settlement>(world).population as f32;
+            match kind {
+                BuildingKind::Granary => 0.3,
+                BuildingKind::Market => 0.1 + population / 2000.0,
+                BuildingKind::Harbor => 0.3,
+                BuildingKind::IrrigationWorks => {
+                    population / settlement.get_factor(world, FactorType::SettlementCarryingCapacity).max(1.0) - 0.5
+                },
+                BuildingKind::Temple => settlement.get_factor(world, FactorType::SettlementUnrest),
+            }
+        };
+        let best = settlements
+            .iter()
+            .filter(|settlement| settlement.can_build(world, kind))
+            .map(|&settlement| (settlement, need(settlement)))
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        let (settlement, need) = match best {
+            Some(best) => best,
+            None => continue,
+        };
+        let name = &settlement.get::<Settlement>(world).name;
+        let value = if kind == BuildingKind::Temple { Value::Tradition } else { Value::Prosperity };
+        let cost = kind.monthly_cost() * kind.months() as f32;
+        let months_of_food = cost / (polity.population(world).max(1) as f32 * MONTHLY_CALORIE_NEED);
+        options.push(
+            AgentOption::new(kind.name())
+                .consider(value, need, format!("{} needs a {}", name, kind.name()))
+                .consider(Value::Prosperity, -months_of_food, format!("{:.1} months of food to build", months_of_food))
+                .command(Box::new(StartBuildingCommand { settlement, kind }))
+        );
+    }
+    options
+}
+
+fn building_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    settlement_query: Query<(Entity, &Buildings)>,
+) {
+    if !date.is_month {
+        return;
+    }
+    for (ent, buildings) in settlement_query.iter() {
+        if !buildings.built.is_empty() || buildings.construction.is_some() {
+            commands.add(BuildingMonthCommand(SettlementRef(ent)));
+        }
+    }
+}
+
+// stored food goes bad, slower where there's a granary
+fn spoilage_system(
+    date: Res<CurrentDate>,
+    settlement_query: Query<(&Buildings, &SettlementPops)>,
+    mut pop_query: Query<&mut GoodStorage>,
+) {
+    if !date.is_month {
+        return;
+    }
+    for (buildings, pops) in settlement_query.iter() {
+        let spoilage = buildings.spoilage();
+        for pop in pops.0.iter() {
+            if let Ok(mut storage) = pop_query.get_mut(pop.entity()) {
+                storage.take_share(spoilage);
+            }
+        }
+    }
+}
+
+// everything about the selected settlement: who lives there, what they know and what they've built
+fn settlement_panel_system(
+    province_map: Res<ProvinceMap>,
+    selected_query: Query<&MapCoordinate, With<Selected>>,
+    province_settlement_query: Query<&SettlementRef, With<Province>>,
+    settlement_query: Query<(&Settlement, &SettlementPops, &SettlementTier, &Fortifications, &Buildings, &Knowledge)>,
+    date: Res<CurrentDate>,
+    pop_query: Query<(&Pop, &ReligionRef, Option<(&FarmingPop, &Fields)>)>,
+    religion_query: Query<&Religion>,
+    mut info_tag_query: Query<(&InfoTag, &mut Text)>,
+) {
+    for (info_tag, mut text) in info_tag_query.iter_mut() {
+        if *info_tag != InfoTag::SelectedSettlement {
+            continue;
+        }
+        let selected = selected_query
+            .iter()
+            .next()
+            .and_then(|coordinate| province_map.0.get(coordinate))
+            .and_then(|province| province_settlement_query.get(province.entity()).ok())
+            .and_then(|settlement| settlement_query.get(settlement.entity()).ok());
+        let (info, pops, tier, fortifications, buildings, knowledge) = match selected {
+            Some(settlement) => settlement,
+            None => {
+                text.sections[0].value = "Select a settlement".to_owned();
+                continue;
+            },
+        };
+        let largest = pops
+            .0
+            .iter()
+            .filter_map(|pop| pop_query.get(pop.entity()).ok())
+            .max_by_key(|(pop, _, _)| pop.size);
+        let faith = largest
+            .and_then(|(_, religion, _)| religion_query.get(religion.entity()).ok())
+            .map(|religion| religion.name.clone())
+            .unwrap_or_default();
+        let farming = largest
+            .and_then(|(_, _, farming)| farming)
+            .map(|(farming_pop, fields)| {
+                let season = fields.season(farming_pop.good);
+                format!(
+                    "\n{:?} fields {}, harvest in {} days",
+                    farming_pop.good,
+                    season.stage(date.date),
+                    season.days_to_harvest(date.date),
+                )
+            })
+            .unwrap_or_default();
+        let built = buildings.built.iter().map(|kind| kind.name()).collect::<Vec<_>>();
+        let construction = buildings
+            .construction
+            .map(|(kind, months)| format!("\nbuilding a {}, {} months left", kind.name(), months))
+            .unwrap_or_default();
+        let known = Technology::iter().filter(|t| knowledge.knows(*t)).map(|t| t.name()).collect::<Vec<_>>();
+        text.sections[0].value = format!(
+            "{}, a {}\npopulation {}, worships {}{}\nwalls level {}\nbuildings: {}{}\nknows: {}",
+            info.name,
+            tier.name(),
+            info.population,
+            faith,
+            farming,
+            fortifications.level,
+            built.join(", "),
+            construction,
+            known.join(", "),
+        );
+    }
+}
+
+pub struct BuildingsPlugin;
+
+impl Plugin for BuildingsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_system_to_day(building_system.system())
+            .add_system_to_day(spoilage_system.system())
+            .add_system(settlement_panel_system.system());
+    }
+}
@@ -0,0 +1,196 @@
+use strum::{EnumIter, IntoEnumIterator};
+
+use crate::time::Date;
+
+// the months of the year in order, with the days in each
+const MONTH_LENGTHS: [usize; 12] = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+
+lazy_static! {
+    pub static ref CALENDAR: Calendar = Calendar::new(&MONTH_LENGTHS);
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, EnumIter)]
+pub enum Month {
+    January,
+    February,
+    March,
+    April,
+    May,
+    June,
+    July,
+    August,
+    September,
+    October,
+    November,
+    December,
+}
+
+impl Month {
+    // months are numbered from 1
+    pub fn from_number(month: usize) -> Option<Self> {
+        Month::iter().nth(month.checked_sub(1)?)
+    }
+
+    pub fn number(&self) -> usize {
+        *self as usize + 1
+    }
+
+    pub fn season(&self) -> Season {
+        match self {
+            Month::December | Month::January | Month::February => Season::Winter,
+            Month::March | Month::April | Month::May => Season::Spring,
+            Month::June | Month::July | Month::August => Season::Summer,
+            Month::September | Month::October | Month::November => Season::Autumn,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, EnumIter)]
+pub enum Season {
+    Spring,
+    Summer,
+    Autumn,
+    Winter,
+}
+
+// how days are counted into months and years, the game runs on CALENDAR
+#[derive(Debug, Clone)]
+pub struct Calendar {
+    month_lengths: Vec<usize>,
+    // days in the year before each month starts
+    month_starts: Vec<usize>,
+    days_in_year: usize,
+}
+
+impl Calendar {
+    pub fn new(month_lengths: &[usize]) -> Self {
+        assert!(!month_lengths.is_empty() && month_lengths.iter().all(|&days| days > 0), "every month needs days");
+        let mut month_starts = Vec::with_capacity(month_lengths.len());
+        let mut days_in_year = 0;
+        for &days in month_lengths.iter() {
+            month_starts.push(days_in_year);
+            days_in_year += days;
+        }
+        Self {
+            month_lengths: month_lengths.to_vec(),
+            month_starts,
+            days_in_year,
+        }
+    }
+
+    pub fn months(&self) -> usize {
+        self.month_lengths.len()
+    }
+
+    pub fn days_in_month(&self, month: usize) -> usize {
+        self.month_lengths[month - 1]
+    }
+
+    pub fn days_in_year(&self) -> usize {
+        self.days_in_year
+    }
+
+    pub fn is_valid(&self, date: Date) -> bool {
+        date.month >= 1 && date.month <= self.months() && date.day >= 1 && date.day <= self.days_in_month(date.month)
+    }
+
+    // days since the first day of year 0
+    pub fn to_abs(&self, date: Date) -> usize {
+        debug_assert!(self.is_valid(date), "invalid date {:?}", date);
+        date.year * self.days_in_year + self.month_starts[date.month - 1] + date.day - 1
+    }
+
+    pub fn from_abs(&self, abs: usize) -> Date {
+        let year = abs / self.days_in_year;
+        let day_of_year = abs % self.days_in_year;
+        let month = self.month_starts.iter().rposition(|&start| start <= day_of_year).unwrap();
+        Date {
+            day: day_of_year - self.month_starts[month] + 1,
+            month: month + 1,
+            year,
+        }
+    }
+
+    pub fn day_of_year(&self, date: Date) -> usize {
+        self.month_starts[date.month - 1] + date.day - 1
+    }
+
+    pub fn next_day(&self, date: Date) -> Date {
+        if date.day < self.days_in_month(date.month) {
+            Date { day: date.day + 1, ..date }
+        } else if date.month < self.months() {
+            Date { day: 1, month: date.month + 1, ..date }
+        } else {
+            Date { day: 1, month: 1, year: date.year + 1 }
+        }
+    }
+}
+
+impl Default for Calendar {
+    fn default() -> Self {
+        CALENDAR.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{thread_rng, Rng};
+
+    use super::*;
+
+    fn calendars() -> Vec<Calendar> {
+        vec![
+            Calendar::new(&MONTH_LENGTHS),
+            Calendar::new(&[30; 12]),
+            Calendar::new(&[7, 1, 40]),
+        ]
+    }
+
+    #[test]
+    fn abs_round_trips() {
+        let mut rng = thread_rng();
+        for calendar in calendars() {
+            for abs in (0..calendar.days_in_year() * 4).chain((0..1000).map(|_| rng.gen_range(0..10_000_000))) {
+                let date = calendar.from_abs(abs);
+                assert!(calendar.is_valid(date), "{:?} from {}", date, abs);
+                assert_eq!(calendar.to_abs(date), abs);
+            }
+        }
+    }
+
+    #[test]
+    fn dates_round_trip() {
+        let mut rng = thread_rng();
+        for calendar in calendars() {
+            for _ in 0..1000 {
+                let month = rng.gen_range(1..=calendar.months());
+                let date = Date {
+                    day: rng.gen_range(1..=calendar.days_in_month(month)),
+                    month,
+                    year: rng.gen_range(0..100_000),
+                };
+                assert_eq!(calendar.from_abs(calendar.to_abs(date)), date);
+            }
+        }
+    }
+
+    #[test]
+    fn next_day_is_one_day_later() {
+        for calendar in calendars() {
+            let mut date = calendar.from_abs(0);
+            for abs in 0..calendar.days_in_year() * 3 {
+                assert_eq!(calendar.to_abs(date), abs);
+                date = calendar.next_day(date);
+            }
+        }
+    }
+
+    #[test]
+    fn months_have_seasons() {
+        assert_eq!(Month::from_number(1), Some(Month::January));
+        assert_eq!(Month::from_number(13), None);
+        assert_eq!(Month::from_number(0), None);
+        assert!(Month::iter().all(|month| Month::from_number(month.number()) == Some(month)));
+        assert_eq!(Date { day: 1, month: 7, year: 1 }.season(), Season::Summer);
+    }
+}
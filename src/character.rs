@@ -0,0 +1,354 @@
+use bevy::{ecs::system::Command, prelude::*};
+use rand::{prelude::SliceRandom, thread_rng, Rng};
+use strum::{EnumIter, IntoEnumIterator};
+
+use crate::prelude::*;
+use crate::agent::{Value, ValueAgent};
+use crate::factor::FactorRef;
+use crate::notification::notify;
+use crate::polity::PolityCapital;
+use crate::pops::{base_mortality, Language, Polity, PopLanguage};
+use crate::settlement::SettlementPops;
+
+// pops at least this big have someone everyone listens to
+const NOTABLE_POP_SIZE: isize = 500;
+const MAX_TRAITS: usize = 2;
+// how old rulers and leaders are when they first show up
+const ADULT_AGES: std::ops::Range<usize> = 20..45;
+// how much older a ruler is than their heir
+const GENERATION_GAP: std::ops::Range<usize> = 18..35;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, EnumIter)]
+pub enum Trait {
+    Brave,
+    Cautious,
+    Ambitious,
+    Content,
+    Pious,
+    Greedy,
+    Just,
+}
+
+impl Trait {
+    // how much having this trait shifts what someone cares about
+    pub fn value_modifiers(&self) -> &'static [(Value, f32)] {
+        match self {
+            Trait::Brave => &[(Value::Expansion, 0.3), (Value::Security, -0.2)],
+            Trait::Cautious => &[(Value::Security, 0.3), (Value::Expansion, -0.2)],
+            Trait::Ambitious => &[(Value::Expansion, 0.4)],
+            Trait::Content => &[(Value::Expansion, -0.3), (Value::Tradition, 0.2)],
+            Trait::Pious => &[(Value::Tradition, 0.3)],
+            Trait::Greedy => &[(Value::Prosperity, 0.3), (Value::Tradition, -0.1)],
+            Trait::Just => &[(Value::Tradition, 0.2), (Value::Security, 0.1)],
+        }
+    }
+
+    // a few traits that don't contradict each other
+    pub fn random_set() -> Vec<Trait> {
+        let mut rng = thread_rng();
+        let mut candidates = Trait::iter().collect::<Vec<_>>();
+        candidates.shuffle(&mut rng);
+        let mut traits: Vec<Trait> = Vec::new();
+        for candidate in candidates.into_iter() {
+            if traits.len() >= rng.gen_range(1..=MAX_TRAITS) {
+                break;
+            }
+            if traits.iter().all(|t| !t.opposes(candidate)) {
+                traits.push(candidate);
+            }
+        }
+        traits
+    }
+
+    fn opposes(&self, other: Trait) -> bool {
+        matches!(
+            (self, other),
+            (Trait::Brave, Trait::Cautious) | (Trait::Cautious, Trait::Brave)
+                | (Trait::Ambitious, Trait::Content) | (Trait::Content, Trait::Ambitious)
+        )
+    }
+}
+
+impl ValueAgent {
+    pub fn with_traits(mut self, traits: &[Trait]) -> Self {
+        for t in traits.iter() {
+            for &(value, amount) in t.value_modifiers().iter() {
+                let weight = match value {
+                    Value::Security => &mut self.security,
+                    Value::Prosperity => &mut self.prosperity,
+                    Value::Expansion => &mut self.expansion,
+                    Value::Tradition => &mut self.tradition,
+                };
+                *weight = (*weight + amount).max(0.0);
+            }
+        }
+        self
+    }
+}
+
+#[game_ref]
+pub struct DynastyRef(pub Entity);
+
+pub struct Dynasty {
+    pub name: String,
+    pub founded: Date,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Role {
+    Ruler(PolityRef),
+    Heir(PolityRef),
+    Leader(PopRef),
+}
+
+#[game_ref]
+pub struct CharacterRef(pub Entity);
+
+pub struct Character {
+    pub name: String,
+    pub born: Date,
+    pub traits: Vec<Trait>,
+    pub culture: CultureRef,
+    pub language: LanguageRef,
+    pub dynasty: DynastyRef,
+    pub role: Role,
+}
+
+impl Character {
+    pub fn age(&self, today: Date) -> usize {
+        today.years_since(self.born)
+    }
+}
+
+impl CharacterRef {
+    pub fn name<'a>(&self, world: &'a World) -> &'a String {
+        &self.get::<Character>(world).name
+    }
+
+    // "name of dynasty"
+    pub fn full_name(&self, world: &World) -> String {
+        let character = self.get::<Character>(world);
+        format!("{} of {}", character.name, character.dynasty.get::<Dynasty>(world).name)
+    }
+}
+
+// who sits on the throne, and who's next
+pub struct Ruler {
+    pub ruler: CharacterRef,
+    pub heir: Option<CharacterRef>,
+}
+
+// the voice of a notable pop
+pub struct PopLeader(pub CharacterRef);
+
+// the family a pop's leaders come from, kept between one leader and the next
+pub struct PopDynasty(pub DynastyRef);
+
+impl PolityRef {
+    pub fn ruler(&self, world: &World) -> Option<CharacterRef> {
+        self.try_get::<Ruler>(world).map(|ruler| ruler.ruler)
+    }
+}
+
+pub fn found_dynasty(world: &mut World, language: LanguageRef) -> DynastyRef {
+    let name = language.get::<Language>(world).generate_name(2);
+    let founded = world.get_resource::<CurrentDate>().unwrap().date;
+    DynastyRef(world.spawn().insert(Dynasty { name, founded }).id())
+}
+
+pub fn spawn_character(
+    world: &mut World,
+    culture: CultureRef,
+    language: LanguageRef,
+    dynasty: DynastyRef,
+    age: usize,
+    role: Role,
+) -> CharacterRef {
+    let today = world.get_resource::<CurrentDate>().unwrap().date;
+    let name = language.get::<Language>(world).generate_name(2);
+    let born = today.years_before(age);
+    CharacterRef(
+        world
+            .spawn()
+            .insert(Character {
+                name,
+                born,
+                traits: Trait::random_set(),
+                culture,
+                language,
+                dynasty,
+                role,
+            })
+            .id()
+    )
+}
+
+// the biggest pop of the polity's capital, whose people the ruling family comes from
+fn leading_pop(world: &World, polity: PolityRef) -> Option<PopRef> {
+    let capital = polity.try_get::<PolityCapital>(world)?.0;
+    capital
+        .get::<SettlementPops>(world)
+        .0
+        .iter()
+        .max_by_key(|pop| pop.get::<Pop>(world).size)
+        .cloned()
+}
+
+// a ruler's child or kinsman, younger than they are
+fn spawn_heir(world: &mut World, polity: PolityRef, ruler: CharacterRef) -> CharacterRef {
+    let (culture, language, dynasty, age) = {
+        let today = world.get_resource::<CurrentDate>().unwrap().date;
+        let ruler = ruler.get::<Character>(world);
+        (ruler.culture, ruler.language, ruler.dynasty, ruler.age(today))
+    };
+    let age = age.saturating_sub(thread_rng().gen_range(GENERATION_GAP));
+    spawn_character(world, culture, language, dynasty, age, Role::Heir(polity))
+}
+
+// a polity without anyone in charge raises a family from its capital to the throne
+pub struct CrownRulerCommand(pub PolityRef);
+
+impl Command for CrownRulerCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        if self.0.try_get::<Ruler>(world).is_some() {
+            return;
+        }
+        let pop = match leading_pop(world, self.0) {
+            Some(pop) => pop,
+            None => return,
+        };
+        let culture = *pop.get::<CultureRef>(world);
+        let language = pop.get::<PopLanguage>(world).language;
+        let dynasty = found_dynasty(world, language);
+        let age = thread_rng().gen_range(ADULT_AGES);
+        let ruler = spawn_character(world, culture, language, dynasty, age, Role::Ruler(self.0));
+        let heir = spawn_heir(world, self.0, ruler);
+        world.entity_mut(self.0.entity()).insert(Ruler { ruler, heir: Some(heir) });
+        let text = format!("{} comes to rule {}", ruler.full_name(world), self.0.name(world));
+        notify(world, text);
+    }
+}
+
+// someone died: rulers are followed by their heirs, heirs and leaders are replaced
+pub struct CharacterDeathCommand(pub CharacterRef);
+
+impl Command for CharacterDeathCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let role = match self.0.try_get::<Character>(world) {
+            Some(character) => character.role,
+            None => return,
+        };
+        let name = self.0.full_name(world);
+        world.despawn(self.0.entity());
+        match role {
+            Role::Ruler(polity) => {
+                let heir = match polity.try_get::<Ruler>(world) {
+                    Some(ruler) if ruler.ruler == self.0 => ruler.heir,
+                    _ => return,
+                };
+                let heir = match heir.filter(|heir| world.get_entity(heir.entity()).is_some()) {
+                    Some(heir) => heir,
+                    // the line died out, someone else will take over
+                    None => {
+                        world.entity_mut(polity.entity()).remove::<Ruler>();
+                        let text = format!("{} dies without an heir, {} is leaderless", name, polity.name(world));
+                        notify(world, text);
+                        return;
+                    },
+                };
+                heir.get_mut::<Character>(world).role = Role::Ruler(polity);
+                let next = spawn_heir(world, polity, heir);
+                world.entity_mut(polity.entity()).insert(Ruler { ruler: heir, heir: Some(next) });
+                let text = format!("{} dies, {} succeeds to {}", name, heir.full_name(world), polity.name(world));
+                notify(world, text);
+            },
+            Role::Heir(polity) => {
+                let ruler = match polity.try_get::<Ruler>(world) {
+                    Some(ruler) if ruler.heir == Some(self.0) => ruler.ruler,
+                    _ => return,
+                };
+                let next = spawn_heir(world, polity, ruler);
+                polity.get_mut::<Ruler>(world).heir = Some(next);
+            },
+            Role::Leader(pop) => {
+                if world.get_entity(pop.entity()).is_some() {
+                    world.entity_mut(pop.entity()).remove::<PopLeader>();
+                }
+            },
+        }
+    }
+}
+
+// a notable pop finds someone to speak for it
+pub struct RaiseLeaderCommand(pub PopRef);
+
+impl Command for RaiseLeaderCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        if world.get_entity(self.0.entity()).is_none() || self.0.try_get::<PopLeader>(world).is_some() {
+            return;
+        }
+        let culture = *self.0.get::<CultureRef>(world);
+        let language = self.0.get::<PopLanguage>(world).language;
+        let dynasty = match self.0.try_get::<PopDynasty>(world) {
+            Some(dynasty) => dynasty.0,
+            None => found_dynasty(world, language),
+        };
+        let age = thread_rng().gen_range(ADULT_AGES);
+        let leader = spawn_character(world, culture, language, dynasty, age, Role::Leader(self.0));
+        world.entity_mut(self.0.entity()).insert(PopLeader(leader)).insert(PopDynasty(dynasty));
+    }
+}
+
+// crown rulers for new polities and find leaders for pops that have grown notable
+fn ruler_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    polity_query: Query<Entity, (With<Polity>, With<PolityCapital>, Without<Ruler>)>,
+    pop_query: Query<(Entity, &Pop), Without<PopLeader>>,
+) {
+    if !date.is_month {
+        return;
+    }
+    for polity in polity_query.iter() {
+        commands.add(CrownRulerCommand(PolityRef(polity)));
+    }
+    for (pop, info) in pop_query.iter() {
+        if info.size >= NOTABLE_POP_SIZE {
+            commands.add(RaiseLeaderCommand(PopRef(pop)));
+        }
+    }
+}
+
+// people get old and die, and so do the ones whose polity or pop is gone
+fn character_death_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    character_query: Query<(Entity, &Character)>,
+    polity_query: Query<&Polity>,
+    pop_query: Query<&Pop>,
+) {
+    if !date.is_year {
+        return;
+    }
+    for (ent, character) in character_query.iter() {
+        let orphaned = match character.role {
+            Role::Ruler(polity) | Role::Heir(polity) => polity_query.get(polity.entity()).is_err(),
+            Role::Leader(pop) => pop_query.get(pop.entity()).is_err(),
+        };
+        if orphaned {
+            commands.entity(ent).despawn();
+        } else if individual_event(base_mortality(character.age(date.date))) {
+            commands.add(CharacterDeathCommand(CharacterRef(ent)));
+        }
+    }
+}
+
+pub struct CharacterPlugin;
+
+impl Plugin for CharacterPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_system_to_day(ruler_system.system())
+            .add_system_to_day(character_death_system.system());
+    }
+}
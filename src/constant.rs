@@ -2,5 +2,4 @@ pub const TILE_SIZE_X: f32 = 37.0;
 pub const TILE_SIZE_Y: f32 = 32.0;
 pub const SQRT_3: f32 = 1.73205080757;
 pub const CHUNK_SIZE: isize = 16;
-pub const DAY_TIMESTEP: &'static str = "DAY_TIMESTEP";
 pub const DAY_LABEL: &'static str = "DAY_LABEL";
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+
+use bevy::{ecs::system::Command, prelude::*};
+use rand::{prelude::SliceRandom, thread_rng};
+use strum::{EnumIter, IntoEnumIterator};
+
+use crate::prelude::*;
+use crate::map::{MapTile, MapTileType};
+use crate::notification::notify;
+use crate::pops::{Culture, Language, PopLanguage};
+use crate::probability::binomial_isample;
+use crate::province::ProvinceMap;
+
+// yearly share of a minority that takes up the local majority culture
+const SETTLEMENT_ASSIMILATION_RATE: f32 = 0.02;
+// yearly share that takes up the culture of the polity when none of its people live nearby
+const POLITY_ASSIMILATION_RATE: f32 = 0.005;
+// yearly chance two big communities in one settlement grow into a culture of their own
+const HYBRID_CHANCE: f32 = 0.02;
+// both cultures need at least this share of a settlement to blend
+const HYBRID_MIN_SHARE: f32 = 0.3;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, EnumIter)]
+pub enum CultureTrait {
+    Agrarian,
+    Seafaring,
+    Fecund,
+    Hardy,
+    Martial,
+    Mercantile,
+    Insular,
+    Cosmopolitan,
+}
+
+impl CultureTrait {
+    pub fn modifier(&self, factor: FactorType) -> f32 {
+        match (*self, factor) {
+            (CultureTrait::Agrarian, FactorType::PopHarvest) => 0.1,
+            (CultureTrait::Fecund, FactorType::PopFertility) => 0.15,
+            (CultureTrait::Hardy, FactorType::PopMortality) => -0.1,
+            _ => 0.0,
+        }
+    }
+
+    pub fn excludes(&self, other: CultureTrait) -> bool {
+        match (*self, other) {
+            (CultureTrait::Insular, CultureTrait::Cosmopolitan) => true,
+            (CultureTrait::Cosmopolitan, CultureTrait::Insular) => true,
+            _ => *self == other,
+        }
+    }
+}
+
+// cultures that came out of two others remember where they came from
+pub struct CultureOrigin {
+    pub parents: Vec<CultureRef>,
+    pub founded: Date,
+}
+
+impl Culture {
+    pub fn new(name: String) -> Self {
+        let mut traits: Vec<CultureTrait> = Vec::new();
+        let mut candidates = CultureTrait::iter().collect::<Vec<_>>();
+        candidates.shuffle(&mut thread_rng());
+        for candidate in candidates.into_iter() {
+            if traits.len() >= 2 {
+                break;
+            }
+            if !traits.iter().any(|t| t.excludes(candidate)) {
+                traits.push(candidate);
+            }
+        }
+        Self {
+            name,
+            traits,
+        }
+    }
+
+    // takes a trait or two from each parent
+    pub fn blend(name: String, a: &Culture, b: &Culture) -> Self {
+        let mut traits: Vec<CultureTrait> = Vec::new();
+        let mut candidates = a.traits.iter().chain(b.traits.iter()).cloned().collect::<Vec<_>>();
+        candidates.shuffle(&mut thread_rng());
+        for candidate in candidates.into_iter() {
+            if traits.len() >= 3 {
+                break;
+            }
+            if !traits.iter().any(|t| t.excludes(candidate)) {
+                traits.push(candidate);
+            }
+        }
+        Self {
+            name,
+            traits,
+        }
+    }
+
+    pub fn has_trait(&self, culture_trait: CultureTrait) -> bool {
+        self.traits.contains(&culture_trait)
+    }
+
+    pub fn modifier(&self, factor: FactorType) -> f32 {
+        self.traits.iter().map(|t| t.modifier(factor)).sum()
+    }
+
+    // how much settling among another culture puts these people off
+    pub fn foreign_aversion(&self) -> f32 {
+        if self.has_trait(CultureTrait::Insular) {
+            1.5
+        } else if self.has_trait(CultureTrait::Cosmopolitan) {
+            0.5
+        } else {
+            1.0
+        }
+    }
+
+    // how readily these people take up the ways of their neighbours
+    pub fn assimilability(&self) -> f32 {
+        if self.has_trait(CultureTrait::Insular) {
+            0.5
+        } else if self.has_trait(CultureTrait::Cosmopolitan) {
+            2.0
+        } else {
+            1.0
+        }
+    }
+
+    // added onto how attractive a province looks to settle
+    pub fn province_preference(&self, world: &World, province: ProvinceRef) -> f32 {
+        let mut preference = 0.0;
+        let tile_type = province.get::<MapTile>(world).tile_type;
+        if self.has_trait(CultureTrait::Agrarian) {
+            preference += if tile_type == MapTileType::Plains { 0.5 } else { -0.5 };
+        }
+        if self.has_trait(CultureTrait::Seafaring) {
+            let coordinate = *province.get::<MapCoordinate>(world);
+            let province_map = world.get_resource::<ProvinceMap>().unwrap();
+            let coastal = coordinate
+                .neighbors_iter()
+                .filter_map(|n| province_map.0.get(&n))
+                .any(|p| p.get::<MapTile>(world).tile_type == MapTileType::Water);
+            if coastal {
+                preference += 0.5;
+            }
+        }
+        preference
+    }
+}
+
+fn assimilation_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    pop_query: Query<(Entity, &Pop, &CultureRef, &PopLanguage, &SettlementRef, &PolityRef)>,
+    culture_query: Query<&Culture>,
+) {
+    if !date.is_year {
+        return;
+    }
+    let mut settlement_cultures: HashMap<SettlementRef, HashMap<CultureRef, isize>> = HashMap::new();
+    let mut polity_cultures: HashMap<PolityRef, HashMap<CultureRef, isize>> = HashMap::new();
+    // the language most spoken by each culture, taken up along with it
+    let mut culture_languages: HashMap<CultureRef, (LanguageRef, isize)> = HashMap::new();
+    for (_, pop, &culture, language, &settlement, &polity) in pop_query.iter() {
+        *settlement_cultures.entry(settlement).or_default().entry(culture).or_insert(0) += pop.size;
+        *polity_cultures.entry(polity).or_default().entry(culture).or_insert(0) += pop.size;
+        let entry = culture_languages.entry(culture).or_insert((language.language, 0));
+        if pop.size > entry.1 {
+            *entry = (language.language, pop.size);
+        }
+    }
+    let majority = |sizes: &HashMap<CultureRef, isize>| {
+        sizes.iter().max_by_key(|(_, size)| **size).map(|(culture, size)| (*culture, *size))
+    };
+
+    for (pop_ent, pop, &culture, _, &settlement, &polity) in pop_query.iter() {
+        let assimilability = culture_query.get(culture.0).map(|c| c.assimilability()).unwrap_or(1.0);
+        let local = &settlement_cultures[&settlement];
+        let local_total: isize = local.values().sum();
+        let (local_majority, local_majority_size) = majority(local).unwrap();
+        let (target, rate) = if local_majority != culture {
+            let share = local_majority_size as f32 / local_total.max(1) as f32;
+            (local_majority, SETTLEMENT_ASSIMILATION_RATE * share)
+        } else {
+            let (polity_majority, _) = majority(&polity_cultures[&polity]).unwrap();
+            if polity_majority != culture && !local.contains_key(&polity_majority) {
+                (polity_majority, POLITY_ASSIMILATION_RATE)
+            } else {
+                continue;
+            }
+        };
+        let amount = binomial_isample(pop.size, rate * assimilability);
+        if amount > 0 {
+            commands.add(PopConvertCommand {
+                pop: PopRef(pop_ent),
+                amount,
+                culture: target,
+                language: culture_languages[&target].0,
+            });
+        }
+    }
+
+    for (&settlement, sizes) in settlement_cultures.iter() {
+        let total: isize = sizes.values().sum();
+        let mut ranked = sizes.iter().map(|(c, s)| (*c, *s)).collect::<Vec<_>>();
+        ranked.sort_by_key(|(_, size)| -*size);
+        if ranked.len() < 2 || total <= 0 {
+            continue;
+        }
+        let big_enough = ranked[1].1 as f32 / total as f32 >= HYBRID_MIN_SHARE;
+        if big_enough && individual_event(HYBRID_CHANCE) {
+            commands.add(BlendCulturesCommand {
+                settlement,
+                a: ranked[0].0,
+                b: ranked[1].0,
+                language: culture_languages[&ranked[0].0].0,
+            });
+        }
+    }
+}
+
+// move people from a pop to one of another culture in the same settlement, founding it if needed
+pub struct PopConvertCommand {
+    pub pop: PopRef,
+    pub amount: isize,
+    pub culture: CultureRef,
+    pub language: LanguageRef,
+}
+
+impl Command for PopConvertCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        self.pop.convert_people(world, self.amount, Some(self.culture), Some(self.language), None);
+    }
+}
+
+// two big communities sharing a settlement grow a culture of their own, and a third of each takes it up
+pub struct BlendCulturesCommand {
+    pub settlement: SettlementRef,
+    pub a: CultureRef,
+    pub b: CultureRef,
+    pub language: LanguageRef,
+}
+
+impl Command for BlendCulturesCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        // a pair that has blended before takes up the culture it made then
+        let existing = world
+            .query::<(Entity, &CultureOrigin)>()
+            .iter(world)
+            .find(|(_, origin)| origin.parents.len() == 2 && origin.parents.contains(&self.a) && origin.parents.contains(&self.b))
+            .map(|(ent, _)| CultureRef(ent));
+        let (culture, text) = match existing {
+            Some(culture) => (culture, None),
+            None => {
+                let name = self.language.get::<Language>(world).generate_name(2);
+                let blended = Culture::blend(name, self.a.get::<Culture>(world), self.b.get::<Culture>(world));
+                let text = format!("{} and {} blend into {}", self.a.get::<Culture>(world).name, self.b.get::<Culture>(world).name, blended.name);
+                let founded = world.get_resource::<CurrentDate>().unwrap().date;
+                let culture = CultureRef(
+                    world
+                        .spawn()
+                        .insert(blended)
+                        .insert(CultureOrigin {
+                            parents: vec![self.a, self.b],
+                            founded,
+                        })
+                        .id()
+                );
+                (culture, Some(text))
+            },
+        };
+        let (a, b) = (self.a, self.b);
+        self.settlement.blend_people(
+            world,
+            |world, pop| {
+                let pop_culture = *pop.get::<CultureRef>(world);
+                pop_culture == a || pop_culture == b
+            },
+            Some(culture),
+            Some(self.language),
+            None,
+        );
+        if let Some(text) = text {
+            notify(world, text);
+        }
+    }
+}
+
+pub struct CulturePlugin;
+
+impl Plugin for CulturePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_system_to_day(assimilation_system.system());
+    }
+}
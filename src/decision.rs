@@ -1,22 +1,104 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt::Debug;
+
 use crate::prelude::*;
 use crate::agent::ValueAgent;
-use crate::settlement::Settlement;
-use bevy::prelude::*;
+use crate::diplomacy::{BreakTreatyCommand, ChangeOpinionCommand, Relations, TreatyKind};
+use crate::factor::{AddFactorCommand, FactorRef};
+use crate::notification::notify;
+use crate::pops::{AgeCohorts, GoodStorage, Hunger, Polity, MONTHLY_CALORIE_NEED};
+use crate::province::ProvinceMap;
+use crate::settlement::{Settlement, SettlementPops};
+use crate::war::{JoinWarCommand, Wars, REFUSED_CALL_OPINION};
+use bevy::{ecs::system::Command, prelude::*};
 use strum::{EnumIter, IntoEnumIterator};
 
+// a month this short of food is a famine
+const FAMINE_SHORTFALL: f32 = 0.2;
+// relief is meant to see a pop through this many months
+const RELIEF_MONTHS: f32 = 3.0;
+// donors keep back this many months of their own food
+const DONOR_RESERVE_MONTHS: f32 = 6.0;
+// settlements close enough to help without the whole polity getting involved
+const NEIGHBOR_RADIUS: isize = 3;
+
+// an ally went to war and wants us along
 pub struct CalledToArmsEvent {
-    caller: PolityRef,
-    callee: PolityRef,
-    enemy: PolityRef,
+    pub war: usize,
+    pub caller: PolityRef,
+    pub callee: PolityRef,
+    pub enemy: PolityRef,
+}
+
+#[derive(EnumIter, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CalledToArmsChoice {
+    Honor,
+    Refuse,
+}
+
+impl GameEvent for CalledToArmsEvent {
+    type Choice = CalledToArmsChoice;
+
+    fn description(&self, world: &World) -> String {
+        format!(
+            "{} calls on {} to fight {}",
+            self.caller.name(world),
+            self.callee.name(world),
+            self.enemy.name(world),
+        )
+    }
+
+    fn choices(&self) -> Vec<CalledToArmsChoice> {
+        CalledToArmsChoice::iter().collect()
+    }
+
+    fn weigh_choice(&self, agent: &ValueAgent, world: &World, choice: CalledToArmsChoice) -> f32 {
+        let relations = world.get_resource::<Relations>().unwrap();
+        let fondness = (relations.opinion(self.callee, self.caller) - relations.opinion(self.callee, self.enemy)) / 100.0;
+        let danger = (self.enemy.population(world) as f32 / self.callee.population(world).max(1) as f32).min(2.0);
+        match choice {
+            // an alliance is only worth something if it's kept
+            CalledToArmsChoice::Honor => 0.3 * agent.tradition + 0.2 * agent.security + fondness,
+            CalledToArmsChoice::Refuse => 0.3 * danger * agent.security + 0.1 * agent.prosperity,
+        }
+    }
+
+    fn effects(&self, world: &World, choice: CalledToArmsChoice) -> Vec<Box<dyn Command>> {
+        match choice {
+            CalledToArmsChoice::Honor => {
+                let attacking = world
+                    .get_resource::<Wars>()
+                    .unwrap()
+                    .get(self.war)
+                    .and_then(|war| war.side(self.caller))
+                    .unwrap_or(true);
+                vec![Box::new(JoinWarCommand { war: self.war, polity: self.callee, attacking })]
+            },
+            CalledToArmsChoice::Refuse => vec![
+                Box::new(BreakTreatyCommand { kind: TreatyKind::Alliance, a: self.caller, b: self.callee }),
+                Box::new(ChangeOpinionCommand { of: self.caller, toward: self.callee, amount: REFUSED_CALL_OPINION }),
+            ],
+        }
+    }
+
+    fn is_valid(&self, world: &World) -> bool {
+        world.get_entity(self.callee.entity()).is_some()
+            && world.get_resource::<Wars>().unwrap().get(self.war).is_some()
+    }
+
+    fn polities(&self) -> Vec<PolityRef> {
+        vec![self.caller, self.callee, self.enemy]
+    }
 }
 
 // Some of our countrymen went hungry
 pub struct PopStarvedEvent {
-    pop: PopRef,
-    amount: isize,
+    pub pop: PopRef,
+    pub polity: PolityRef,
+    pub amount: isize,
 }
 
-#[derive(EnumIter)]
+#[derive(EnumIter, Copy, Clone, Debug, PartialEq, Eq)]
 pub enum PopStarvedChoice {
     SendFullRelief,
     SendSomeHelp,
@@ -29,7 +111,7 @@ impl GameEvent for PopStarvedEvent {
     fn description(&self, world: &World) -> String {
         let pop = self.pop.accessor(world);
         format!(
-            "{} people in {} starved!",
+            "{} people in {} are going hungry!",
             self.amount,
             pop.settlement().name(),
         )
@@ -40,43 +122,153 @@ impl GameEvent for PopStarvedEvent {
     }
 
     fn weigh_choice(&self, agent: &ValueAgent, world: &World, choice: PopStarvedChoice) -> f32 {
-        let pop = self.pop.accessor(world);
-        let pop_size = pop.size();
+        let severity = self.pop.get::<Hunger>(world).shortfall;
+        let needed = relief_needed(world, self.pop).max(1.0);
+        let loyalty = self.pop.get_factor(world, FactorType::PopLoyalty);
         match choice {
             PopStarvedChoice::SendFullRelief => {
-                0.0
+                let available: f32 = relief_donors(world, self.pop, self.polity, None).iter().map(|(_, s)| s).sum();
+                // generous when it's cheap, emptying the granaries costs
+                2.0 * severity * (available / needed).min(1.0) * agent.prosperity
+                    - 0.5 * (needed / available.max(1.0)).min(1.0) * agent.security
+            },
+            PopStarvedChoice::SendSomeHelp => {
+                let available: f32 = relief_donors(world, self.pop, self.polity, Some(NEIGHBOR_RADIUS)).iter().map(|(_, s)| s).sum();
+                1.5 * severity * (available / (0.5 * needed)).min(1.0) * agent.prosperity - 0.2 * agent.security
             },
-            PopStarvedChoice::SendSomeHelp => todo!(),
-            PopStarvedChoice::Ignore => todo!(),
+            // loyal pops put up with more
+            PopStarvedChoice::Ignore => (0.3 + loyalty - severity) * agent.tradition,
         }
     }
+
+    fn effects(&self, world: &World, choice: PopStarvedChoice) -> Vec<Box<dyn Command>> {
+        let loyalty = self.pop.fst(FactorType::PopLoyalty);
+        let pressure = self.pop.fst(FactorType::PopPressure);
+        match choice {
+            PopStarvedChoice::SendFullRelief => vec![
+                Box::new(FamineReliefCommand {
+                    pop: self.pop,
+                    polity: self.polity,
+                    share: 1.0,
+                    radius: None,
+                }),
+                Box::new(AddFactorCommand { target: loyalty, amt: 0.3 }),
+                Box::new(AddFactorCommand { target: pressure, amt: -0.5 }),
+            ],
+            PopStarvedChoice::SendSomeHelp => vec![
+                Box::new(FamineReliefCommand {
+                    pop: self.pop,
+                    polity: self.polity,
+                    share: 0.5,
+                    radius: Some(NEIGHBOR_RADIUS),
+                }),
+                Box::new(AddFactorCommand { target: loyalty, amt: 0.1 }),
+            ],
+            PopStarvedChoice::Ignore => vec![
+                Box::new(AddFactorCommand { target: loyalty, amt: -0.3 }),
+                Box::new(AddFactorCommand { target: pressure, amt: 0.5 }),
+            ],
+        }
+    }
+
+    fn is_valid(&self, world: &World) -> bool {
+        world.get_entity(self.pop.entity()).is_some()
+    }
+
+    fn polities(&self) -> Vec<PolityRef> {
+        vec![self.polity]
+    }
 }
 
-// My liege!
-pub struct PopulationPressureEvent {
-    pop: PopRef,
-    settlement: SettlementRef,
+// calories a pop is short over the time relief is meant to cover
+fn relief_needed(world: &World, pop: PopRef) -> f32 {
+    let shortfall = pop.get::<Hunger>(world).shortfall;
+    shortfall * pop.get::<AgeCohorts>(world).eaters() * MONTHLY_CALORIE_NEED * RELIEF_MONTHS
 }
 
-#[derive(Copy, Clone, Debug)]
-pub enum PopulationPressureChoice {
-    SeekNewVillage,
-    Ignore,
+// pops of the polity with food to spare and how much, nearest first
+fn relief_donors(world: &World, pop: PopRef, polity: PolityRef, radius: Option<isize>) -> Vec<(PopRef, f32)> {
+    let home = *pop.get::<SettlementRef>(world).get::<MapCoordinate>(world);
+    let province_map = world.get_resource::<ProvinceMap>().unwrap();
+    let mut donors = Vec::new();
+    for (&coordinate, province) in province_map.0.iter() {
+        let distance = home.distance(coordinate);
+        if radius.map_or(false, |r| distance > r) {
+            continue;
+        }
+        let settlement = match province.try_get::<SettlementRef>(world) {
+            Some(&settlement) => settlement,
+            None => continue,
+        };
+        for &donor in settlement.get::<SettlementPops>(world).0.iter() {
+            if donor == pop || *donor.get::<PolityRef>(world) != polity {
+                continue;
+            }
+            let reserve = donor.get::<AgeCohorts>(world).eaters() * MONTHLY_CALORIE_NEED * DONOR_RESERVE_MONTHS;
+            let surplus = donor.get::<GoodStorage>(world).calories() - reserve;
+            if surplus > 0.0 {
+                donors.push((distance, donor, surplus));
+            }
+        }
+    }
+    donors.sort_by_key(|(distance, _, _)| *distance);
+    donors.into_iter().map(|(_, donor, surplus)| (donor, surplus)).collect()
 }
 
-impl GameEvent for PopulationPressureEvent {
-    type Choice = PopulationPressureChoice;
+// ship food from the rest of the polity to a hungry pop
+pub struct FamineReliefCommand {
+    pub pop: PopRef,
+    pub polity: PolityRef,
+    // of what the pop is short
+    pub share: f32,
+    // only ask settlements this close, or the whole polity
+    pub radius: Option<isize>,
+}
 
-    fn description(&self, world: &World) -> String {
-        format!("under pressure {:?} of {:?}", self.pop, self.settlement)
+impl Command for FamineReliefCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        if world.get_entity(self.pop.entity()).is_none() {
+            return;
+        }
+        let mut remaining = relief_needed(world, self.pop) * self.share;
+        // the granaries of the realm go first
+        let food = self.polity.spend_calories(world, remaining, "famine relief");
+        remaining -= food.calories();
+        self.pop.get_mut::<GoodStorage>(world).merge(food);
+        for (donor, surplus) in relief_donors(world, self.pop, self.polity, self.radius).into_iter() {
+            if remaining <= 0.0 {
+                break;
+            }
+            let food = donor.get_mut::<GoodStorage>(world).take_calories(surplus.min(remaining));
+            remaining -= food.calories();
+            self.pop.get_mut::<GoodStorage>(world).merge(food);
+        }
     }
+}
 
-    fn choices(&self) -> Vec<Self::Choice> {
-        todo!()
+fn famine_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    mut starving: Local<HashSet<Entity>>,
+    pop_query: Query<(Entity, &Pop, &Hunger, &PolityRef)>,
+) {
+    if !date.is_month {
+        return;
     }
-
-    fn weigh_choice(&self, agent: &ValueAgent, world: &World, choice: Self::Choice) -> f32 {
-        todo!()
+    // only raise it when a famine starts, not every month it goes on
+    for (pop_ent, pop, hunger, &polity) in pop_query.iter() {
+        if hunger.shortfall < FAMINE_SHORTFALL {
+            starving.remove(&pop_ent);
+        } else if starving.insert(pop_ent) {
+            commands.add(PostEventCommand {
+                owner: polity,
+                event: Box::new(PopStarvedEvent {
+                    pop: PopRef(pop_ent),
+                    polity,
+                    amount: (pop.size as f32 * hunger.shortfall) as isize,
+                }),
+            });
+        }
     }
 }
 
@@ -90,4 +282,207 @@ pub trait GameEvent {
     fn description(&self, world: &World) -> String;
     fn choices(&self) -> Vec<Self::Choice>;
     fn weigh_choice(&self, agent: &ValueAgent, world: &World, choice: Self::Choice) -> f32;
+    fn effects(&self, world: &World, choice: Self::Choice) -> Vec<Box<dyn Command>>;
+    // whether the event still makes sense, eg the pop it's about hasn't died out
+    fn is_valid(&self, world: &World) -> bool {
+        true
+    }
+    // every polity the event is about, its owner included
+    fn polities(&self) -> Vec<PolityRef>;
+    // events with the same key put the same question to their owner
+    fn key(&self) -> Option<EventKey> {
+        None
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventKey {
+    Proposal { from: PolityRef, kind: TreatyKind },
+}
+
+// GameEvent with its choices numbered, so different kinds of events can sit in one queue
+pub trait QueuedEvent: Send + Sync {
+    fn description(&self, world: &World) -> String;
+    fn choice_names(&self) -> Vec<String>;
+    fn weigh_choice(&self, agent: &ValueAgent, world: &World, choice: usize) -> f32;
+    fn effects(&self, world: &World, choice: usize) -> Vec<Box<dyn Command>>;
+    fn is_valid(&self, world: &World) -> bool;
+    fn polities(&self) -> Vec<PolityRef>;
+    fn key(&self) -> Option<EventKey>;
+}
+
+impl<T> QueuedEvent for T where T: GameEvent + Send + Sync, T::Choice: Debug {
+    fn description(&self, world: &World) -> String {
+        GameEvent::description(self, world)
+    }
+
+    fn choice_names(&self) -> Vec<String> {
+        self.choices().iter().map(|choice| format!("{:?}", choice)).collect()
+    }
+
+    fn weigh_choice(&self, agent: &ValueAgent, world: &World, choice: usize) -> f32 {
+        GameEvent::weigh_choice(self, agent, world, self.choices().remove(choice))
+    }
+
+    fn effects(&self, world: &World, choice: usize) -> Vec<Box<dyn Command>> {
+        GameEvent::effects(self, world, self.choices().remove(choice))
+    }
+
+    fn is_valid(&self, world: &World) -> bool {
+        GameEvent::is_valid(self, world)
+    }
+
+    fn polities(&self) -> Vec<PolityRef> {
+        GameEvent::polities(self)
+    }
+
+    fn key(&self) -> Option<EventKey> {
+        GameEvent::key(self)
+    }
+}
+
+// days the player gets to answer before the council decides for them
+const EVENT_DEADLINE_DAYS: usize = 30;
+
+pub struct PendingEvent {
+    pub id: usize,
+    pub owner: PolityRef,
+    pub key: Option<EventKey>,
+    pub event: Box<dyn QueuedEvent>,
+    // worked out when posted, so the ui doesn't need the world to show it
+    pub description: String,
+    pub choice_names: Vec<String>,
+    pub deadline: Date,
+}
+
+#[derive(Default)]
+pub struct EventQueue {
+    pub pending: VecDeque<PendingEvent>,
+    next_id: usize,
+}
+
+impl EventQueue {
+    // the same question isn't put to anyone twice while the first is still waiting on them
+    pub fn is_pending(&self, owner: PolityRef, key: EventKey) -> bool {
+        self.pending.iter().any(|pending| pending.owner == owner && pending.key == Some(key))
+    }
+
+    pub fn post(&mut self, world: &World, owner: PolityRef, event: Box<dyn QueuedEvent>) {
+        let key = event.key();
+        if key.map(|key| self.is_pending(owner, key)).unwrap_or(false) {
+            return;
+        }
+        let today = world.get_resource::<CurrentDate>().unwrap().date;
+        let pending = PendingEvent {
+            id: self.next_id,
+            owner,
+            key,
+            description: event.description(world),
+            choice_names: event.choice_names(),
+            event,
+            deadline: today.days_after(EVENT_DEADLINE_DAYS),
+        };
+        self.next_id += 1;
+        self.pending.push_back(pending);
+    }
+
+    // a polity that's gone has no say, and nothing about it needs answering
+    pub fn forget(&mut self, polity: PolityRef) {
+        self.pending.retain(|pending| !pending.event.polities().contains(&polity));
+    }
+
+    pub fn take(&mut self, id: usize) -> Option<PendingEvent> {
+        let idx = self.pending.iter().position(|pending| pending.id == id)?;
+        self.pending.remove(idx)
+    }
+
+    // the oldest event waiting on the player
+    pub fn player_event(&self, player: &PlayerPolity) -> Option<&PendingEvent> {
+        self.pending.iter().find(|pending| Some(pending.owner) == player.0)
+    }
+}
+
+// the polity whose events wait for the player instead of being decided by ai
+#[derive(Default)]
+pub struct PlayerPolity(pub Option<PolityRef>);
+
+pub struct PostEventCommand {
+    pub owner: PolityRef,
+    pub event: Box<dyn QueuedEvent>,
+}
+
+impl Command for PostEventCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        if !self.event.is_valid(world) {
+            return;
+        }
+        // take the queue out so it can look at the world while posting
+        let mut queue = world.remove_resource::<EventQueue>().unwrap_or_default();
+        queue.post(world, self.owner, self.event);
+        world.insert_resource(queue);
+    }
+}
+
+// apply a choice, picking one the way the owner's council would if none was given
+pub struct ResolveEventCommand {
+    pub pending: PendingEvent,
+    pub choice: Option<usize>,
+}
+
+impl Command for ResolveEventCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let pending = self.pending;
+        if !pending.event.is_valid(world) {
+            return;
+        }
+        let agent = ValueAgent::of_polity(world, pending.owner);
+        let choice = self.choice.unwrap_or_else(|| {
+            (0..pending.choice_names.len())
+                .map(|choice| (choice, pending.event.weigh_choice(&agent, world, choice)))
+                .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+                .map(|(choice, _)| choice)
+                .unwrap()
+        });
+        let owner = pending.owner.get::<Polity>(world).name.clone();
+        notify(world, format!("{} {} chooses {}", pending.description, owner, pending.choice_names[choice]));
+        for command in pending.event.effects(world, choice).into_iter() {
+            command.write(world);
+        }
+    }
+}
+
+// ai polities answer straight away, the player's wait until they're answered or run out of time
+fn event_dispatch_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    player: Res<PlayerPolity>,
+    mut queue: ResMut<EventQueue>,
+) {
+    if !date.is_day {
+        return;
+    }
+    let mut waiting = VecDeque::new();
+    while let Some(pending) = queue.pending.pop_front() {
+        if Some(pending.owner) == player.0 && pending.deadline.is_after(date.date) {
+            waiting.push_back(pending);
+        } else {
+            commands.add(ResolveEventCommand {
+                pending,
+                choice: None,
+            });
+        }
+    }
+    queue.pending = waiting;
+}
+
+pub struct DecisionPlugin;
+
+impl Plugin for DecisionPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .init_resource::<EventQueue>()
+            .init_resource::<PlayerPolity>()
+            .add_system_to_day(event_dispatch_system.system())
+            .add_system_to_day(famine_system.system());
+    }
 }
@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+
+use bevy::{ecs::system::Command, prelude::*};
+use strum::{EnumIter, IntoEnumIterator};
+
+use crate::prelude::*;
+use crate::agent::{AgentOption, Value, ValueAgent};
+use crate::character::{Character, Ruler};
+use crate::decision::{EventKey, EventQueue, GameEvent, PlayerPolity, PostEventCommand};
+use crate::factor::FactorRef;
+use crate::notification::notify;
+use crate::polity::PolityBorders;
+use crate::pops::{GoodStorage, Polity};
+use crate::settlement::SettlementPops;
+use crate::ui::InfoTag;
+use crate::war::Wars;
+
+// yearly share of the gap between opinion and where it's heading that closes
+const OPINION_DRIFT: f32 = 0.1;
+// how much turning down a proposal stings
+const REJECTION_PENALTY: f32 = -10.0;
+// share of a tributary's stores that goes to its suzerain every year
+const TRIBUTE_SHARE: f32 = 0.05;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, EnumIter)]
+pub enum TreatyKind {
+    Alliance,
+    NonAggression,
+    TradeAccess,
+    // the first polity pays the second
+    Tributary,
+}
+
+#[derive(Debug, Clone)]
+pub struct Treaty {
+    pub kind: TreatyKind,
+    pub a: PolityRef,
+    pub b: PolityRef,
+    pub signed: Date,
+}
+
+impl Treaty {
+    pub fn binds(&self, polity: PolityRef) -> bool {
+        self.a == polity || self.b == polity
+    }
+
+    pub fn other(&self, polity: PolityRef) -> PolityRef {
+        if self.a == polity { self.b } else { self.a }
+    }
+}
+
+#[derive(Default)]
+pub struct Relations {
+    // how the first polity feels about the second, -100 to 100
+    pub opinions: HashMap<(PolityRef, PolityRef), f32>,
+    pub treaties: Vec<Treaty>,
+}
+
+impl Relations {
+    pub fn opinion(&self, of: PolityRef, toward: PolityRef) -> f32 {
+        *self.opinions.get(&(of, toward)).unwrap_or(&0.0)
+    }
+
+    pub fn change_opinion(&mut self, of: PolityRef, toward: PolityRef, amount: f32) {
+        let opinion = self.opinions.entry((of, toward)).or_insert(0.0);
+        *opinion = (*opinion + amount).max(-100.0).min(100.0);
+    }
+
+    // tributaries go one way, everything else either
+    pub fn has_treaty(&self, kind: TreatyKind, a: PolityRef, b: PolityRef) -> bool {
+        self.treaties.iter().any(|t| {
+            t.kind == kind && ((t.a == a && t.b == b) || (kind != TreatyKind::Tributary && t.a == b && t.b == a))
+        })
+    }
+
+    pub fn treaties_of(&self, polity: PolityRef) -> impl Iterator<Item = &Treaty> {
+        self.treaties.iter().filter(move |t| t.binds(polity))
+    }
+
+    pub fn allies(&self, polity: PolityRef) -> Vec<PolityRef> {
+        self.treaties_of(polity)
+            .filter(|t| t.kind == TreatyKind::Alliance)
+            .map(|t| t.other(polity))
+            .collect()
+    }
+
+    pub fn break_treaties(&mut self, kind: TreatyKind, a: PolityRef, b: PolityRef) {
+        self.treaties.retain(|t| !(t.kind == kind && t.binds(a) && t.binds(b)));
+    }
+
+    // a polity that's gone holds no opinions and keeps no treaties
+    pub fn forget(&mut self, polity: PolityRef) {
+        self.opinions.retain(|(of, toward), _| *of != polity && *toward != polity);
+        self.treaties.retain(|t| !t.binds(polity));
+    }
+
+    // polities that know about each other, through a border or a treaty
+    pub fn known(&self, polity: PolityRef, borders: &PolityBorders) -> Vec<PolityRef> {
+        let mut known = borders.neighbors(polity);
+        for treaty in self.treaties_of(polity) {
+            let other = treaty.other(polity);
+            if !known.contains(&other) {
+                known.push(other);
+            }
+        }
+        known
+    }
+}
+
+// where opinion is heading without anything else happening
+fn opinion_baseline(
+    relations: &Relations,
+    borders: &PolityBorders,
+    cultures: &HashMap<PolityRef, CultureRef>,
+    of: PolityRef,
+    toward: PolityRef,
+) -> f32 {
+    let mut baseline = 0.0;
+    if cultures.get(&of).is_some() && cultures.get(&of) == cultures.get(&toward) {
+        baseline += 20.0;
+    }
+    // land both want
+    let shared_border = borders.of(of).filter(|edge| edge.owner == toward || edge.other == Some(toward)).count();
+    baseline -= (shared_border as f32).min(20.0);
+    for treaty in relations.treaties_of(of).filter(|t| t.binds(toward)) {
+        baseline += match treaty.kind {
+            TreatyKind::Alliance => 15.0,
+            TreatyKind::NonAggression => 5.0,
+            TreatyKind::TradeAccess => 10.0,
+            TreatyKind::Tributary if treaty.a == of => -15.0,
+            TreatyKind::Tributary => 5.0,
+        };
+    }
+    baseline
+}
+
+fn relations_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    borders: Res<PolityBorders>,
+    mut relations: ResMut<Relations>,
+    polity_query: Query<Entity, With<Polity>>,
+    pop_query: Query<(&Pop, &CultureRef, &PolityRef)>,
+) {
+    if !date.is_year {
+        return;
+    }
+    let mut culture_sizes: HashMap<PolityRef, HashMap<CultureRef, isize>> = HashMap::new();
+    for (pop, &culture, &polity) in pop_query.iter() {
+        *culture_sizes.entry(polity).or_default().entry(culture).or_insert(0) += pop.size;
+    }
+    let cultures = culture_sizes
+        .into_iter()
+        .filter_map(|(polity, sizes)| sizes.into_iter().max_by_key(|(_, size)| *size).map(|(culture, _)| (polity, culture)))
+        .collect::<HashMap<_, _>>();
+
+    for polity_ent in polity_query.iter() {
+        let polity = PolityRef(polity_ent);
+        for other in relations.known(polity, &borders).into_iter() {
+            let baseline = opinion_baseline(&relations, &borders, &cultures, polity, other);
+            let drift = (baseline - relations.opinion(polity, other)) * OPINION_DRIFT;
+            relations.change_opinion(polity, other, drift);
+        }
+    }
+    for treaty in relations.treaties.iter().filter(|t| t.kind == TreatyKind::Tributary) {
+        commands.add(PayTributeCommand {
+            from: treaty.a,
+            to: treaty.b,
+        });
+    }
+}
+
+pub struct PayTributeCommand {
+    pub from: PolityRef,
+    pub to: PolityRef,
+}
+
+impl Command for PayTributeCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let receiver = match self.to.capital(world) {
+            Some(capital) => capital.get::<SettlementPops>(world).0.first().cloned(),
+            None => None,
+        };
+        let receiver = match receiver {
+            Some(receiver) => receiver,
+            None => return,
+        };
+        for pop in self.from.pops(world).into_iter() {
+            let tribute = pop.get_mut::<GoodStorage>(world).take_share(TRIBUTE_SHARE);
+            receiver.get_mut::<GoodStorage>(world).merge(tribute);
+        }
+    }
+}
+
+pub struct SignTreatyCommand {
+    pub kind: TreatyKind,
+    pub a: PolityRef,
+    pub b: PolityRef,
+}
+
+impl Command for SignTreatyCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let signed = world.get_resource::<CurrentDate>().unwrap().date;
+        {
+            let mut relations = world.get_resource_mut::<Relations>().unwrap();
+            if relations.has_treaty(self.kind, self.a, self.b) {
+                return;
+            }
+            relations.treaties.push(Treaty {
+                kind: self.kind,
+                a: self.a,
+                b: self.b,
+                signed,
+            });
+        }
+        let text = format!("{} and {} sign a {:?} treaty", self.a.name(world), self.b.name(world), self.kind);
+        notify(world, text);
+    }
+}
+
+pub struct BreakTreatyCommand {
+    pub kind: TreatyKind,
+    pub a: PolityRef,
+    pub b: PolityRef,
+}
+
+impl Command for BreakTreatyCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        if !world.get_resource::<Relations>().unwrap().has_treaty(self.kind, self.a, self.b) {
+            return;
+        }
+        world.get_resource_mut::<Relations>().unwrap().break_treaties(self.kind, self.a, self.b);
+        let text = format!("{} and {} break their {:?} treaty", self.a.name(world), self.b.name(world), self.kind);
+        notify(world, text);
+    }
+}
+
+pub struct ChangeOpinionCommand {
+    pub of: PolityRef,
+    pub toward: PolityRef,
+    pub amount: f32,
+}
+
+impl Command for ChangeOpinionCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        world.get_resource_mut::<Relations>().unwrap().change_opinion(self.of, self.toward, self.amount);
+    }
+}
+
+// one polity asks another to sign a treaty
+pub struct DiplomaticProposalEvent {
+    pub from: PolityRef,
+    pub to: PolityRef,
+    pub kind: TreatyKind,
+}
+
+#[derive(EnumIter, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProposalChoice {
+    Accept,
+    Reject,
+}
+
+impl DiplomaticProposalEvent {
+    // for a tributary the one being asked is the one paying
+    fn treaty_sides(&self) -> (PolityRef, PolityRef) {
+        match self.kind {
+            TreatyKind::Tributary => (self.to, self.from),
+            _ => (self.from, self.to),
+        }
+    }
+}
+
+impl GameEvent for DiplomaticProposalEvent {
+    type Choice = ProposalChoice;
+
+    fn description(&self, world: &World) -> String {
+        match self.kind {
+            TreatyKind::Tributary => format!("{} demands tribute from {}", self.from.name(world), self.to.name(world)),
+            kind => format!("{} proposes a {:?} treaty to {}", self.from.name(world), kind, self.to.name(world)),
+        }
+    }
+
+    fn choices(&self) -> Vec<ProposalChoice> {
+        ProposalChoice::iter().collect()
+    }
+
+    fn weigh_choice(&self, agent: &ValueAgent, world: &World, choice: ProposalChoice) -> f32 {
+        let opinion = world.get_resource::<Relations>().unwrap().opinion(self.to, self.from);
+        match choice {
+            ProposalChoice::Accept => {
+                let strength = self.from.population(world) as f32 / self.to.population(world).max(1) as f32;
+                opinion / 50.0 + match self.kind {
+                    TreatyKind::Alliance => 0.5 * agent.security,
+                    TreatyKind::NonAggression => 0.3 * agent.security - 0.3 * agent.expansion,
+                    TreatyKind::TradeAccess => 0.5 * agent.prosperity,
+                    // paying up beats being overrun by someone much bigger
+                    TreatyKind::Tributary => (strength - 2.0).max(-1.0).min(1.0) * agent.security - 0.5 * agent.tradition,
+                }
+            },
+            ProposalChoice::Reject => 0.1 * agent.tradition,
+        }
+    }
+
+    fn effects(&self, world: &World, choice: ProposalChoice) -> Vec<Box<dyn Command>> {
+        match choice {
+            ProposalChoice::Accept => {
+                let (a, b) = self.treaty_sides();
+                vec![Box::new(SignTreatyCommand { kind: self.kind, a, b })]
+            },
+            ProposalChoice::Reject => vec![Box::new(ChangeOpinionCommand {
+                of: self.from,
+                toward: self.to,
+                amount: REJECTION_PENALTY,
+            })],
+        }
+    }
+
+    // both sides have to still be around to sign anything
+    fn is_valid(&self, world: &World) -> bool {
+        let (a, b) = self.treaty_sides();
+        self.from.try_get::<Polity>(world).is_some()
+            && self.to.try_get::<Polity>(world).is_some()
+            && !world.get_resource::<Relations>().unwrap().has_treaty(self.kind, a, b)
+    }
+
+    fn polities(&self) -> Vec<PolityRef> {
+        vec![self.from, self.to]
+    }
+
+    fn key(&self) -> Option<EventKey> {
+        Some(EventKey::Proposal { from: self.from, kind: self.kind })
+    }
+}
+
+// treaties a polity's council could go after with its neighbours
+pub fn diplomatic_options(world: &World, polity: PolityRef) -> Vec<AgentOption> {
+    let relations = world.get_resource::<Relations>().unwrap();
+    let borders = world.get_resource::<PolityBorders>().unwrap();
+    let queue = world.get_resource::<EventQueue>().unwrap();
+    // nothing to gain from asking again while they're still making up their minds
+    let unasked = |other: PolityRef, kind: TreatyKind| !queue.is_pending(other, EventKey::Proposal { from: polity, kind });
+    let own_population = polity.population(world).max(1) as f32;
+    let mut options = Vec::new();
+    let known = relations.known(polity, borders);
+    let friendliest = known
+        .iter()
+        .map(|other| (*other, relations.opinion(polity, *other)))
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+    if let Some((other, opinion)) = friendliest {
+        let propose = |kind: TreatyKind, value: Value, name: &'static str| {
+            AgentOption::new(name)
+                .consider(value, 0.2 + opinion / 100.0, format!("{} thinks {:.0} of them", polity.name(world), opinion))
+                .command(Box::new(PostEventCommand {
+                    owner: other,
+                    event: Box::new(DiplomaticProposalEvent { from: polity, to: other, kind }),
+                }))
+        };
+        if !relations.has_treaty(TreatyKind::Alliance, polity, other) && unasked(other, TreatyKind::Alliance) {
+            options.push(propose(TreatyKind::Alliance, Value::Security, "Propose alliance"));
+        }
+        if !relations.has_treaty(TreatyKind::TradeAccess, polity, other) && unasked(other, TreatyKind::TradeAccess) {
+            options.push(propose(TreatyKind::TradeAccess, Value::Prosperity, "Propose trade"));
+        }
+    }
+    // keep the strongest neighbour from looking our way
+    let strongest = known
+        .iter()
+        .map(|other| (*other, other.population(world) as f32 / own_population))
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+    let wars = world.get_resource::<Wars>().unwrap();
+    let pactable = |other: PolityRef, ratio: f32| {
+        ratio > 1.0
+            && !relations.has_treaty(TreatyKind::NonAggression, polity, other)
+            && !wars.at_war(polity, other)
+            && unasked(other, TreatyKind::NonAggression)
+    };
+    if let Some((other, ratio)) = strongest.filter(|(other, ratio)| pactable(*other, *ratio)) {
+        options.push(
+            AgentOption::new("Propose non-aggression")
+                .consider(Value::Security, (0.2 * ratio).min(1.0), format!("{:.0}% our size", ratio * 100.0))
+                .command(Box::new(PostEventCommand {
+                    owner: other,
+                    event: Box::new(DiplomaticProposalEvent { from: polity, to: other, kind: TreatyKind::NonAggression }),
+                }))
+        );
+    }
+    // lean on the weakest neighbour
+    let weakest = known
+        .iter()
+        .map(|other| (*other, other.population(world) as f32 / own_population))
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+    let demandable = |other: PolityRef, ratio: f32| {
+        ratio < 0.5 && !relations.has_treaty(TreatyKind::Tributary, other, polity) && unasked(other, TreatyKind::Tributary)
+    };
+    if let Some((other, ratio)) = weakest.filter(|(other, ratio)| demandable(*other, *ratio)) {
+        options.push(
+            AgentOption::new("Demand tribute")
+                .consider(Value::Expansion, 0.5 - ratio, format!("{:.0}% our size", ratio * 100.0))
+                .consider(Value::Security, -0.1, "makes enemies".to_owned())
+                .command(Box::new(PostEventCommand {
+                    owner: other,
+                    event: Box::new(DiplomaticProposalEvent { from: polity, to: other, kind: TreatyKind::Tributary }),
+                }))
+        );
+    }
+    options
+}
+
+// fills in the diplomacy panel with where the player stands with everyone they know
+fn diplomacy_panel_system(
+    relations: Res<Relations>,
+    borders: Res<PolityBorders>,
+    player: Res<PlayerPolity>,
+    polity_query: Query<&Polity>,
+    ruler_query: Query<&Ruler>,
+    character_query: Query<&Character>,
+    mut info_tag_query: Query<(&InfoTag, &mut Text)>,
+) {
+    for (info_tag, mut text) in info_tag_query.iter_mut() {
+        if *info_tag != InfoTag::PlayerDiplomacy {
+            continue;
+        }
+        let player = match player.0 {
+            Some(player) => player,
+            None => continue,
+        };
+        let name = |polity: PolityRef| polity_query.get(polity.0).map(|p| p.name.clone()).unwrap_or_default();
+        let lines = relations
+            .known(player, &borders)
+            .into_iter()
+            .map(|other| {
+                let treaties = relations
+                    .treaties_of(player)
+                    .filter(|t| t.binds(other))
+                    .map(|t| format!("{:?}", t.kind))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!(
+                    "{}: {:.0} / {:.0} {}",
+                    name(other),
+                    relations.opinion(player, other),
+                    relations.opinion(other, player),
+                    treaties,
+                )
+            })
+            .collect::<Vec<_>>();
+        let ruler = ruler_query
+            .get(player.entity())
+            .ok()
+            .and_then(|ruler| character_query.get(ruler.ruler.entity()).ok())
+            .map(|ruler| format!(", ruled by {}", ruler.name))
+            .unwrap_or_default();
+        text.sections[0].value = format!("{}{}\n{}", name(player), ruler, lines.join("\n"));
+    }
+}
+
+pub struct DiplomacyPlugin;
+
+impl Plugin for DiplomacyPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .init_resource::<Relations>()
+            .add_system_to_day(relations_system.system())
+            .add_system(diplomacy_panel_system.system());
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+
+use bevy::{ecs::system::Command, prelude::*};
+use bevy_tilemap::prelude::*;
+use rand::{prelude::SliceRandom, thread_rng, Rng};
+
+use crate::prelude::*;
+use crate::factor::FactorRef;
+use crate::input::CurrentOverlayType;
+use crate::map::{MapTileType, TileSpriteIndices};
+use crate::notification::Notifications;
+use crate::pops::{AgeCohorts, Hunger, PopDieCommand};
+use crate::probability::binomial_isample;
+use crate::province::ProvinceMap;
+use crate::settlement::{Settlement, SettlementPops};
+
+// monthly chance any one pop comes down with something new
+const DISEASE_SEED_CHANCE: f32 = 0.0005;
+// settlements this close to an outbreak can catch it through trade and travel
+const CONTACT_RADIUS: isize = 2;
+
+#[game_ref]
+pub struct DiseaseRef(pub Entity);
+
+#[derive(Debug, Clone)]
+pub struct Disease {
+    pub name: String,
+    // monthly chance of passing it on to someone, scaled by how many around are sick
+    pub infectivity: f32,
+    // chance a case ends in death
+    pub lethality: f32,
+    // how long a case lasts
+    pub duration_days: usize,
+    // how long survivors are safe from catching it again
+    pub immunity_years: usize,
+    pub extinct: bool,
+}
+
+impl Disease {
+    pub fn random() -> Self {
+        let mut rng = thread_rng();
+        let adjectives = ["spotted", "sweating", "bloody", "coughing", "red", "grey", "wasting", "burning"];
+        let nouns = ["fever", "sickness", "flux", "pox", "plague", "ague"];
+        Self {
+            name: format!("{} {}", adjectives.choose(&mut rng).unwrap(), nouns.choose(&mut rng).unwrap()),
+            infectivity: rng.gen_range(0.2..0.8),
+            lethality: rng.gen_range(0.01..0.3),
+            duration_days: rng.gen_range(10..60),
+            immunity_years: rng.gen_range(1..30),
+            extinct: false,
+        }
+    }
+
+    // share of current cases that end, by death or recovery, over a month
+    pub fn monthly_resolution(&self) -> f32 {
+        (30.0 / self.duration_days as f32).min(1.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Infection {
+    pub disease: DiseaseRef,
+    pub infected: isize,
+    pub immune: isize,
+    // once nobody is sick, immunity wears off on this date
+    pub immune_until: Option<Date>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PopDiseases(pub Vec<Infection>);
+
+impl PopDiseases {
+    fn entry(&mut self, disease: DiseaseRef) -> &mut Infection {
+        if let Some(idx) = self.0.iter().position(|i| i.disease == disease) {
+            &mut self.0[idx]
+        } else {
+            self.0.push(Infection {
+                disease,
+                infected: 0,
+                immune: 0,
+                immune_until: None,
+            });
+            self.0.last_mut().unwrap()
+        }
+    }
+
+    pub fn infected(&self, disease: DiseaseRef) -> isize {
+        self.0.iter().find(|i| i.disease == disease).map(|i| i.infected).unwrap_or(0)
+    }
+
+    pub fn total_infected(&self) -> isize {
+        self.0.iter().map(|i| i.infected).sum()
+    }
+
+    // infect up to `amount` people who aren't already sick or immune, returns how many caught it
+    pub fn infect(&mut self, disease: DiseaseRef, amount: isize, size: isize) -> isize {
+        let infection = self.entry(disease);
+        let susceptible = (size - infection.infected - infection.immune).max(0);
+        let caught = amount.min(susceptible);
+        infection.infected += caught;
+        infection.immune_until = None;
+        caught
+    }
+
+    // the sick and immune among `share` of this pop, taken away with them
+    pub fn split(&mut self, share: f32) -> PopDiseases {
+        let share = share.max(0.0).min(1.0);
+        let mut taken = Vec::new();
+        for infection in self.0.iter_mut() {
+            let infected = (infection.infected as f32 * share).round() as isize;
+            let immune = (infection.immune as f32 * share).round() as isize;
+            infection.infected -= infected;
+            infection.immune -= immune;
+            taken.push(Infection {
+                disease: infection.disease,
+                infected,
+                immune,
+                immune_until: infection.immune_until,
+            });
+        }
+        PopDiseases(taken)
+    }
+
+    pub fn merge(&mut self, other: PopDiseases) {
+        for infection in other.0.into_iter() {
+            let own = self.entry(infection.disease);
+            own.infected += infection.infected;
+            own.immune += infection.immune;
+            if own.infected > 0 {
+                own.immune_until = None;
+            } else {
+                own.immune_until = own.immune_until.or(infection.immune_until);
+            }
+        }
+    }
+}
+
+fn disease_seed_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    pop_query: Query<Entity, With<Pop>>,
+) {
+    if !date.is_month {
+        return;
+    }
+    for pop_ent in pop_query.iter() {
+        if individual_event(DISEASE_SEED_CHANCE) {
+            commands.add(SpawnDiseaseCommand {
+                pop: PopRef(pop_ent),
+            });
+        }
+    }
+}
+
+// something new crawls out of the swamp and makes a few people sick
+pub struct SpawnDiseaseCommand {
+    pub pop: PopRef,
+}
+
+impl Command for SpawnDiseaseCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let size = match self.pop.try_get::<Pop>(world) {
+            Some(pop) => pop.size,
+            None => return,
+        };
+        let disease = Disease::random();
+        let name = disease.name.clone();
+        let disease = DiseaseRef(world.spawn().insert(disease).id());
+        self.pop.get_mut::<PopDiseases>(world).infect(disease, thread_rng().gen_range(1..6), size);
+        let settlement = self.pop.accessor(world).settlement().name().clone();
+        crate::notification::notify(world, format!("The {} has broken out in {}", name, settlement));
+    }
+}
+
+fn disease_progress_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    mut notifications: ResMut<Notifications>,
+    mut disease_query: Query<&mut Disease>,
+    mut pop_query: Query<(Entity, &mut Pop, &mut AgeCohorts, &mut PopDiseases, &SettlementRef, &Hunger)>,
+    settlement_query: Query<(&Settlement, &SettlementPops, &MapCoordinate)>,
+    province_settlement_query: Query<&SettlementRef, With<Province>>,
+    province_map: Res<ProvinceMap>,
+) {
+    if !date.is_month {
+        return;
+    }
+    // how many are sick of what in each settlement
+    let mut outbreaks: HashMap<(SettlementRef, DiseaseRef), isize> = HashMap::new();
+    let mut sizes: HashMap<SettlementRef, isize> = HashMap::new();
+    for (_, pop, _, diseases, &settlement, _) in pop_query.iter_mut() {
+        *sizes.entry(settlement).or_insert(0) += pop.size;
+        for infection in diseases.0.iter() {
+            if infection.infected > 0 {
+                *outbreaks.entry((settlement, infection.disease)).or_insert(0) += infection.infected;
+            }
+        }
+    }
+
+    let mut still_active: HashMap<DiseaseRef, isize> = HashMap::new();
+    for (pop_ent, mut pop, mut ages, mut diseases, &settlement, hunger) in pop_query.iter_mut() {
+        // anything going around the settlement can reach every pop in it
+        for (&(outbreak_settlement, disease), _) in outbreaks.iter() {
+            if outbreak_settlement == settlement {
+                diseases.entry(disease);
+            }
+        }
+        let settlement_size = sizes[&settlement].max(1) as f32;
+        let mut dead = 0;
+        for infection in diseases.0.iter_mut() {
+            let disease = match disease_query.get_mut(infection.disease.0) {
+                Ok(disease) => disease,
+                Err(_) => continue,
+            };
+            let local_infected = *outbreaks.get(&(settlement, infection.disease)).unwrap_or(&0) as f32;
+            let susceptible = (pop.size - infection.infected - infection.immune).max(0);
+            // the hungry catch things easier
+            let catch_chance = (disease.infectivity * local_infected / settlement_size * (1.0 + hunger.shortfall)).min(1.0);
+            let caught = binomial_isample(susceptible, catch_chance);
+            let resolved = binomial_isample(infection.infected, disease.monthly_resolution());
+            let died = binomial_isample(resolved, disease.lethality);
+            infection.infected += caught - resolved;
+            infection.immune += resolved - died;
+            dead += died;
+            if infection.infected > 0 {
+                *still_active.entry(infection.disease).or_insert(0) += infection.infected;
+            } else if infection.immune_until.is_none() {
+                let until = date.date.years_after(disease.immunity_years);
+                infection.immune_until = Some(until);
+            }
+        }
+        if dead > 0 {
+            ages.kill(dead);
+            pop.size = ages.size();
+        }
+        // the dead and those who left don't stay sick or immune
+        for infection in diseases.0.iter_mut() {
+            infection.infected = infection.infected.min(pop.size).max(0);
+            infection.immune = infection.immune.min(pop.size - infection.infected).max(0);
+        }
+        diseases.0.retain(|i| i.infected > 0 || i.immune_until.map(|until| until.is_after(date.date)).unwrap_or(true));
+        if pop.size <= 0 {
+            commands.add(PopDieCommand(PopRef(pop_ent)));
+        }
+    }
+
+    // carried along roads and trade to the neighbours
+    let mut seeds = Vec::new();
+    for (&(settlement, disease_ref), &infected) in outbreaks.iter() {
+        let disease = match disease_query.get_mut(disease_ref.0) {
+            Ok(disease) => disease,
+            Err(_) => continue,
+        };
+        let (_, _, &coordinate) = match settlement_query.get(settlement.0) {
+            Ok(settlement) => settlement,
+            Err(_) => continue,
+        };
+        let infected_share = infected as f32 / sizes[&settlement].max(1) as f32;
+        for neighbor in coordinate.neighbors_in_radius(CONTACT_RADIUS).into_iter() {
+            let neighbor_settlement = match province_map.0.get(&neighbor).and_then(|p| province_settlement_query.get(p.0).ok()) {
+                Some(&neighbor_settlement) if neighbor_settlement != settlement => neighbor_settlement,
+                _ => continue,
+            };
+            let distance = coordinate.distance(neighbor).max(1) as f32;
+            if individual_event((disease.infectivity * infected_share / distance).min(1.0)) {
+                seeds.push((neighbor_settlement, disease_ref));
+            }
+        }
+    }
+    for (settlement, disease_ref) in seeds.into_iter() {
+        let (info, pops, _) = settlement_query.get(settlement.0).unwrap();
+        let pop_ref = match pops.0.choose(&mut thread_rng()) {
+            Some(&pop_ref) => pop_ref,
+            None => continue,
+        };
+        if let Ok((_, pop, _, mut diseases, _, _)) = pop_query.get_mut(pop_ref.0) {
+            let fresh = diseases.infected(disease_ref) == 0;
+            if diseases.infect(disease_ref, 1, pop.size) > 0 && fresh {
+                let name = disease_query.get_mut(disease_ref.0).unwrap().name.clone();
+                notifications.push(date.date, format!("The {} has spread to {}", name, info.name));
+            }
+        }
+    }
+
+    for (&(_, disease_ref), _) in outbreaks.iter() {
+        if !still_active.contains_key(&disease_ref) {
+            if let Ok(mut disease) = disease_query.get_mut(disease_ref.0) {
+                if !disease.extinct {
+                    disease.extinct = true;
+                    notifications.push(date.date, format!("The {} has run its course", disease.name));
+                }
+            }
+        }
+    }
+}
+
+pub fn disease_overlay_system(
+    mut frame: Local<isize>,
+    tile_sprite_indices: Res<TileSpriteIndices>,
+    pop_query: Query<(&Pop, &PopDiseases, &SettlementRef)>,
+    settlement_query: Query<&MapCoordinate, With<Settlement>>,
+    current_overlay: Res<CurrentOverlayType>,
+    mut tile_map_query: Query<&mut Tilemap>,
+) {
+    *frame += 1;
+    if *frame % 20 == 0 && *current_overlay == CurrentOverlayType::Disease {
+        // (sick, immune, total) per settlement
+        let mut health: HashMap<SettlementRef, (isize, isize, isize)> = HashMap::new();
+        for (pop, diseases, &settlement) in pop_query.iter() {
+            let entry = health.entry(settlement).or_insert((0, 0, 0));
+            entry.0 += diseases.total_infected();
+            entry.1 += diseases.0.iter().map(|i| i.immune).max().unwrap_or(0);
+            entry.2 += pop.size;
+        }
+        for (settlement, (sick, immune, total)) in health.iter() {
+            if let Ok(coordinate) = settlement_query.get(settlement.0) {
+                let total = (*total).max(1) as f32;
+                let color = Color::rgb(
+                    (*sick as f32 / total * 10.0).min(1.0),
+                    0.2,
+                    (*immune as f32 / total).min(1.0),
+                );
+                let point = coordinate.point3();
+                for mut tile_map in tile_map_query.iter_mut() {
+                    let mut tile = tile_map.get_tile_mut(point, 0).unwrap();
+                    tile.color = color;
+                    let sprite_index = *tile_sprite_indices.0.get(&MapTileType::None).unwrap();
+                    tile.index = sprite_index;
+                }
+            }
+        }
+    }
+}
+
+pub struct DiseasePlugin;
+
+impl Plugin for DiseasePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_system_to_day(disease_seed_system.system())
+            .add_system_to_day(disease_progress_system.system())
+            .add_system(disease_overlay_system.system());
+    }
+}
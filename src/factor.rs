@@ -1,10 +1,10 @@
-use bevy::{core::FixedTimestep, ecs::{component::Component, system::Command, world::EntityRef, system::SystemParam}, prelude::*};
+use bevy::{ecs::{component::Component, system::Command, world::EntityRef, system::SystemParam}, prelude::*};
 use rand::{Rng, distributions::Slice, prelude::SliceRandom, random, thread_rng};
 use rand_distr::Uniform;
 use std::collections::{HashMap, VecDeque};
 use std::hash::Hash;
 use std::fmt::Debug;
-use crate::{formula::{FactorSubject, FormulaId, FormulaSystem}, pops::GoodType, prelude::*};
+use crate::{character::{CharacterRef, DynastyRef}, disease::DiseaseRef, formula::{FactorSubject, FormulaId, FormulaSystem}, pops::GoodType, prelude::*};
 
 pub enum FactorEffectLabel {
 
@@ -23,9 +23,17 @@ pub enum FactorType {
     SettlementPopulation,
     SettlementCarryingCapacity,
     SettlementPressure,
+    // how close a settlement is to revolt, 0.0 is calm and 1.0 is ready to rise
+    SettlementUnrest,
 
     PopDemand(GoodType),
     PopPressure,
+    // how well a pop thinks of its polity, 0.0 is indifferent
+    PopLoyalty,
+    // added onto the base fertility and mortality curves, 0.0 is no change
+    PopFertility,
+    PopMortality,
+    PopHarvest,
 }
 
 pub enum FactorDecay {
@@ -44,6 +52,10 @@ pub enum FactorRef {
     Province(ProvinceRef),
     Culture(CultureRef),
     Settlement(SettlementRef),
+    Disease(DiseaseRef),
+    Character(CharacterRef),
+    Dynasty(DynastyRef),
+    Religion(ReligionRef),
 }
 
 pub type FST = (FactorRef, FactorType);
@@ -96,8 +108,8 @@ pub trait Factored {
 }
 
 pub struct AddFactorCommand {
-    target: FST,
-    amt: f32,
+    pub target: FST,
+    pub amt: f32,
 }
 
 impl Command for AddFactorCommand {
@@ -100,24 +100,23 @@ pub struct FormulaSystem<T> where T: FactorSubject {
 
 // TODO: don't propogate onto end nodes
 impl<T> FormulaSystem<T> where T: FactorSubject {
+    // factors nobody has touched yet start out as a constant 0.0
     pub fn add_factor(&self, f: &T, amount: f32) {
-        self.factors.get_mut(f).map(|mut factor| {
-            match factor.value_mut() {
-                Factor::Constant(n) => *n += amount,
-                Factor::Decay(n, _) => *n += amount,
-                Factor::Formula(_) => println!("add to factor {:?}", f),
-            }
-        });
+        let mut factor = self.factors.entry(f.clone()).or_insert(Factor::Constant(0.0));
+        match factor.value_mut() {
+            Factor::Constant(n) => *n += amount,
+            Factor::Decay(n, _) => *n += amount,
+            Factor::Formula(_) => println!("add to factor {:?}", f),
+        }
     }
 
     pub fn set_factor(&self, f: &T, amount: f32) {
-        self.factors.get_mut(f).map(|mut factor| {
-            match factor.value_mut() {
-                Factor::Constant(n) => *n = amount,
-                Factor::Decay(n, _) => *n = amount,
-                Factor::Formula(_) => println!("add to factor {:?}", f),
-            }
-        });
+        let mut factor = self.factors.entry(f.clone()).or_insert(Factor::Constant(0.0));
+        match factor.value_mut() {
+            Factor::Constant(n) => *n = amount,
+            Factor::Decay(n, _) => *n = amount,
+            Factor::Formula(_) => println!("add to factor {:?}", f),
+        }
     }
 
     pub fn get_factor(&self, f: &T) -> f32 {
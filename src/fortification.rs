@@ -0,0 +1,356 @@
+use bevy::{ecs::system::Command, prelude::*};
+
+use crate::prelude::*;
+use crate::agent::{AgentOption, Value};
+use crate::map::{MapTile, MapTileType};
+use crate::notification::{notify, Notifications};
+use crate::pops::{AgeCohorts, GoodStorage, PopDieCommand, MONTHLY_CALORIE_NEED};
+use crate::province::ProvinceMap;
+use crate::settlement::{Settlement, SettlementPops};
+use crate::technology::Technology;
+use crate::war::{occupy_settlement, Army, Wars};
+
+pub const MAX_FORTIFICATION_LEVEL: usize = 3;
+// months of work for the first level of walls, each level after takes as long again
+const FORTIFICATION_MONTHS: usize = 12;
+// food eaten by the builders, per level and per person living there
+const FORTIFICATION_CALORIES_PER_HEAD: f32 = 0.5 * MONTHLY_CALORIE_NEED;
+// weeks a siege takes per level of walls, against an army the size of the garrison
+const SIEGE_WEEKS_PER_LEVEL: f32 = 6.0;
+// share of the besiegers lost each week to sickness and desertion
+const SIEGE_ATTRITION: f32 = 0.01;
+// with the fields cut off and the countryside crowded in, stores go this many times faster
+const SIEGE_CROWDING: f32 = 2.0;
+// share of the hungry part of the garrison that dies each week
+const SIEGE_STARVATION: f32 = 0.05;
+// chance each month that pirates hit an unwalled settlement on the coast
+const PIRATE_RAID_CHANCE: f32 = 0.01;
+// chance each month that an enemy army nearby raids an unwalled settlement
+const ARMY_RAID_CHANCE: f32 = 0.2;
+const RAID_RADIUS: isize = 2;
+// share of the stores carried off in a raid, and of the people killed
+const RAID_PLUNDER: f32 = 0.3;
+const RAID_DEATHS: f32 = 0.01;
+// how long a raid is remembered when thinking about walls, in years
+const RAID_MEMORY_YEARS: usize = 3;
+
+// walls around a settlement, each level makes it longer to take
+#[derive(Debug, Default)]
+pub struct Fortifications {
+    pub level: usize,
+    // months of work left on the next level, None when nobody's building
+    pub construction: Option<usize>,
+    pub last_raided: Option<Date>,
+}
+
+// an army camped outside a walled settlement
+pub struct Siege {
+    pub army: Entity,
+    pub by: PolityRef,
+    pub war: usize,
+    pub started: Date,
+    // the walls give at 1.0
+    pub progress: f32,
+}
+
+pub fn coastal(world: &World, coordinate: MapCoordinate) -> bool {
+    let province_map = world.get_resource::<ProvinceMap>().unwrap();
+    coordinate
+        .neighbors_iter()
+        .filter_map(|c| province_map.0.get(&c))
+        .any(|p| p.get::<MapTile>(world).tile_type == MapTileType::Water)
+}
+
+// a palisade anyone can put up, stone walls need masons
+fn max_level(world: &World, settlement: SettlementRef) -> usize {
+    if settlement.knows(world, Technology::Masonry) {
+        MAX_FORTIFICATION_LEVEL
+    } else {
+        1
+    }
+}
+
+// the treasury feeds the builders, and everyone in the settlement chips in for the rest
+pub fn pay_for_works(world: &mut World, settlement: SettlementRef, calories: f32, item: &'static str) -> Option<()> {
+    let polity = *settlement.get::<PolityRef>(world);
+    let from_treasury = polity.treasury_calories(world).min(calories);
+    let calories = calories - from_treasury;
+    let pops = settlement.get::<SettlementPops>(world).0.clone();
+    let available: f32 = pops.iter().map(|pop| pop.get::<GoodStorage>(world).calories()).sum();
+    if available < calories {
+        return None;
+    }
+    polity.spend_calories(world, from_treasury, item);
+    if calories <= 0.0 {
+        return Some(());
+    }
+    for pop in pops.into_iter() {
+        let share = pop.get::<GoodStorage>(world).calories() / available;
+        pop.get_mut::<GoodStorage>(world).take_calories(calories * share);
+    }
+    Some(())
+}
+
+// kill some of a pop's people and keep its size in step, it dies out if nobody's left
+fn kill_people(world: &mut World, pop: PopRef, amount: isize) -> isize {
+    let (killed, remaining) = {
+        let mut ages = pop.get_mut::<AgeCohorts>(world);
+        let killed = ages.kill(amount);
+        (killed, ages.size())
+    };
+    pop.get_mut::<Pop>(world).size = remaining;
+    if remaining <= 0 {
+        Box::new(PopDieCommand(pop)).write(world);
+    }
+    killed
+}
+
+pub struct BuildFortificationCommand {
+    pub settlement: SettlementRef,
+}
+
+impl Command for BuildFortificationCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let level = {
+            let fortifications = self.settlement.get::<Fortifications>(world);
+            if fortifications.construction.is_some() || fortifications.level >= max_level(world, self.settlement) {
+                return;
+            }
+            fortifications.level
+        };
+        let population = self.settlement.get::<Settlement>(world).population;
+        let cost = FORTIFICATION_CALORIES_PER_HEAD * population as f32 * (level + 1) as f32;
+        if pay_for_works(world, self.settlement, cost, "fortifications").is_none() {
+            return;
+        }
+        self.settlement.get_mut::<Fortifications>(world).construction = Some(FORTIFICATION_MONTHS * (level + 1));
+        let text = format!("{} starts building walls", self.settlement.get::<Settlement>(world).name);
+        notify(world, text);
+    }
+}
+
+// one week of a siege: the besiegers waste away, the defenders go hungry, the walls wear down
+pub struct SiegeWeekCommand(pub SettlementRef);
+
+impl SiegeWeekCommand {
+    fn lift(&self, world: &mut World) {
+        world.entity_mut(self.0.entity()).remove::<Siege>();
+        let text = format!("the siege of {} is lifted", self.0.get::<Settlement>(world).name);
+        notify(world, text);
+    }
+}
+
+impl Command for SiegeWeekCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let (army, by, war) = match self.0.try_get::<Siege>(world) {
+            Some(siege) => (siege.army, siege.by, siege.war),
+            None => return,
+        };
+        let here = *self.0.get::<MapCoordinate>(world);
+        let besieging = world.get::<MapCoordinate>(army) == Some(&here) && world.get::<Army>(army).is_some();
+        let war_on = world.get_resource::<Wars>().unwrap().get(war).map(|war| war.hostile(by, *self.0.get::<PolityRef>(world)));
+        if !besieging || war_on != Some(true) {
+            self.lift(world);
+            return;
+        }
+
+        let besiegers = {
+            let mut army = world.get_mut::<Army>(army).unwrap();
+            let losses = (army.size() as f32 * SIEGE_ATTRITION) as isize;
+            army.kill(losses);
+            army.size()
+        };
+        if besiegers <= 0 {
+            self.lift(world);
+            return;
+        }
+
+        // the garrison eats into its stores, and starves once they're gone
+        let mut garrison = 0;
+        let mut hungry = 0.0;
+        let pops = self.0.get::<SettlementPops>(world).0.clone();
+        for pop in pops.into_iter() {
+            let need = pop.get::<AgeCohorts>(world).eaters() * MONTHLY_CALORIE_NEED / 4.0 * SIEGE_CROWDING;
+            let eaten = pop.get_mut::<GoodStorage>(world).take_calories(need).calories();
+            let shortfall = if need > 0.0 { (1.0 - eaten / need).max(0.0) } else { 0.0 };
+            let size = pop.get::<Pop>(world).size;
+            kill_people(world, pop, (size as f32 * shortfall * SIEGE_STARVATION) as isize);
+            if let Some(ages) = pop.try_get::<AgeCohorts>(world) {
+                garrison += ages.working();
+            }
+            hungry += shortfall * size as f32;
+        }
+        let population = self.0.get::<Settlement>(world).population.max(1) as f32;
+        let starving = hungry / population;
+
+        let level = self.0.get::<Fortifications>(world).level.max(1) as f32;
+        let odds = (besiegers as f32 / garrison.max(1) as f32).max(0.25).min(2.0);
+        let fallen = {
+            let mut siege = self.0.get_mut::<Siege>(world);
+            siege.progress += odds * (1.0 + starving) / (SIEGE_WEEKS_PER_LEVEL * level);
+            siege.progress >= 1.0
+        };
+        if fallen {
+            world.entity_mut(self.0.entity()).remove::<Siege>();
+            // the breach has to be rebuilt after the war
+            let mut fortifications = self.0.get_mut::<Fortifications>(world);
+            fortifications.level = fortifications.level.saturating_sub(1);
+            occupy_settlement(world, self.0, by, war);
+        }
+    }
+}
+
+// plunder an unwalled settlement, for an army that's the loot keeps it fed
+pub struct RaidCommand {
+    pub settlement: SettlementRef,
+    pub army: Option<Entity>,
+}
+
+impl Command for RaidCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        if self.settlement.get::<Fortifications>(world).level > 0 {
+            return;
+        }
+        let mut killed = 0;
+        let pops = self.settlement.get::<SettlementPops>(world).0.clone();
+        for pop in pops.into_iter() {
+            pop.get_mut::<GoodStorage>(world).take_share(RAID_PLUNDER);
+            let size = pop.get::<Pop>(world).size;
+            killed += kill_people(world, pop, (size as f32 * RAID_DEATHS) as isize);
+        }
+        let today = world.get_resource::<CurrentDate>().unwrap().date;
+        self.settlement.get_mut::<Fortifications>(world).last_raided = Some(today);
+        let raiders = self.army.and_then(|army| {
+            world.get_mut::<Army>(army).map(|mut army| {
+                army.supply = 1.0;
+                army.polity
+            })
+        });
+        let raiders = match raiders {
+            Some(polity) => polity.name(world).clone(),
+            None => "pirates".to_owned(),
+        };
+        let text = format!("{} raid {}, {} killed", raiders, self.settlement.get::<Settlement>(world).name, killed);
+        notify(world, text);
+    }
+}
+
+// walls for whichever settlement feels most exposed
+pub fn fortification_options(world: &World, polity: PolityRef) -> Vec<AgentOption> {
+    let today = world.get_resource::<CurrentDate>().unwrap().date;
+    let at_war = world.get_resource::<Wars>().unwrap().of(polity).next().is_some();
+    let threat = |settlement: SettlementRef| {
+        let fortifications = settlement.get::<Fortifications>(world);
+        let mut threat = -0.3 * fortifications.level as f32;
+        if fortifications.last_raided.map(|raided| raided.years_after(RAID_MEMORY_YEARS).is_after(today)).unwrap_or(false) {
+            threat += 0.5;
+        }
+        if coastal(world, *settlement.get::<MapCoordinate>(world)) {
+            threat += 0.2;
+        }
+        if at_war {
+            threat += 0.3;
+        }
+        threat
+    };
+    let exposed = polity
+        .settlements(world)
+        .into_iter()
+        .filter(|settlement| {
+            let fortifications = settlement.get::<Fortifications>(world);
+            fortifications.construction.is_none() && fortifications.level < max_level(world, *settlement)
+        })
+        .map(|settlement| (settlement, threat(settlement)))
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+    match exposed {
+        Some((settlement, threat)) => vec![
+            AgentOption::new("Build walls")
+                .consider(Value::Security, threat, format!("{} is exposed", settlement.get::<Settlement>(world).name))
+                .consider(Value::Prosperity, -0.2, "builders have to be fed".to_owned())
+                .command(Box::new(BuildFortificationCommand { settlement })),
+        ],
+        None => Vec::new(),
+    }
+}
+
+fn fortification_system(
+    date: Res<CurrentDate>,
+    mut notifications: ResMut<Notifications>,
+    mut settlement_query: Query<(&Settlement, &mut Fortifications)>,
+) {
+    if !date.is_month {
+        return;
+    }
+    for (settlement, mut fortifications) in settlement_query.iter_mut() {
+        match fortifications.construction {
+            Some(months) if months > 1 => fortifications.construction = Some(months - 1),
+            Some(_) => {
+                fortifications.construction = None;
+                fortifications.level += 1;
+                notifications.push(date.date, format!("{} finishes its walls", settlement.name));
+            },
+            None => {},
+        }
+    }
+}
+
+fn siege_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    siege_query: Query<Entity, With<Siege>>,
+) {
+    if !date.is_week {
+        return;
+    }
+    for settlement in siege_query.iter() {
+        commands.add(SiegeWeekCommand(SettlementRef(settlement)));
+    }
+}
+
+// pirates along the coast, and enemy armies living off the land
+fn raid_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    wars: Res<Wars>,
+    province_map: Res<ProvinceMap>,
+    tile_query: Query<&MapTile>,
+    settlement_query: Query<(Entity, &Fortifications, &PolityRef, &MapCoordinate)>,
+    army_query: Query<(Entity, &Army, &MapCoordinate)>,
+) {
+    if !date.is_month {
+        return;
+    }
+    for (ent, fortifications, &owner, coordinate) in settlement_query.iter() {
+        if fortifications.level > 0 {
+            continue;
+        }
+        let settlement = SettlementRef(ent);
+        let raider = army_query
+            .iter()
+            .filter(|&(_, army, &at)| wars.at_war(army.polity, owner) && coordinate.distance(at) <= RAID_RADIUS)
+            .find(|_| individual_event(ARMY_RAID_CHANCE))
+            .map(|(army, _, _)| army);
+        if raider.is_some() {
+            commands.add(RaidCommand { settlement, army: raider });
+            continue;
+        }
+        let coastal = coordinate
+            .neighbors_iter()
+            .filter_map(|c| province_map.0.get(&c))
+            .any(|p| tile_query.get(p.entity()).map(|t| t.tile_type == MapTileType::Water).unwrap_or(false));
+        if coastal && individual_event(PIRATE_RAID_CHANCE) {
+            commands.add(RaidCommand { settlement, army: None });
+        }
+    }
+}
+
+pub struct FortificationPlugin;
+
+impl Plugin for FortificationPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_system_to_day(fortification_system.system())
+            .add_system_to_day(siege_system.system())
+            .add_system_to_day(raid_system.system());
+    }
+}
@@ -173,12 +173,24 @@ pub fn info_box_change_system(
     if keyboard_input.pressed(KeyCode::M) {
         *info_box_mode = InfoBoxMode::ModifierSelectList;
     }
+    if keyboard_input.pressed(KeyCode::K) {
+        *info_box_mode = InfoBoxMode::DiplomacyMode;
+    }
+    if keyboard_input.pressed(KeyCode::T) {
+        *info_box_mode = InfoBoxMode::LedgerMode;
+    }
+    if keyboard_input.pressed(KeyCode::S) {
+        *info_box_mode = InfoBoxMode::SettlementMode;
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum CurrentOverlayType {
     ProvincePop,
     Polity,
+    Language,
+    Disease,
+    Religion,
     None,
 }
 
@@ -196,6 +208,18 @@ pub fn overlay_input_system(
         *current_overlay = CurrentOverlayType::Polity;
         *overlay_command = OverlayCommand::Clear;
     }
+    if keyboard_input.pressed(KeyCode::L) {
+        *current_overlay = CurrentOverlayType::Language;
+        *overlay_command = OverlayCommand::Clear;
+    }
+    if keyboard_input.pressed(KeyCode::D) {
+        *current_overlay = CurrentOverlayType::Disease;
+        *overlay_command = OverlayCommand::Clear;
+    }
+    if keyboard_input.pressed(KeyCode::R) {
+        *current_overlay = CurrentOverlayType::Religion;
+        *overlay_command = OverlayCommand::Clear;
+    }
     if keyboard_input.pressed(KeyCode::O) {
         *current_overlay = CurrentOverlayType::None;
         *overlay_command = OverlayCommand::Clear;
@@ -208,10 +232,13 @@ pub fn change_speed_system(
     mut game_paused: ResMut<GamePaused>,
 ) {
     if keyboard_input.just_pressed(KeyCode::LBracket) {
-        game_speed.0 = game_speed.0.max(1) - 1;
+        game_speed.slower();
     }
     if keyboard_input.just_pressed(KeyCode::RBracket) {
-        game_speed.0 = game_speed.0.min(10) + 1;
+        game_speed.faster();
+    }
+    if keyboard_input.just_pressed(KeyCode::Backslash) {
+        game_speed.fastest();
     }
     if keyboard_input.just_pressed(KeyCode::Space) {
         game_paused.0 = !game_paused.0;
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+use bevy::{ecs::system::Command, prelude::*};
+use bevy_tilemap::prelude::*;
+
+use crate::prelude::*;
+use crate::input::CurrentOverlayType;
+use crate::map::{MapTileType, TileSpriteIndices};
+use crate::notification::notify;
+use crate::pops::{Language, PopLanguage};
+use crate::settlement::Settlement;
+
+// drift at which a group of speakers no longer understands the parent language
+pub const LANGUAGE_SPLIT_DRIFT: f32 = 1.0;
+// yearly drift of a completely isolated pop next door to other speakers
+const BASE_DRIFT_PER_YEAR: f32 = 0.01;
+// other speakers further away than this don't count as contact
+const CONTACT_RADIUS: isize = 4;
+
+// where a language sits in its family, lives on the language entity
+pub struct LanguageFamily {
+    pub parent: Option<LanguageRef>,
+    pub children: Vec<LanguageRef>,
+    pub founded: Date,
+}
+
+impl LanguageFamily {
+    pub fn root(founded: Date) -> Self {
+        Self {
+            parent: None,
+            children: Vec::new(),
+            founded,
+        }
+    }
+}
+
+impl LanguageRef {
+    pub fn name<'a>(&self, world: &'a World) -> &'a String {
+        &self.get::<Language>(world).name
+    }
+
+    pub fn parent(&self, world: &World) -> Option<LanguageRef> {
+        self.try_get::<LanguageFamily>(world).and_then(|family| family.parent)
+    }
+
+    // the oldest language this one came from
+    pub fn root(&self, world: &World) -> LanguageRef {
+        let mut current = *self;
+        while let Some(parent) = current.parent(world) {
+            current = parent;
+        }
+        current
+    }
+
+    // this language and everything descended from it, with depth below this one
+    pub fn descendants(&self, world: &World) -> Vec<(usize, LanguageRef)> {
+        let mut result = Vec::new();
+        let mut stack = vec![(0, *self)];
+        while let Some((depth, language)) = stack.pop() {
+            result.push((depth, language));
+            if let Some(family) = language.try_get::<LanguageFamily>(world) {
+                for &child in family.children.iter().rev() {
+                    stack.push((depth + 1, child));
+                }
+            }
+        }
+        result
+    }
+}
+
+fn language_drift_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    mut pop_query: Query<(Entity, &Pop, &SettlementRef, &mut PopLanguage)>,
+    settlement_query: Query<&MapCoordinate>,
+) {
+    if !date.is_year {
+        return;
+    }
+    // who speaks what where
+    let mut speakers: HashMap<(LanguageRef, SettlementRef), isize> = HashMap::new();
+    for (_, pop, &settlement, language) in pop_query.iter_mut() {
+        *speakers.entry((language.language, settlement)).or_insert(0) += pop.size;
+    }
+    let mut splitting = Vec::new();
+    for (pop_ent, pop, &settlement, mut language) in pop_query.iter_mut() {
+        let home = match settlement_query.get(settlement.0) {
+            Ok(&coord) => coord,
+            Err(_) => continue,
+        };
+        let mut local = 0;
+        let mut contact = 0.0;
+        let mut nearest = None;
+        for (&(other_language, other_settlement), &size) in speakers.iter() {
+            if other_language != language.language {
+                continue;
+            }
+            if other_settlement == settlement {
+                local += size;
+                continue;
+            }
+            if let Ok(&coord) = settlement_query.get(other_settlement.0) {
+                let distance = home.distance(coord);
+                nearest = Some(nearest.map_or(distance, |n: isize| n.min(distance)));
+                if distance <= CONTACT_RADIUS {
+                    contact += size as f32 / distance.max(1) as f32;
+                }
+            }
+        }
+        // the only speakers of a language have no one to drift away from
+        let nearest = match nearest {
+            Some(n) => n,
+            None => continue,
+        };
+        let contact_share = contact / (contact + local.max(1) as f32);
+        let remoteness = 1.0 + (nearest as f32 / CONTACT_RADIUS as f32).min(2.0);
+        language.drift += BASE_DRIFT_PER_YEAR * (1.0 - contact_share) * remoteness;
+        if language.drift >= LANGUAGE_SPLIT_DRIFT {
+            splitting.push((PopRef(pop_ent), settlement));
+        }
+    }
+    for (pop, settlement) in splitting.into_iter() {
+        commands.add(SplitLanguageCommand { pop, settlement });
+    }
+}
+
+// the speakers of a language in one settlement have drifted far enough to speak a new one
+pub struct SplitLanguageCommand {
+    pub pop: PopRef,
+    pub settlement: SettlementRef,
+}
+
+impl Command for SplitLanguageCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let parent = match self.pop.try_get::<PopLanguage>(world) {
+            Some(language) if language.drift >= LANGUAGE_SPLIT_DRIFT => language.language,
+            // someone else in the settlement already split us off
+            _ => return,
+        };
+        let founded = world.get_resource::<CurrentDate>().unwrap().date;
+        let daughter = parent.get::<Language>(world).derive();
+        let child = LanguageRef(
+            world
+                .spawn()
+                .insert(daughter)
+                .insert(LanguageFamily {
+                    parent: Some(parent),
+                    children: Vec::new(),
+                    founded,
+                })
+                .id()
+        );
+        if let Some(mut family) = parent.try_get_mut::<LanguageFamily>(world) {
+            family.children.push(child);
+        }
+        let root = parent.root(world);
+        let text = format!(
+            "{} splits off from {} in {}, one of {} tongues of the {} family",
+            child.name(world),
+            parent.name(world),
+            self.settlement.get::<Settlement>(world).name,
+            root.descendants(world).len(),
+            root.name(world),
+        );
+        let mut query = world.query::<(&SettlementRef, &mut PopLanguage)>();
+        for (&settlement, mut language) in query.iter_mut(world) {
+            if settlement == self.settlement && language.language == parent {
+                language.language = child;
+                language.drift = 0.0;
+            }
+        }
+        notify(world, text);
+    }
+}
+
+// root languages get their own hue, daughters get lighter the further they are from it
+fn language_color(language: LanguageRef, family_query: &Query<&LanguageFamily>) -> Color {
+    let mut root = language;
+    let mut depth = 0;
+    while let Some(parent) = family_query.get(root.0).ok().and_then(|family| family.parent) {
+        root = parent;
+        depth += 1;
+    }
+    let hue = (root.0.id() * 47 % 360) as f32;
+    let lightness = (0.3 + 0.1 * depth as f32).min(0.8);
+    Color::hsl(hue, 0.7, lightness)
+}
+
+pub fn language_overlay_system(
+    mut frame: Local<isize>,
+    tile_sprite_indices: Res<TileSpriteIndices>,
+    pop_query: Query<(&Pop, &PopLanguage, &SettlementRef)>,
+    settlement_query: Query<&MapCoordinate>,
+    family_query: Query<&LanguageFamily>,
+    current_overlay: Res<CurrentOverlayType>,
+    mut tile_map_query: Query<&mut Tilemap>,
+) {
+    *frame += 1;
+    if *frame % 20 == 0 && *current_overlay == CurrentOverlayType::Language {
+        // colour each settlement by its most spoken language
+        let mut majority: HashMap<SettlementRef, (LanguageRef, isize)> = HashMap::new();
+        for (pop, language, &settlement) in pop_query.iter() {
+            let entry = majority.entry(settlement).or_insert((language.language, 0));
+            if pop.size > entry.1 {
+                *entry = (language.language, pop.size);
+            }
+        }
+        for (settlement, (language, _)) in majority.iter() {
+            if let Ok(coordinate) = settlement_query.get(settlement.0) {
+                let color = language_color(*language, &family_query);
+                let point = coordinate.point3();
+                for mut tile_map in tile_map_query.iter_mut() {
+                    let mut tile = tile_map.get_tile_mut(point, 0).unwrap();
+                    tile.color = color;
+                    let sprite_index = *tile_sprite_indices.0.get(&MapTileType::None).unwrap();
+                    tile.index = sprite_index;
+                }
+            }
+        }
+    }
+}
+
+pub struct LanguagePlugin;
+
+impl Plugin for LanguagePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_system_to_day(language_drift_system.system())
+            .add_system(language_overlay_system.system());
+    }
+}
@@ -19,6 +19,7 @@ pub mod save;
 pub mod input;
 pub mod camera;
 pub mod time;
+pub mod calendar;
 pub mod province;
 pub mod stage;
 pub mod settlement;
@@ -27,6 +28,23 @@ pub mod agent;
 pub mod gameref;
 pub mod decision;
 pub mod formula;
+pub mod language;
+pub mod culture;
+pub mod migration;
+pub mod disease;
+pub mod notification;
+pub mod polity;
+pub mod diplomacy;
+pub mod war;
+pub mod fortification;
+pub mod treasury;
+pub mod unrest;
+pub mod character;
+pub mod religion;
+pub mod technology;
+pub mod buildings;
+pub mod urbanization;
+pub mod agriculture;
 // pub mod modifier;
 
 pub mod prelude {
@@ -37,6 +55,7 @@ pub mod prelude {
         pub use crate::province::{Province, ProvinceRef};
         pub use crate::probability::individual_event;
         pub use crate::pops::{Pop, CultureRef, LanguageRef, PolityRef};
+        pub use crate::religion::ReligionRef;
         pub use crate::settlement::{SettlementRef, Districts};
         pub use crate::map::MapCoordinate;
         pub use crate::macros::GameRef;
@@ -46,10 +65,27 @@ pub mod prelude {
 }
 
 use agent::AgentPlugin;
-use bevy::{core::FixedTimestep, diagnostic::{ FrameTimeDiagnosticsPlugin, DiagnosticsPlugin }, prelude::*, sprite::SpriteSettings};
+use bevy::{diagnostic::{ FrameTimeDiagnosticsPlugin, DiagnosticsPlugin }, prelude::*, sprite::SpriteSettings};
 use bevy_tilemap::prelude::TilemapDefaultPlugins;
 use factor::FST;
 use formula::FormulaSystem;
+use language::LanguagePlugin;
+use culture::CulturePlugin;
+use migration::MigrationPlugin;
+use disease::DiseasePlugin;
+use decision::DecisionPlugin;
+use polity::PolityPlugin;
+use diplomacy::DiplomacyPlugin;
+use war::WarPlugin;
+use fortification::FortificationPlugin;
+use treasury::TreasuryPlugin;
+use unrest::UnrestPlugin;
+use character::CharacterPlugin;
+use religion::ReligionPlugin;
+use technology::TechnologyPlugin;
+use buildings::BuildingsPlugin;
+use urbanization::UrbanizationPlugin;
+use agriculture::AgriculturePlugin;
 use province::ProvincePlugin;
 use settlement::SettlementPlugin;
 // fuck yo namespace
@@ -87,23 +123,13 @@ fn main() {
             CoreStage::Update,
             DayStage::Init,
             SystemStage::parallel()
-                // .with_run_criteria(
-                //     FixedTimestep::step(0.0001)
-                //         // labels are optional. they provide a way to access the current
-                //         // FixedTimestep state from within a system
-                //         .with_label(DAY_TIMESTEP),
-                // )
         )
         .add_stage_after(
             CoreStage::Update,
             DayStage::Main,
             SystemStage::parallel()
-                .with_run_criteria(
-                    FixedTimestep::step(0.001)
-                        // labels are optional. they provide a way to access the current
-                        // FixedTimestep state from within a system
-                        .with_label(DAY_TIMESTEP),
-                )
+                // once for each day the game clock owes
+                .with_run_criteria(day_run_criteria_system.system())
         )
         // .insert_resource(SpriteSettings { frustum_culling_enabled: true })
         .add_plugins(DefaultPlugins)
@@ -117,6 +143,23 @@ fn main() {
         .add_plugin(PopPlugin)
         .add_plugin(SettlementPlugin)
         .add_plugin(ProvincePlugin)
+        .add_plugin(LanguagePlugin)
+        .add_plugin(CulturePlugin)
+        .add_plugin(MigrationPlugin)
+        .add_plugin(DiseasePlugin)
+        .add_plugin(DecisionPlugin)
+        .add_plugin(PolityPlugin)
+        .add_plugin(DiplomacyPlugin)
+        .add_plugin(WarPlugin)
+        .add_plugin(FortificationPlugin)
+        .add_plugin(TreasuryPlugin)
+        .add_plugin(UnrestPlugin)
+        .add_plugin(CharacterPlugin)
+        .add_plugin(ReligionPlugin)
+        .add_plugin(TechnologyPlugin)
+        .add_plugin(BuildingsPlugin)
+        .add_plugin(UrbanizationPlugin)
+        .add_plugin(AgriculturePlugin)
         .add_plugin(DiagnosticsPlugin::default())
         .add_plugin(FrameTimeDiagnosticsPlugin::default())
         .init_resource::<FormulaSystem<FST>>()
@@ -16,6 +16,18 @@ use crate::settlement::{Settlement, SettlementBundle, SettlementPops};
 use crate::{input::CurrentOverlayType, province::{Province, ProvinceMap}, time::Date};
 use crate::stage::{DayStage, InitStage};
 use crate::factor::{FST, FactorRef};
+use crate::language::LanguageFamily;
+use crate::decision::PlayerPolity;
+use crate::agent::ValueAgent;
+use crate::polity::{claim_settlement, found_polity, ProvinceOwner};
+use crate::fortification::Fortifications;
+use crate::unrest::Grievances;
+use crate::religion::found_religion;
+use crate::technology::Knowledge;
+use crate::buildings::Buildings;
+use crate::urbanization::{Occupations, SettlementTier};
+use crate::agriculture::{farm_workers, Climate, Fields};
+use crate::time::DeferredCommands;
 
 use crate::{SettlementRef, pops::*};
 use crate::constant::*;
@@ -85,11 +97,21 @@ impl MapCoordinate {
         astar::astar(
             &self,
             |coord| coord.neighbors_iter().map(|coord| (coord, 1)),
-            |coord| coord.distance(self),
-            |coord| *coord == self,
+            |coord| coord.distance(other),
+            |coord| *coord == other,
         ).unwrap().0
     }
 
+    // like path_to, but only stepping onto coordinates that pass; these must be finite or it'll never give up
+    pub fn path_to_passable<F>(self, other: MapCoordinate, passable: F) -> Option<Vec<MapCoordinate>> where F: Fn(&MapCoordinate) -> bool {
+        astar::astar(
+            &self,
+            |coord| coord.neighbors_iter().filter(|n| passable(n)).map(|n| (n, 1)).collect::<Vec<_>>(),
+            |coord| coord.distance(other),
+            |coord| *coord == other,
+        ).map(|(path, _)| path)
+    }
+
     pub fn hex_side(self, other: MapCoordinate) -> HexSide {
         HexSide::N
     }
@@ -151,7 +173,7 @@ impl MapCoordinate {
             neighbors: self.neighbors_in_radius(radius),
         }
     }
-    fn point3(&self) -> Point3 {
+    pub fn point3(&self) -> Point3 {
         Point3::new(self.x as i32, self.y as i32, 0)
     }
 }
@@ -324,34 +346,44 @@ impl Command for SpawnCultureCommand {
     fn write(self: Box<Self>, world: &mut World) {
         let language = Language::new();
         let name = language.generate_name(2);
-        let mut polity_name = language.generate_name(2);
+        let founded = world.get_resource::<CurrentDate>().unwrap().date;
         let language_ent = {
             let mut language_builder = world.spawn();
             language_builder
-                .insert(language);
+                .insert(language)
+                .insert(LanguageFamily::root(founded));
             language_builder.id()
         };
         let culture_ent = {
             let mut culture_builder = world.spawn();
             culture_builder
-                .insert(Culture {
-                    name,
-                });
+                .insert(Culture::new(name));
             culture_builder.id()
         };
-        let mut polity_ent = {
-            world
-                .spawn()
-                .insert(Polity { name: polity_name })
-                .id()
+        // every people starts out with gods of their own, worshipped where they first lived
+        let religion = found_religion(world, LanguageRef(language_ent), Some(self.province));
+        // people springing up inside someone's land answer to them, everyone else starts their own polity
+        let polity = match self.province.try_get::<ProvinceOwner>(world) {
+            Some(owner) => owner.0,
+            None => {
+                let polity = found_polity(world, LanguageRef(language_ent));
+                // the player takes the first polity to appear
+                if let Some(mut player) = world.get_resource_mut::<PlayerPolity>() {
+                    if player.0.is_none() {
+                        player.0 = Some(polity);
+                    }
+                }
+                polity
+            },
         };
-        let polity = PolityRef(polity_ent);
         let spawn_pop_command = SpawnSettlementCommand {
             province: self.province,
             language: LanguageRef(language_ent),
             culture: CultureRef(culture_ent),
+            religion,
             polity,
             size: 100,
+            ages: None,
         };
 
         Box::new(spawn_pop_command).write(world);
@@ -362,16 +394,18 @@ pub struct SpawnSettlementCommand {
     pub province: ProvinceRef,
     pub language: LanguageRef,
     pub culture: CultureRef,
+    pub religion: ReligionRef,
     pub size: isize,
     pub polity: PolityRef,
+    pub ages: Option<AgeCohorts>,
 }
 
-impl Command for SpawnSettlementCommand {
-    fn write(self: Box<Self>, world: &mut World) {
-        // TODO: only spawn polity if not in parent admin zone
-        if let Some(settlement) = self.province.try_get::<Settlement>(world) {
+impl SpawnSettlementCommand {
+    // the founding pop is the first of the new settlement's pops, None if the province is taken
+    pub fn spawn(self, world: &mut World) -> Option<SettlementRef> {
+        if let Some(settlement) = self.province.try_get::<SettlementRef>(world) {
             println!("someone already here! {:?}", settlement);
-            return;
+            return None;
         }
         let name = self.language.get::<Language>(world).generate_name(2);
         let coordinate = *self.province.get::<MapCoordinate>(world);
@@ -386,6 +420,11 @@ impl Command for SpawnSettlementCommand {
                     province: self.province,
                     polity: self.polity,
                     coordinate,
+                    fortifications: Fortifications::default(),
+                    grievances: Grievances::default(),
+                    knowledge: Knowledge::default(),
+                    buildings: Buildings::default(),
+                    tier: SettlementTier::default(),
                 })
                 .id()
         });
@@ -404,14 +443,24 @@ impl Command for SpawnSettlementCommand {
             .get_entity_mut(self.province.entity())
             .unwrap()
             .insert(settlement);
-        Box::new(SpawnPopCommand {
+        claim_settlement(world, settlement);
+        SpawnPopCommand {
             province: self.province,
             settlement,
             language: self.language,
             culture: self.culture,
+            religion: self.religion,
             size: self.size,
             polity: self.polity,
-        }).write(world);
+            ages: self.ages,
+        }.spawn(world);
+        Some(settlement)
+    }
+}
+
+impl Command for SpawnSettlementCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        self.spawn(world);
     }
 }
 
@@ -420,34 +469,66 @@ pub struct SpawnPopCommand {
     pub settlement: SettlementRef,
     pub language: LanguageRef,
     pub culture: CultureRef,
+    pub religion: ReligionRef,
     pub size: isize,
     pub polity: PolityRef,
-}
-
-impl Command for SpawnPopCommand {
-    fn write(self: Box<Self>, world: &mut World) {
+    // people coming from an existing pop keep their ages, otherwise they're made up from size
+    pub ages: Option<AgeCohorts>,
+}
+
+impl SpawnPopCommand {
+    pub fn spawn(self, world: &mut World) -> PopRef {
+        let climate = Climate::of(*self.settlement.get::<MapCoordinate>(world));
+        let crop = climate.staple();
+        let (ages, storage, fields) = match self.ages {
+            Some(ages) => (ages, GoodStorage(HashMap::new()), Fields::new(climate)),
+            None => {
+                let ages = AgeCohorts::with_size(self.size);
+                let workers = farm_workers(&ages, &Occupations::default());
+                (
+                    ages,
+                    // enough grain to get through to the first harvest
+                    GoodStorage(vec![
+                        (crop, self.size as f32 * crop.max_consumed_monthly_per_capita() * 12.0),
+                    ].into_iter().collect()),
+                    Fields::sown(climate, workers),
+                )
+            },
+        };
         let pop_ent = {
             let bundle = {
                 PopBundle {
-                    base: Pop { size: self.size },
+                    base: Pop { size: ages.size() },
                     province: self.province,
                     culture: self.culture,
+                    religion: self.religion,
                     settlement: self.settlement,
                     polity: self.polity,
                     language: PopLanguage {
                         language: self.language,
                         drift: 0.0,
                     },
-                    storage: GoodStorage(HashMap::new()),
-                    kid_buffer: KidBuffer(VecDeque::new()),
+                    storage,
+                    ages,
+                    hunger: Hunger::default(),
+                    diseases: PopDiseases::default(),
+                    occupations: Occupations::default(),
                 }
             };
             world.spawn()
                 .insert_bundle(bundle)
-                .insert(FarmingPop { good: GoodType::Wheat })
+                .insert(FarmingPop { good: crop })
+                .insert(fields)
                 .id()
         };
         self.settlement.add_pop(world, PopRef(pop_ent));
+        PopRef(pop_ent)
+    }
+}
+
+impl Command for SpawnPopCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        self.spawn(world);
     }
 }
 
@@ -548,6 +629,8 @@ fn build_world(
     mut query: Query<&mut Tilemap>,
     mut hex_map: ResMut<HexMap>,
     tile_sprite_indices: Res<TileSpriteIndices>,
+    mut date: ResMut<CurrentDate>,
+    mut deferred: ResMut<DeferredCommands>,
 ) {
     if load_map.0 == None {
         return;
@@ -557,7 +640,15 @@ fn build_world(
         let mut file = File::open(save_file_name).unwrap();
         let mut contents = String::new();
         file.read_to_string(&mut contents).unwrap();
-        let entities: Vec<MapEntitySaveData> = serde_json::from_str(&contents).unwrap();
+        // a saved game picks up on its date with its scheduled work, a fresh map starts over
+        let entities = match serde_json::from_str::<GameSaveData>(&contents) {
+            Ok(save) => {
+                date.date = save.date;
+                deferred.load(save.schedule);
+                save.entities
+            },
+            Err(_) => serde_json::from_str::<Vec<MapEntitySaveData>>(&contents).unwrap(),
+        };
         // let mut tiles = Vec::new();
         for esd in &entities {
             let mut ecmds = commands.spawn();
@@ -764,33 +855,6 @@ pub fn pop_overlay_system(
     }
 }
 
-pub fn polity_overlay_system(
-    mut frame: Local<isize>,
-    mut overlay_command: ResMut<OverlayCommand>,
-    tile_sprite_indices: Res<TileSpriteIndices>,
-    tile_coord_query: Query<&MapCoordinate, With<MapTile>>,
-    polity_query: Query<(&PolityRef, &MapCoordinate)>,
-    current_overlay: Res<CurrentOverlayType>,
-    date: Res<CurrentDate>,
-    mut tile_map_query: Query<&mut Tilemap>,
-) {
-    *frame += 1;
-    if *frame % 20 == 0 && *current_overlay == CurrentOverlayType::Polity {
-        // println!("polity overlay change");
-        for (&polity, &coordinate) in polity_query.iter() {
-            let color = polity.color();
-            // println!("polity overlay: {:?} {:?} {:?}", polity, coordinate, color);
-            let point = coordinate.point3();
-            for mut tile_map in tile_map_query.iter_mut() {
-                let mut tile = tile_map.get_tile_mut(point, 0).unwrap();
-                tile.color = color;
-                let sprite_index = *tile_sprite_indices.0.get(&MapTileType::None).unwrap();
-                tile.index = sprite_index;
-            }
-        }
-    }
-}
-
 pub struct MapPlugin;
 
 impl Plugin for MapPlugin {
@@ -809,7 +873,6 @@ impl Plugin for MapPlugin {
             .add_system(load_tile_map_system.system())
             .add_system(build_world.system())
             .add_system(pop_overlay_system.system())
-            .add_system(polity_overlay_system.system())
             .add_system(show_overlay_system.system())
             .add_system_to_stage(DayStage::Main, map_tile_type_changed_system.system())
             .add_system(position_translation.system());
@@ -0,0 +1,508 @@
+use bevy::{ecs::system::Command, prelude::*};
+use serde::{Serialize, Deserialize};
+
+use crate::prelude::*;
+use crate::factor::FactorRef;
+use crate::map::{MapTile, MapTileType, SpawnSettlementCommand};
+use crate::disease::PopDiseases;
+use crate::pops::{AgeCohorts, Culture, GoodStorage, PopDieCommand, PopLanguage};
+use crate::probability::logistic;
+use crate::province::ProvinceMap;
+use crate::save::{CommandRegistryBuilder, SaveableCommand};
+use crate::settlement::{Settlement, SettlementPops};
+use crate::urbanization::SettlementTier;
+use crate::agriculture::Fields;
+use crate::time::DeferredCommands;
+
+// how far away migrants will look for a new home
+pub const MIGRATION_RADIUS: isize = 3;
+// how long it takes a group of migrants to cross one hex
+pub const MIGRATION_DAYS_PER_HEX: usize = 10;
+// how many a fresh settlement is assumed to support before anyone lives there
+const NEW_SETTLEMENT_CAPACITY: f32 = 100.0;
+// migrants turned away somewhere only try elsewhere if it looks at least this good
+const RESETTLE_THRESHOLD: f32 = 0.0;
+// how much more a settlement one tier up looks to people from the countryside
+const URBAN_DRAW: f32 = 0.5;
+
+// how attractive a province looks to migrants, higher is better
+pub fn migration_destination_value(
+    world: &World,
+    culture: CultureRef,
+    polity: PolityRef,
+    origin: MapCoordinate,
+    dest: ProvinceRef,
+    migrating: isize,
+) -> f32 {
+    let distance = origin.distance(*dest.get::<MapCoordinate>(world));
+    let mut value = culture.get::<Culture>(world).province_preference(world, dest) - 0.3 * distance as f32;
+    if let Some(&settlement) = dest.try_get::<SettlementRef>(world) {
+        let carrying_capacity = settlement.get_factor(world, FactorType::SettlementCarryingCapacity);
+        let free = carrying_capacity - settlement.get::<Settlement>(world).population as f32;
+        value += (free / migrating.max(1) as f32).max(-2.0).min(2.0);
+        match settlement.majority_culture(world) {
+            Some(majority) if majority == culture => value += 1.0,
+            Some(_) => value -= 2.0 * culture.get::<Culture>(world).foreign_aversion(),
+            None => {},
+        }
+        if *settlement.get::<PolityRef>(world) == polity {
+            value += 0.5;
+        } else {
+            value -= 1.0;
+        }
+        // towns and cities pull people in from smaller places around them
+        let origin_tier = world
+            .get_resource::<ProvinceMap>()
+            .unwrap()
+            .0
+            .get(&origin)
+            .and_then(|province| province.try_get::<SettlementRef>(world))
+            .and_then(|origin| origin.try_get::<SettlementTier>(world))
+            .cloned()
+            .unwrap_or_default();
+        let tier = settlement.try_get::<SettlementTier>(world).cloned().unwrap_or_default();
+        value += URBAN_DRAW * (tier as isize - origin_tier as isize).max(0) as f32;
+    } else {
+        // clearing new land is harder than moving in with someone
+        value += (NEW_SETTLEMENT_CAPACITY / migrating.max(1) as f32).min(2.0) - 0.5;
+    }
+    value
+}
+
+// the best place to go within MIGRATION_RADIUS of `origin`, skipping `exclude`
+fn best_destination(
+    world: &World,
+    culture: CultureRef,
+    polity: PolityRef,
+    origin: MapCoordinate,
+    exclude: &[MapCoordinate],
+    migrating: isize,
+) -> Option<(ProvinceRef, f32)> {
+    let province_map = world.get_resource::<ProvinceMap>().unwrap();
+    origin
+        .neighbors_in_radius(MIGRATION_RADIUS)
+        .into_iter()
+        .filter(|c| !exclude.contains(c))
+        // missed the map?
+        .filter_map(|c| province_map.0.get(&c).cloned())
+        .filter(|p| p.get::<MapTile>(world).tile_type.inhabitable())
+        .map(|p| (p, migration_destination_value(world, culture, polity, origin, p, migrating)))
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+}
+
+pub fn land_path(world: &World, from: MapCoordinate, to: MapCoordinate) -> Option<Vec<MapCoordinate>> {
+    let province_map = world.get_resource::<ProvinceMap>().unwrap();
+    from.path_to_passable(to, |c| {
+        province_map.0.get(c)
+            .map(|p| p.get::<MapTile>(world).tile_type != MapTileType::Water)
+            .unwrap_or(false)
+    })
+}
+
+// a group of people on the road, with their ages and what they could carry
+pub struct Migrants {
+    pub origin: SettlementRef,
+    pub culture: CultureRef,
+    pub language: LanguageRef,
+    pub religion: ReligionRef,
+    pub polity: PolityRef,
+    pub dest: ProvinceRef,
+    // where they meant to go when they set out, they'll join whoever's there on arrival anyway
+    pub settlement: Option<SettlementRef>,
+    pub path: Vec<MapCoordinate>,
+    pub step: usize,
+    pub departed: Date,
+    pub arrival: Date,
+    pub returning: bool,
+}
+
+impl Migrants {
+    // set off along a new path from wherever they are now
+    pub fn set_route(&mut self, path: Vec<MapCoordinate>, today: Date) {
+        self.arrival = today.days_after(MIGRATION_DAYS_PER_HEX * (path.len().max(1) - 1));
+        self.departed = today;
+        self.path = path;
+        self.step = 0;
+    }
+}
+
+#[derive(Bundle)]
+pub struct MigrantsBundle {
+    pub migrants: Migrants,
+    pub ages: AgeCohorts,
+    pub storage: GoodStorage,
+    pub diseases: PopDiseases,
+    pub coordinate: MapCoordinate,
+}
+
+pub struct MigrantsMaterial(pub Handle<ColorMaterial>);
+
+// split `amount` people off a pop and put them on the road to `dest`
+pub fn send_migrants(world: &mut World, pop: PopRef, amount: isize, dest: ProvinceRef, path: Vec<MapCoordinate>) -> Entity {
+    let today = world.get_resource::<CurrentDate>().unwrap().date;
+    let origin = *pop.get::<SettlementRef>(world);
+    let culture = *pop.get::<CultureRef>(world);
+    let language = pop.get::<PopLanguage>(world).language;
+    let religion = *pop.get::<ReligionRef>(world);
+    let polity = *pop.get::<PolityRef>(world);
+    let settlement = dest.try_get::<SettlementRef>(world).cloned();
+    let start = path[0];
+    // the sick travel too, and bring it wherever they end up
+    let share = amount as f32 / pop.get::<Pop>(world).size.max(1) as f32;
+    let diseases = pop.get_mut::<PopDiseases>(world).split(share);
+    let (ages, storage, fields) = pop.take_people(world, amount);
+    let mut migrants = Migrants {
+        origin,
+        culture,
+        language,
+        religion,
+        polity,
+        dest,
+        settlement,
+        path: Vec::new(),
+        step: 0,
+        departed: today,
+        arrival: today,
+        returning: false,
+    };
+    migrants.set_route(path, today);
+    let material = world.get_resource::<MigrantsMaterial>().map(|m| m.0.clone());
+    let mut group = world.spawn();
+    group.insert_bundle(MigrantsBundle {
+        migrants,
+        ages,
+        storage,
+        diseases,
+        coordinate: start,
+    });
+    // their share of the crop waiting back home goes with them
+    if let Some(fields) = fields {
+        group.insert(fields);
+    }
+    // headless worlds (tests) don't have anything to draw with
+    if let Some(material) = material {
+        group.insert_bundle(SpriteBundle {
+            material,
+            sprite: Sprite::new(Vec2::new(8.0, 8.0)),
+            transform: Transform::from_xyz(0.0, 0.0, 2.0),
+            ..Default::default()
+        });
+    }
+    let group = group.id();
+    schedule_arrival(world, group);
+    if pop.get::<Pop>(world).size <= 0 {
+        Box::new(PopDieCommand(pop)).write(world);
+    }
+    group
+}
+
+pub struct PopSeekMigrationCommand {
+    pub pop: PopRef,
+    pub pressure: f32,
+}
+
+impl Command for PopSeekMigrationCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let pop_size = self.pop.get::<Pop>(world).size;
+        let migrating = pop_size / 5;
+        if migrating <= 0 {
+            return;
+        }
+        let culture = *self.pop.get::<CultureRef>(world);
+        let polity = *self.pop.get::<PolityRef>(world);
+        let coordinate = *self
+            .pop
+            .accessor(world)
+            .get_ref::<ProvinceRef>()
+            .get::<MapCoordinate>();
+        let (dest, dest_value) = match best_destination(world, culture, polity, coordinate, &[coordinate], migrating) {
+            Some(best) => best,
+            None => return,
+        };
+        let target_value = -2.0 + self.pressure + dest_value;
+        if !individual_event(logistic(target_value)) {
+            return;
+        }
+        let path = match land_path(world, coordinate, *dest.get::<MapCoordinate>(world)) {
+            Some(path) => path,
+            None => return,
+        };
+        // println!("really migrate?? {:?}", self.pop);
+        self.pop.set_factor(world, FactorType::PopPressure, 0.0);
+        send_migrants(world, self.pop, migrating, dest, path);
+    }
+}
+
+fn migrant_travel_system(
+    date: Res<CurrentDate>,
+    mut migrants_query: Query<(&mut Migrants, &mut MapCoordinate)>,
+) {
+    if !date.is_day {
+        return;
+    }
+    for (mut migrants, mut coordinate) in migrants_query.iter_mut() {
+        let elapsed = date.date.abs_day().saturating_sub(migrants.departed.abs_day());
+        let step = (elapsed / MIGRATION_DAYS_PER_HEX).min(migrants.path.len().max(1) - 1);
+        if step != migrants.step {
+            migrants.step = step;
+            *coordinate = migrants.path[step];
+        }
+    }
+}
+
+fn schedule_arrival(world: &mut World, group: Entity) {
+    let arrival = world.get::<Migrants>(group).unwrap().arrival;
+    world
+        .get_resource_mut::<DeferredCommands>()
+        .unwrap()
+        .add_saved(arrival, &MigrantsDueCommand { group: group.to_bits(), arrival });
+}
+
+// set up for the day migrants are due when they set out, it comes to nothing if they've been sent elsewhere since
+#[derive(Serialize, Deserialize)]
+pub struct MigrantsDueCommand {
+    pub group: u64,
+    pub arrival: Date,
+}
+
+impl SaveableCommand for MigrantsDueCommand {
+    const NAME: &'static str = "migrants_due";
+}
+
+impl Command for MigrantsDueCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let group = Entity::from_bits(self.group);
+        if world.get::<Migrants>(group).map(|migrants| migrants.arrival) == Some(self.arrival) {
+            Box::new(MigrantsArriveCommand(group)).write(world);
+        }
+    }
+}
+
+// migrants reached the end of their path: join a settlement if one is there, otherwise found one
+pub struct MigrantsArriveCommand(pub Entity);
+
+impl MigrantsArriveCommand {
+    fn turned_away(world: &World, migrants: &Migrants, size: isize) -> bool {
+        if !migrants.dest.get::<MapTile>(world).tile_type.inhabitable() {
+            return true;
+        }
+        // strangers aren't let into a settlement that's already full
+        if let Some(&settlement) = migrants.dest.try_get::<SettlementRef>(world) {
+            let carrying_capacity = settlement.get_factor(world, FactorType::SettlementCarryingCapacity);
+            let population = settlement.get::<Settlement>(world).population;
+            let foreign = *settlement.get::<PolityRef>(world) != migrants.polity;
+            return foreign && (population + size) as f32 > carrying_capacity;
+        }
+        false
+    }
+
+    // try somewhere close by, and if there's nowhere go home
+    fn reroute(&self, world: &mut World) {
+        let today = world.get_resource::<CurrentDate>().unwrap().date;
+        let here = *world.get::<MapCoordinate>(self.0).unwrap();
+        let size = world.get::<AgeCohorts>(self.0).unwrap().size();
+        let (culture, polity, failed, origin) = {
+            let migrants = world.get::<Migrants>(self.0).unwrap();
+            (migrants.culture, migrants.polity, *migrants.dest.get::<MapCoordinate>(world), migrants.origin)
+        };
+        let alternative = best_destination(world, culture, polity, here, &[failed], size)
+            .filter(|(_, value)| *value > RESETTLE_THRESHOLD)
+            .and_then(|(dest, _)| {
+                land_path(world, here, *dest.get::<MapCoordinate>(world)).map(|path| (dest, path))
+            });
+        if let Some((dest, path)) = alternative {
+            let settlement = dest.try_get::<SettlementRef>(world).cloned();
+            let mut migrants = world.get_mut::<Migrants>(self.0).unwrap();
+            migrants.dest = dest;
+            migrants.settlement = settlement;
+            migrants.set_route(path, today);
+            schedule_arrival(world, self.0);
+            return;
+        }
+        let home = *origin.get::<ProvinceRef>(world);
+        match land_path(world, here, *origin.get::<MapCoordinate>(world)) {
+            Some(path) => {
+                let mut migrants = world.get_mut::<Migrants>(self.0).unwrap();
+                migrants.dest = home;
+                migrants.settlement = Some(origin);
+                migrants.returning = true;
+                migrants.set_route(path, today);
+                schedule_arrival(world, self.0);
+            },
+            // cut off from home entirely, give up and walk back in
+            None => self.settle(world, origin),
+        }
+    }
+
+    fn settle(&self, world: &mut World, settlement: SettlementRef) {
+        let (culture, language, religion) = {
+            let migrants = world.get::<Migrants>(self.0).unwrap();
+            (migrants.culture, migrants.language, migrants.religion)
+        };
+        let mut group = world.entity_mut(self.0);
+        let ages = group.remove::<AgeCohorts>().unwrap();
+        let storage = group.remove::<GoodStorage>().unwrap();
+        let diseases = group.remove::<PopDiseases>().unwrap();
+        let fields = group.remove::<Fields>();
+        let pop = settlement.settle_people(world, culture, language, religion, ages, storage, fields);
+        pop.get_mut::<PopDiseases>(world).merge(diseases);
+        world.despawn(self.0);
+    }
+}
+
+impl Command for MigrantsArriveCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let (returning, origin, dest) = match world.get::<Migrants>(self.0) {
+            Some(migrants) => (migrants.returning, migrants.origin, migrants.dest),
+            None => return,
+        };
+        if returning {
+            self.settle(world, origin);
+            return;
+        }
+        let size = world.get::<AgeCohorts>(self.0).unwrap().size();
+        if Self::turned_away(world, world.get::<Migrants>(self.0).unwrap(), size) {
+            self.reroute(world);
+            return;
+        }
+        if let Some(&settlement) = dest.try_get::<SettlementRef>(world) {
+            self.settle(world, settlement);
+            return;
+        }
+        let (culture, language, religion, polity) = {
+            let migrants = world.get::<Migrants>(self.0).unwrap();
+            (migrants.culture, migrants.language, migrants.religion, migrants.polity)
+        };
+        let mut group = world.entity_mut(self.0);
+        let ages = group.remove::<AgeCohorts>().unwrap();
+        let storage = group.remove::<GoodStorage>().unwrap();
+        let diseases = group.remove::<PopDiseases>().unwrap();
+        let fields = group.remove::<Fields>();
+        let founded = SpawnSettlementCommand {
+            province: dest,
+            language,
+            culture,
+            religion,
+            polity,
+            size: ages.size(),
+            ages: Some(ages),
+        }.spawn(world);
+        match founded {
+            Some(settlement) => {
+                let founders = settlement.get::<SettlementPops>(world).0[0];
+                founders.get_mut::<GoodStorage>(world).merge(storage);
+                if let Some(fields) = fields {
+                    founders.get_mut::<Fields>(world).merge(fields);
+                }
+                founders.get_mut::<PopDiseases>(world).merge(diseases);
+                world.despawn(self.0);
+            },
+            None => unreachable!("no settlement at {:?} but couldn't found one", dest),
+        }
+    }
+}
+
+fn setup_migrants_material(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    commands.insert_resource(MigrantsMaterial(materials.add(Color::rgb(0.9, 0.6, 0.1).into())));
+}
+
+pub struct MigrationPlugin;
+
+impl Plugin for MigrationPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_startup_system(setup_migrants_material.system())
+            .add_system_to_day(migrant_travel_system.system())
+            .register_command::<MigrantsDueCommand>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::factor::FST;
+    use crate::formula::FormulaSystem;
+    use crate::language::LanguageFamily;
+    use crate::pops::{total_population, Language, Polity};
+    use crate::province::{Province, ResetProvinceMap};
+    use crate::religion::Religion;
+    use crate::time::Date;
+
+    fn province(world: &mut World, x: isize, y: isize, tile_type: MapTileType) -> ProvinceRef {
+        ProvinceRef(
+            world
+                .spawn()
+                .insert(MapCoordinate { x, y })
+                .insert(MapTile { tile_type })
+                .insert(Province {
+                    total_population: 0,
+                    fertility: 30.0,
+                })
+                .id()
+        )
+    }
+
+    fn setup() -> (World, PopRef, ProvinceRef, ProvinceRef) {
+        let mut world = World::default();
+        world.insert_resource(CurrentDate {
+            date: Date { day: 1, month: 1, year: 1 },
+            ..Default::default()
+        });
+        world.insert_resource(FormulaSystem::<FST>::default());
+        world.insert_resource(DeferredCommands::default());
+        world.insert_resource(ProvinceMap(HashMap::new()));
+        let home = province(&mut world, 0, 0, MapTileType::Plains);
+        let plains = province(&mut world, 1, 0, MapTileType::Plains);
+        let water = province(&mut world, 2, 0, MapTileType::Water);
+        Box::new(ResetProvinceMap).write(&mut world);
+        let language = LanguageRef(world.spawn().insert(Language::new()).insert(LanguageFamily::root(Date::default())).id());
+        let culture = CultureRef(world.spawn().insert(Culture::new("test".to_owned())).id());
+        let religion = ReligionRef(world.spawn().insert(Religion::new("test".to_owned())).id());
+        let polity = PolityRef(world.spawn().insert(Polity { name: "test".to_owned() }).id());
+        let settlement = SpawnSettlementCommand {
+            province: home,
+            language,
+            culture,
+            religion,
+            size: 500,
+            polity,
+            ages: None,
+        }.spawn(&mut world).unwrap();
+        let pop = settlement.get::<SettlementPops>(&world).0[0];
+        (world, pop, plains, water)
+    }
+
+    #[test]
+    fn migration_conserves_population() {
+        let (mut world, pop, plains, water) = setup();
+        let total = total_population(&mut world);
+        let here = MapCoordinate { x: 0, y: 0 };
+
+        // found a new settlement next door
+        let group = send_migrants(&mut world, pop, 100, plains, vec![here, MapCoordinate { x: 1, y: 0 }]);
+        assert_eq!(total_population(&mut world), total);
+        Box::new(MigrantsArriveCommand(group)).write(&mut world);
+        assert!(plains.try_get::<SettlementRef>(&world).is_some());
+        assert_eq!(total_population(&mut world), total);
+
+        // join it
+        let group = send_migrants(&mut world, pop, 50, plains, vec![here, MapCoordinate { x: 1, y: 0 }]);
+        Box::new(MigrantsArriveCommand(group)).write(&mut world);
+        assert!(world.get_entity(group).is_none());
+        assert_eq!(total_population(&mut world), total);
+
+        // turned away by the sea, they have to go somewhere else
+        let group = send_migrants(&mut world, pop, 50, water, vec![here]);
+        Box::new(MigrantsArriveCommand(group)).write(&mut world);
+        assert!(world.get_entity(group).is_some());
+        assert_eq!(total_population(&mut world), total);
+        Box::new(MigrantsArriveCommand(group)).write(&mut world);
+        assert!(world.get_entity(group).is_none());
+        assert_eq!(total_population(&mut world), total);
+    }
+}
@@ -0,0 +1,47 @@
+use std::collections::VecDeque;
+
+use bevy::{ecs::system::Command, prelude::*};
+use serde::{Serialize, Deserialize};
+
+use crate::prelude::*;
+use crate::save::SaveableCommand;
+
+// how many of the most recent notifications are kept around to show
+const MAX_NOTIFICATIONS: usize = 5;
+
+// things the player should hear about, newest first
+#[derive(Default)]
+pub struct Notifications(pub VecDeque<(Date, String)>);
+
+impl Notifications {
+    pub fn push(&mut self, date: Date, text: String) {
+        self.0.push_front((date, text));
+        self.0.truncate(MAX_NOTIFICATIONS);
+    }
+
+    pub fn latest(&self) -> Option<&(Date, String)> {
+        self.0.front()
+    }
+}
+
+// for commands, which only have the world to work with
+pub fn notify(world: &mut World, text: String) {
+    let date = world.get_resource::<CurrentDate>().unwrap().date;
+    if let Some(mut notifications) = world.get_resource_mut::<Notifications>() {
+        notifications.push(date, text);
+    }
+}
+
+// a message that can wait in the schedule for its day
+#[derive(Serialize, Deserialize)]
+pub struct NotifyCommand(pub String);
+
+impl Command for NotifyCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        notify(world, self.0);
+    }
+}
+
+impl SaveableCommand for NotifyCommand {
+    const NAME: &'static str = "notify";
+}
@@ -0,0 +1,613 @@
+use std::collections::HashMap;
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::{ecs::system::Command, prelude::*};
+use bevy_tilemap::prelude::*;
+
+use crate::prelude::*;
+use crate::agent::ValueAgent;
+use crate::decision::{EventQueue, PlayerPolity};
+use crate::diplomacy::Relations;
+use crate::factor::{FactorRef, FST};
+use crate::formula::FormulaSystem;
+use crate::input::CurrentOverlayType;
+use crate::map::{MapTile, MapTileType, TileSpriteIndices};
+use crate::migration::Migrants;
+use crate::notification::notify;
+use crate::pops::{Language, Polity, PopLanguage};
+use crate::province::ProvinceMap;
+use crate::settlement::{Settlement, SettlementPops};
+use crate::treasury::{Ledger, Taxation, Treasury};
+use crate::urbanization::SettlementTier;
+use crate::war::leave_wars;
+
+// yearly chance per hex past the reach of its capital that a settlement breaks away
+const SECESSION_CHANCE: f32 = 0.01;
+// yearly chance a village next to a bigger polity bends the knee
+const VASSALIZE_CHANCE: f32 = 0.05;
+// yearly chance an overlord swallows a vassal whole
+const ANNEX_CHANCE: f32 = 0.02;
+
+// the polity a province belongs to, unclaimed provinces don't have one
+pub struct ProvinceOwner(pub PolityRef);
+
+// the settlement a polity is run from, lives on the polity entity
+pub struct PolityCapital(pub SettlementRef);
+
+impl PolityRef {
+    pub fn name<'a>(&self, world: &'a World) -> &'a String {
+        &self.get::<Polity>(world).name
+    }
+
+    pub fn population(&self, world: &World) -> isize {
+        self.settlements(world).iter().map(|s| s.get::<Settlement>(world).population).sum()
+    }
+
+    pub fn capital(&self, world: &World) -> Option<SettlementRef> {
+        self.try_get::<PolityCapital>(world).map(|capital| capital.0)
+    }
+
+    pub fn provinces(&self, world: &World) -> Vec<ProvinceRef> {
+        let province_map = world.get_resource::<ProvinceMap>().unwrap();
+        province_map
+            .0
+            .values()
+            .filter(|province| province.try_get::<ProvinceOwner>(world).map(|owner| owner.0) == Some(*self))
+            .cloned()
+            .collect()
+    }
+
+    pub fn settlements(&self, world: &World) -> Vec<SettlementRef> {
+        let province_map = world.get_resource::<ProvinceMap>().unwrap();
+        province_map
+            .0
+            .values()
+            .filter_map(|province| province.try_get::<SettlementRef>(world))
+            .filter(|settlement| *settlement.get::<PolityRef>(world) == *self)
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AdminTier {
+    Village,
+    Chiefdom,
+    Kingdom,
+}
+
+impl AdminTier {
+    pub fn of(settlements: usize, population: isize) -> Self {
+        if settlements >= 10 || population >= 10000 {
+            AdminTier::Kingdom
+        } else if settlements >= 3 || population >= 2000 {
+            AdminTier::Chiefdom
+        } else {
+            AdminTier::Village
+        }
+    }
+
+    // how far from the capital it can keep settlements in line
+    pub fn reach(&self) -> isize {
+        match self {
+            AdminTier::Village => 3,
+            AdminTier::Chiefdom => 6,
+            AdminTier::Kingdom => 12,
+        }
+    }
+}
+
+// where a polity sits among the others, lives on the polity entity
+#[derive(Debug, Clone)]
+pub struct PolityHierarchy {
+    pub tier: AdminTier,
+    pub overlord: Option<PolityRef>,
+    pub vassals: Vec<PolityRef>,
+}
+
+impl Default for PolityHierarchy {
+    fn default() -> Self {
+        Self {
+            tier: AdminTier::Village,
+            overlord: None,
+            vassals: Vec::new(),
+        }
+    }
+}
+
+impl PolityRef {
+    pub fn tier(&self, world: &World) -> AdminTier {
+        self.try_get::<PolityHierarchy>(world).map(|h| h.tier).unwrap_or(AdminTier::Village)
+    }
+
+    pub fn overlord(&self, world: &World) -> Option<PolityRef> {
+        self.try_get::<PolityHierarchy>(world).and_then(|h| h.overlord)
+    }
+
+    // the polity at the top of this one's chain of overlords
+    pub fn realm(&self, world: &World) -> PolityRef {
+        let mut current = *self;
+        while let Some(overlord) = current.overlord(world) {
+            current = overlord;
+        }
+        current
+    }
+}
+
+pub fn found_polity(world: &mut World, language: LanguageRef) -> PolityRef {
+    let name = language.get::<Language>(world).generate_name(2);
+    PolityRef(
+        world
+            .spawn()
+            .insert(Polity { name })
+            .insert(ValueAgent::random())
+            .insert(PolityHierarchy::default())
+            .insert(Taxation::default())
+            .insert(Treasury::default())
+            .insert(Ledger::default())
+            .id()
+    )
+}
+
+// hand a settlement, its people and its land over to another polity
+pub fn transfer_settlement(world: &mut World, settlement: SettlementRef, to: PolityRef) {
+    let from = *settlement.get::<PolityRef>(world);
+    *settlement.get_mut::<PolityRef>(world) = to;
+    let pops = settlement.get::<SettlementPops>(world).0.clone();
+    for pop in pops.into_iter() {
+        *pop.get_mut::<PolityRef>(world) = to;
+    }
+    if from.capital(world) == Some(settlement) {
+        world.entity_mut(from.entity()).remove::<PolityCapital>();
+    }
+    claim_settlement(world, settlement);
+}
+
+// a new settlement takes its province for its polity, and becomes the capital of a polity without one
+pub fn claim_settlement(world: &mut World, settlement: SettlementRef) {
+    let polity = *settlement.get::<PolityRef>(world);
+    let province = *settlement.get::<ProvinceRef>(world);
+    world.entity_mut(province.entity()).insert(ProvinceOwner(polity));
+    if polity.capital(world).is_none() {
+        world.entity_mut(polity.entity()).insert(PolityCapital(settlement));
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BorderEdge {
+    // the owned side of the edge
+    pub coordinate: MapCoordinate,
+    pub neighbor: MapCoordinate,
+    pub owner: PolityRef,
+    // None when the other side is unclaimed
+    pub other: Option<PolityRef>,
+}
+
+// hex edges where ownership changes, each edge once
+#[derive(Default)]
+pub struct PolityBorders(pub Vec<BorderEdge>);
+
+impl PolityBorders {
+    pub fn of(&self, polity: PolityRef) -> impl Iterator<Item = &BorderEdge> {
+        self.0.iter().filter(move |edge| edge.owner == polity || edge.other == Some(polity))
+    }
+
+    // polities sharing a border with this one
+    pub fn neighbors(&self, polity: PolityRef) -> Vec<PolityRef> {
+        let mut neighbors = Vec::new();
+        for edge in self.of(polity) {
+            let other = if edge.owner == polity { edge.other } else { Some(edge.owner) };
+            if let Some(other) = other {
+                if !neighbors.contains(&other) {
+                    neighbors.push(other);
+                }
+            }
+        }
+        neighbors
+    }
+}
+
+fn territory_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    settlement_query: Query<(&Settlement, &PolityRef, &MapCoordinate, &SettlementTier)>,
+    province_query: Query<(Entity, &MapCoordinate, &MapTile, Option<&SettlementRef>, Option<&ProvinceOwner>), With<Province>>,
+    mut borders: ResMut<PolityBorders>,
+) {
+    if !date.is_year {
+        return;
+    }
+    // nearest settlement gets the land, bigger ones win ties
+    let mut claims: HashMap<MapCoordinate, (isize, isize, PolityRef)> = HashMap::new();
+    for (settlement, &polity, &coordinate, tier) in settlement_query.iter() {
+        let reach = tier.hinterland();
+        for claimed in coordinate.neighbors_in_radius(reach).into_iter() {
+            let claim = (coordinate.distance(claimed), -settlement.population, polity);
+            let better = claims
+                .get(&claimed)
+                .map(|&(distance, size, _)| (claim.0, claim.1) < (distance, size))
+                .unwrap_or(true);
+            if better {
+                claims.insert(claimed, claim);
+            }
+        }
+    }
+
+    let mut owners: HashMap<MapCoordinate, PolityRef> = HashMap::new();
+    for (province_ent, &coordinate, tile, settlement, current) in province_query.iter() {
+        let owner = if let Some(settlement) = settlement {
+            settlement_query.get(settlement.0).ok().map(|(_, &polity, _, _)| polity)
+        } else if tile.tile_type == MapTileType::Water {
+            None
+        } else {
+            claims.get(&coordinate).map(|&(_, _, polity)| polity)
+        };
+        if owner != current.map(|owner| owner.0) {
+            match owner {
+                Some(polity) => { commands.entity(province_ent).insert(ProvinceOwner(polity)); },
+                None => { commands.entity(province_ent).remove::<ProvinceOwner>(); },
+            }
+        }
+        if let Some(polity) = owner {
+            owners.insert(coordinate, polity);
+        }
+    }
+
+    let mut edges = Vec::new();
+    for (&coordinate, &owner) in owners.iter() {
+        for neighbor in coordinate.neighbors_iter() {
+            let other = owners.get(&neighbor).cloned();
+            if other == Some(owner) {
+                continue;
+            }
+            // a border between two polities is seen from both sides, only keep one
+            if other.is_some() && (neighbor.x, neighbor.y) < (coordinate.x, coordinate.y) {
+                continue;
+            }
+            edges.push(BorderEdge {
+                coordinate,
+                neighbor,
+                owner,
+                other,
+            });
+        }
+    }
+    borders.0 = edges;
+}
+
+// capitals that left the polity are replaced by its biggest settlement
+fn capital_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    polity_query: Query<(Entity, Option<&PolityCapital>), With<Polity>>,
+    settlement_query: Query<(Entity, &Settlement, &PolityRef)>,
+) {
+    if !date.is_year {
+        return;
+    }
+    let mut largest: HashMap<PolityRef, (isize, SettlementRef)> = HashMap::new();
+    for (settlement_ent, settlement, &polity) in settlement_query.iter() {
+        let entry = largest.entry(polity).or_insert((settlement.population, SettlementRef(settlement_ent)));
+        if settlement.population > entry.0 {
+            *entry = (settlement.population, SettlementRef(settlement_ent));
+        }
+    }
+    for (polity_ent, capital) in polity_query.iter() {
+        let polity = PolityRef(polity_ent);
+        let still_ours = capital
+            .and_then(|capital| settlement_query.get(capital.0.0).ok())
+            .map(|(_, _, &owner)| owner == polity)
+            .unwrap_or(false);
+        if still_ours {
+            continue;
+        }
+        match largest.get(&polity) {
+            Some(&(_, settlement)) => { commands.entity(polity_ent).insert(PolityCapital(settlement)); },
+            None => { commands.entity(polity_ent).remove::<PolityCapital>(); },
+        }
+    }
+}
+
+fn admin_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    borders: Res<PolityBorders>,
+    formula_system: Res<FormulaSystem<FST>>,
+    mut polity_query: Query<(Entity, &mut PolityHierarchy, Option<&PolityCapital>)>,
+    settlement_query: Query<(Entity, &Settlement, &SettlementPops, &PolityRef, &MapCoordinate)>,
+) {
+    if !date.is_year {
+        return;
+    }
+    // settlements and people under each polity, counting what its vassals hold
+    let mut holdings: HashMap<PolityRef, (usize, isize)> = HashMap::new();
+    for (_, settlement, _, &polity, _) in settlement_query.iter() {
+        let own = holdings.entry(polity).or_insert((0, 0));
+        own.0 += 1;
+        own.1 += settlement.population;
+        if let Some(overlord) = polity_query.get_mut(polity.0).ok().and_then(|(_, h, _)| h.overlord) {
+            let theirs = holdings.entry(overlord).or_insert((0, 0));
+            theirs.0 += 1;
+            theirs.1 += settlement.population;
+        }
+    }
+    let mut tiers = HashMap::new();
+    for (polity_ent, mut hierarchy, _) in polity_query.iter_mut() {
+        let (settlements, population) = holdings.get(&PolityRef(polity_ent)).cloned().unwrap_or((0, 0));
+        hierarchy.tier = AdminTier::of(settlements, population);
+        tiers.insert(PolityRef(polity_ent), (hierarchy.tier, hierarchy.overlord));
+    }
+
+    // far off settlements that don't think much of their rulers go their own way
+    for (settlement_ent, _, pops, &polity, &coordinate) in settlement_query.iter() {
+        let capital = match polity_query.get_mut(polity.0).ok().and_then(|(_, _, capital)| capital.map(|c| c.0)) {
+            Some(capital) if capital.0 != settlement_ent => capital,
+            _ => continue,
+        };
+        let capital_coordinate = match settlement_query.get(capital.0) {
+            Ok((_, _, _, _, &c)) => c,
+            Err(_) => continue,
+        };
+        let past_reach = coordinate.distance(capital_coordinate) - tiers[&polity].0.reach();
+        if past_reach <= 0 {
+            continue;
+        }
+        let unrest = formula_system.get_factor(&SettlementRef(settlement_ent).fst(FactorType::SettlementUnrest));
+        if individual_event(SECESSION_CHANCE * past_reach as f32 * unrest.max(0.1)) {
+            commands.add(SecedeCommand { settlement: SettlementRef(settlement_ent) });
+        }
+    }
+
+    // villages fall in with bigger neighbours, and overlords swallow their vassals
+    for (&polity, &(tier, overlord)) in tiers.iter() {
+        match overlord {
+            Some(overlord) => {
+                if individual_event(ANNEX_CHANCE) {
+                    commands.add(AbsorbPolityCommand { polity, into: overlord });
+                }
+            },
+            None if tier == AdminTier::Village => {
+                let stronger = borders
+                    .neighbors(polity)
+                    .into_iter()
+                    .filter(|n| tiers.get(n).map(|&(t, o)| t > tier && o.is_none()).unwrap_or(false))
+                    .max_by_key(|n| tiers[n].0);
+                if let Some(overlord) = stronger {
+                    if individual_event(VASSALIZE_CHANCE) {
+                        commands.add(VassalizeCommand { vassal: polity, overlord });
+                    }
+                }
+            },
+            None => {},
+        }
+    }
+}
+
+// a settlement breaks away and becomes a polity of its own
+pub struct SecedeCommand {
+    pub settlement: SettlementRef,
+}
+
+// found a polity for a settlement in the tongue of its biggest pop and hand it over, None if nobody lives there
+pub fn secede(world: &mut World, settlement: SettlementRef) -> Option<PolityRef> {
+    let language = settlement
+        .get::<SettlementPops>(world)
+        .0
+        .iter()
+        .max_by_key(|p| p.get::<Pop>(world).size)
+        .map(|p| p.get::<PopLanguage>(world).language)?;
+    let polity = found_polity(world, language);
+    transfer_settlement(world, settlement, polity);
+    Some(polity)
+}
+
+impl Command for SecedeCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let from = *self.settlement.get::<PolityRef>(world);
+        let polity = match secede(world, self.settlement) {
+            Some(polity) => polity,
+            None => return,
+        };
+        let text = format!(
+            "{} breaks away from {} as {}",
+            self.settlement.get::<Settlement>(world).name,
+            from.name(world),
+            polity.name(world),
+        );
+        notify(world, text);
+    }
+}
+
+pub struct VassalizeCommand {
+    pub vassal: PolityRef,
+    pub overlord: PolityRef,
+}
+
+impl Command for VassalizeCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        // one polity bending the knee to a vassal of its own would make a loop
+        if self.overlord.realm(world) == self.vassal || self.vassal.overlord(world).is_some() {
+            return;
+        }
+        match self.vassal.try_get_mut::<PolityHierarchy>(world) {
+            Some(mut hierarchy) => hierarchy.overlord = Some(self.overlord),
+            None => return,
+        }
+        if let Some(mut hierarchy) = self.overlord.try_get_mut::<PolityHierarchy>(world) {
+            hierarchy.vassals.push(self.vassal);
+        }
+        let text = format!("{} becomes a vassal of {}", self.vassal.name(world), self.overlord.name(world));
+        notify(world, text);
+    }
+}
+
+// everything a polity holds goes to another, and the polity is no more
+pub struct AbsorbPolityCommand {
+    pub polity: PolityRef,
+    pub into: PolityRef,
+}
+
+impl Command for AbsorbPolityCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        if self.polity == self.into || world.get_entity(self.polity.entity()).is_none() {
+            return;
+        }
+        leave_wars(world, self.polity);
+        for settlement in self.polity.settlements(world).into_iter() {
+            transfer_settlement(world, settlement, self.into);
+        }
+        let mut migrants = world.query::<&mut Migrants>();
+        for mut group in migrants.iter_mut(world) {
+            if group.polity == self.polity {
+                group.polity = self.into;
+            }
+        }
+        // its vassals now answer to the new overlord, and it stops being anyone's vassal
+        let hierarchy = self.polity.try_get::<PolityHierarchy>(world).cloned().unwrap_or_default();
+        for vassal in hierarchy.vassals.iter() {
+            if let Some(mut vassal_hierarchy) = vassal.try_get_mut::<PolityHierarchy>(world) {
+                vassal_hierarchy.overlord = Some(self.into);
+            }
+        }
+        if let Some(mut into_hierarchy) = self.into.try_get_mut::<PolityHierarchy>(world) {
+            into_hierarchy.vassals.retain(|v| *v != self.polity);
+            into_hierarchy.vassals.extend(hierarchy.vassals.iter().cloned());
+        }
+        if let Some(overlord) = hierarchy.overlord.filter(|o| *o != self.into) {
+            if let Some(mut overlord_hierarchy) = overlord.try_get_mut::<PolityHierarchy>(world) {
+                overlord_hierarchy.vassals.retain(|v| *v != self.polity);
+            }
+        }
+        if let Some(mut own) = self.polity.try_get_mut::<PolityHierarchy>(world) {
+            *own = PolityHierarchy::default();
+        }
+        // land it claimed without settling, and whatever it had saved up
+        for province in self.polity.provinces(world).into_iter() {
+            world.entity_mut(province.entity()).insert(ProvinceOwner(self.into));
+        }
+        if let Some(treasury) = world.entity_mut(self.polity.entity()).remove::<Treasury>() {
+            if let Some(mut into_treasury) = self.into.try_get_mut::<Treasury>(world) {
+                into_treasury.0.merge(treasury.0);
+            }
+        }
+        world.get_resource_mut::<Relations>().unwrap().forget(self.polity);
+        world.get_resource_mut::<EventQueue>().unwrap().forget(self.polity);
+        let mut player = world.get_resource_mut::<PlayerPolity>().unwrap();
+        if player.0 == Some(self.polity) {
+            player.0 = Some(self.into);
+        }
+        // its ruler and heir are left without a polity and die out with it
+        let text = format!("{} is absorbed into {}", self.polity.name(world), self.into.name(world));
+        world.despawn(self.polity.entity());
+        notify(world, text);
+    }
+}
+
+pub fn polity_overlay_system(
+    mut frame: Local<isize>,
+    tile_sprite_indices: Res<TileSpriteIndices>,
+    province_query: Query<(&MapCoordinate, &ProvinceOwner, Option<&SettlementRef>)>,
+    unclaimed_query: Query<(&MapCoordinate, &MapTile), (With<Province>, Without<ProvinceOwner>)>,
+    capital_query: Query<&PolityCapital>,
+    current_overlay: Res<CurrentOverlayType>,
+    mut tile_map_query: Query<&mut Tilemap>,
+) {
+    *frame += 1;
+    if *frame % 20 == 0 && *current_overlay == CurrentOverlayType::Polity {
+        for (&coordinate, owner, settlement) in province_query.iter() {
+            let base = owner.0.color();
+            let is_capital = settlement.is_some()
+                && capital_query.get(owner.0.0).map(|capital| Some(&capital.0) == settlement).unwrap_or(false);
+            // settled land in full, claimed land washed out, capitals darker
+            let color = if is_capital {
+                Color::rgb(base.r() * 0.6, base.g() * 0.6, base.b() * 0.6)
+            } else if settlement.is_some() {
+                base
+            } else {
+                Color::rgb(0.5 + base.r() * 0.5, 0.5 + base.g() * 0.5, 0.5 + base.b() * 0.5)
+            };
+            let point = coordinate.point3();
+            for mut tile_map in tile_map_query.iter_mut() {
+                let mut tile = tile_map.get_tile_mut(point, 0).unwrap();
+                tile.color = color;
+                let sprite_index = *tile_sprite_indices.0.get(&MapTileType::None).unwrap();
+                tile.index = sprite_index;
+            }
+        }
+        // land that was given up goes back to how it looks without the overlay
+        for (&coordinate, map_tile) in unclaimed_query.iter() {
+            let point = coordinate.point3();
+            for mut tile_map in tile_map_query.iter_mut() {
+                let mut tile = tile_map.get_tile_mut(point, 0).unwrap();
+                tile.color = Color::WHITE;
+                let sprite_index = *tile_sprite_indices.0.get(&map_tile.tile_type).unwrap();
+                tile.index = sprite_index;
+            }
+        }
+    }
+}
+
+pub struct BorderLine;
+
+pub struct BorderMaterial(pub Handle<ColorMaterial>);
+
+// draw a line along each border edge while the polity overlay is up
+fn border_line_system(
+    mut commands: Commands,
+    borders: Res<PolityBorders>,
+    current_overlay: Res<CurrentOverlayType>,
+    material: Res<BorderMaterial>,
+    line_query: Query<Entity, With<BorderLine>>,
+) {
+    let showing = *current_overlay == CurrentOverlayType::Polity;
+    let drawn = line_query.iter().next().is_some();
+    if drawn && (!showing || borders.is_changed()) {
+        for line in line_query.iter() {
+            commands.entity(line).despawn();
+        }
+    }
+    if !showing || (drawn && !borders.is_changed()) {
+        return;
+    }
+    for edge in borders.0.iter() {
+        let (x1, y1) = edge.coordinate.pixel_pos();
+        let (x2, y2) = edge.neighbor.pixel_pos();
+        let (dx, dy) = (x2 - x1, y2 - y1);
+        // the shared side runs across the line between the two centres, about 1/sqrt(3) of its length
+        let length = (dx * dx + dy * dy).sqrt() / 3f32.sqrt();
+        commands
+            .spawn_bundle(SpriteBundle {
+                material: material.0.clone(),
+                sprite: Sprite::new(Vec2::new(length, 2.0)),
+                transform: Transform {
+                    translation: Vec3::new((x1 + x2) / 2.0, (y1 + y2) / 2.0, 3.0),
+                    rotation: Quat::from_rotation_z(dy.atan2(dx) + FRAC_PI_2),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(BorderLine);
+    }
+}
+
+fn setup_border_material(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    commands.insert_resource(BorderMaterial(materials.add(Color::rgb(0.1, 0.1, 0.1).into())));
+}
+
+pub struct PolityPlugin;
+
+impl Plugin for PolityPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .init_resource::<PolityBorders>()
+            .add_startup_system(setup_border_material.system())
+            .add_system_to_day(territory_system.system())
+            .add_system_to_day(capital_system.system())
+            .add_system_to_day(admin_system.system())
+            .add_system(polity_overlay_system.system())
+            .add_system(border_line_system.system());
+    }
+}
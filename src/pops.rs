@@ -1,15 +1,24 @@
-use bevy::{core::FixedTimestep, ecs::{component::Component, system::Command, world::EntityRef, system::SystemParam}, prelude::*};
-use rand::{Rng, distributions::Slice, prelude::SliceRandom, random, thread_rng};
+use bevy::{ecs::{component::Component, system::Command, world::EntityRef, system::SystemParam}, prelude::*};
+use rand::{Rng, distributions::Slice, prelude::SliceRandom, thread_rng};
 use rand_distr::Uniform;
 use std::collections::{HashMap, VecDeque};
 use std::hash::Hash;
 use crate::{formula::FormulaSystem, prelude::*};
-use crate::{constant::{DAY_LABEL, DAY_TIMESTEP}, map::*, province::{Province, ProvinceMap, ProvinceRef, ProvinceSettlements}};
+use crate::{constant::DAY_LABEL, map::*, province::{Province, ProvinceMap, ProvinceRef, ProvinceSettlements}};
 use crate::time::*;
 use crate::probability::*;
 use crate::stage::*;
 use crate::factor::*;
 use crate::settlement::*;
+use crate::culture::*;
+use crate::migration::Migrants;
+use crate::disease::PopDiseases;
+use crate::war::Army;
+use crate::treasury::{Ledger, Taxation, Treasury};
+use crate::religion::Religion;
+use crate::urbanization::Occupations;
+use crate::agriculture::Fields;
+use crate::technology::Knowledge;
 
 
 
@@ -33,16 +42,84 @@ pub struct PopBundle {
     pub base: Pop,
     pub province: ProvinceRef,
     pub culture: CultureRef,
+    pub religion: ReligionRef,
     pub settlement: SettlementRef,
     pub polity: PolityRef,
     pub language: PopLanguage,
     pub storage: GoodStorage,
-    pub kid_buffer: KidBuffer,
+    pub ages: AgeCohorts,
+    pub hunger: Hunger,
+    pub diseases: PopDiseases,
+    pub occupations: Occupations,
 }
 
 #[game_ref]
 pub struct PopRef(pub Entity);
 
+impl PopRef {
+    // pull people out of this pop along with their share of its stores and crop
+    pub fn take_people(&self, world: &mut World, amount: isize) -> (AgeCohorts, GoodStorage, Option<Fields>) {
+        let size = self.get::<Pop>(world).size;
+        let (taken, remaining) = {
+            let mut ages = self.get_mut::<AgeCohorts>(world);
+            let taken = ages.split(amount);
+            (taken, ages.size())
+        };
+        let share = if size > 0 { taken.size() as f32 / size as f32 } else { 0.0 };
+        let storage = self.get_mut::<GoodStorage>(world).take_share(share);
+        let fields = self.try_get_mut::<Fields>(world).map(|mut fields| fields.take_share(share));
+        self.get_mut::<Pop>(world).size = remaining;
+        (taken, storage, fields)
+    }
+
+    // fighting-age people called up, they leave their stores behind
+    pub fn take_levy(&self, world: &mut World, amount: isize) -> AgeCohorts {
+        let (taken, remaining) = {
+            let mut ages = self.get_mut::<AgeCohorts>(world);
+            let taken = ages.split_working(amount);
+            (taken, ages.size())
+        };
+        self.get_mut::<Pop>(world).size = remaining;
+        taken
+    }
+
+    pub fn add_people(&self, world: &mut World, ages: &AgeCohorts, storage: GoodStorage, fields: Option<Fields>) {
+        let size = {
+            let mut own_ages = self.get_mut::<AgeCohorts>(world);
+            own_ages.merge(ages);
+            own_ages.size()
+        };
+        self.get_mut::<GoodStorage>(world).merge(storage);
+        if let (Some(fields), Some(mut own_fields)) = (fields, self.try_get_mut::<Fields>(world)) {
+            own_fields.merge(fields);
+        }
+        self.get_mut::<Pop>(world).size = size;
+    }
+
+    // move people over to a pop of another culture, language or faith in the same settlement, founding it if needed
+    pub fn convert_people(
+        &self,
+        world: &mut World,
+        amount: isize,
+        culture: Option<CultureRef>,
+        language: Option<LanguageRef>,
+        religion: Option<ReligionRef>,
+    ) {
+        if world.get_entity(self.entity()).is_none() {
+            return;
+        }
+        let settlement = *self.get::<SettlementRef>(world);
+        let culture = culture.unwrap_or(*self.get::<CultureRef>(world));
+        let language = language.unwrap_or(self.get::<PopLanguage>(world).language);
+        let religion = religion.unwrap_or(*self.get::<ReligionRef>(world));
+        let (ages, storage, fields) = self.take_people(world, amount);
+        settlement.settle_people(world, culture, language, religion, ages, storage, fields);
+        if self.get::<Pop>(world).size <= 0 {
+            Box::new(PopDieCommand(*self)).write(world);
+        }
+    }
+}
+
 
 // pub type PopQuery<'w> = Query<'w, (&'w Pop, &'w FarmingPop, &'w MapCoordinate)>;
 
@@ -66,40 +143,155 @@ pub struct PopCulture(pub Entity);
 pub struct PopPolity(pub Entity);
 
 
-#[derive(Debug)]
-pub struct KidBuffer(pub VecDeque<isize>);
+// people of this age or older are done being children
+pub const WORKING_AGE: usize = 15;
+// people of this age or older are elderly and work less
+pub const ELDER_AGE: usize = 55;
+pub const MAX_AGE: usize = 80;
+const FERTILE_AGES: std::ops::Range<usize> = 16..45;
+
+// yearly chance of dying at an age in good times
+pub fn base_mortality(age: usize) -> f32 {
+    match age {
+        0 => 0.25,
+        1..=4 => 0.05,
+        5..=14 => 0.01,
+        15..=49 => 0.015,
+        50..=64 => 0.04,
+        65..=74 => 0.1,
+        _ => 0.2,
+    }
+}
 
-impl KidBuffer {
-    pub fn new() -> Self {
-        Self(VecDeque::new())
+// yearly births per person (not per woman) of an age
+pub fn base_fertility(age: usize) -> f32 {
+    if FERTILE_AGES.contains(&age) {
+        0.09
+    } else {
+        0.0
+    }
+}
+
+// one entry per year of age, index 0 are newborns
+#[derive(Debug, Clone)]
+pub struct AgeCohorts(pub VecDeque<isize>);
+
+impl AgeCohorts {
+    // spread a population over ages in roughly the shape the mortality curve leaves behind
+    pub fn with_size(size: isize) -> Self {
+        let mut survival = Vec::with_capacity(MAX_AGE);
+        let mut alive = 1.0;
+        for age in 0..MAX_AGE {
+            survival.push(alive);
+            alive *= 1.0 - base_mortality(age);
+        }
+        let total_weight: f32 = survival.iter().sum();
+        let mut cohorts = survival
+            .iter()
+            .map(|w| (size as f32 * w / total_weight).floor() as isize)
+            .collect::<VecDeque<_>>();
+        let remainder = size - cohorts.iter().sum::<isize>();
+        cohorts[WORKING_AGE + 5] += remainder;
+        Self(cohorts)
     }
 
     pub fn size(&self) -> isize {
-        self.0.iter().fold(0, |acc, e| acc + e)
+        self.0.iter().sum()
     }
 
-    pub fn spawn(&mut self, babies: isize) -> isize {
-        // println!("spawn babies {}", babies);
-        self.0.push_front(babies);
-        // println!("{:?}", self);
-        if self.0.len() > 12 {
-            self.0.pop_back().unwrap()
-        } else {
-            babies
+    fn sum_ages(&self, ages: std::ops::Range<usize>) -> isize {
+        self.0.iter().skip(ages.start).take(ages.end - ages.start).sum()
+    }
+
+    pub fn children(&self) -> isize {
+        self.sum_ages(0..WORKING_AGE)
+    }
+
+    pub fn working(&self) -> isize {
+        self.sum_ages(WORKING_AGE..ELDER_AGE)
+    }
+
+    pub fn elderly(&self) -> isize {
+        self.sum_ages(ELDER_AGE..MAX_AGE)
+    }
+
+    // adults' worth of mouths to feed, children and the old eat less
+    pub fn eaters(&self) -> f32 {
+        self.working() as f32 + 0.8 * self.elderly() as f32 + 0.6 * self.children() as f32
+    }
+
+    // remove `amount` people taken evenly across ages, returning them as their own cohorts
+    pub fn split(&mut self, amount: isize) -> AgeCohorts {
+        let total = self.size();
+        let amount = amount.min(total).max(0);
+        let mut taken = VecDeque::with_capacity(self.0.len());
+        if total == 0 {
+            return Self(taken);
         }
+        for cohort in self.0.iter_mut() {
+            let take = (*cohort * amount / total).min(*cohort);
+            *cohort -= take;
+            taken.push_back(take);
+        }
+        // integer division leaves a few behind, take them from the largest cohorts
+        let mut left = amount - taken.iter().sum::<isize>();
+        while left > 0 {
+            let (idx, _) = self.0.iter().enumerate().max_by_key(|(_, c)| **c).unwrap();
+            self.0[idx] -= 1;
+            taken[idx] += 1;
+            left -= 1;
+        }
+        Self(taken)
     }
 
-    pub fn starve(&mut self) -> isize {
-        let cohort = sample(3.0).abs().min(12.0) as usize;
-        if self.0.len() > cohort {
-            let cohort_size = self.0[cohort];
-            let dead_kids = positive_isample(cohort_size / 20 + 2, cohort_size / 5 + 1);
-            // println!("cohort {}, size {}, dead {}", cohort, cohort_size, dead_kids);
-            self.0[cohort] = (cohort_size - dead_kids).max(0);
-            cohort_size - self.0[cohort]
-        } else {
-            0
+    pub fn merge(&mut self, other: &AgeCohorts) {
+        for (age, &cohort) in other.0.iter().enumerate() {
+            if age < self.0.len() {
+                self.0[age] += cohort;
+            } else {
+                self.0.push_back(cohort);
+            }
+        }
+    }
+
+    // like split, but only taking people of working age
+    pub fn split_working(&mut self, amount: isize) -> AgeCohorts {
+        let mut working = AgeCohorts(
+            self.0
+                .iter()
+                .enumerate()
+                .map(|(age, &cohort)| if age >= WORKING_AGE && age < ELDER_AGE { cohort } else { 0 })
+                .collect()
+        );
+        let taken = working.split(amount);
+        for (age, &cohort) in taken.0.iter().enumerate() {
+            self.0[age] -= cohort;
         }
+        taken
+    }
+
+    // kill `amount` people spread across ages, returns the number actually killed
+    pub fn kill(&mut self, amount: isize) -> isize {
+        self.split(amount).size()
+    }
+
+    // one year passes: births at the front, deaths throughout, the oldest fall off the end
+    pub fn age_year(&mut self, fertility_mod: f32, mortality_mod: f32) -> (isize, isize) {
+        let mut births = 0;
+        for (age, &cohort) in self.0.iter().enumerate() {
+            births += binomial_isample(cohort, base_fertility(age) * fertility_mod);
+        }
+        let mut deaths = 0;
+        for (age, cohort) in self.0.iter_mut().enumerate() {
+            let died = binomial_isample(*cohort, base_mortality(age) * mortality_mod);
+            *cohort -= died;
+            deaths += died;
+        }
+        self.0.push_front(births);
+        while self.0.len() > MAX_AGE {
+            deaths += self.0.pop_back().unwrap();
+        }
+        (births, deaths)
     }
 }
 
@@ -107,6 +299,7 @@ impl KidBuffer {
 pub struct CultureRef(pub Entity);
 pub struct Culture {
     pub name: String,
+    pub traits: Vec<CultureTrait>,
 }
 
 
@@ -126,6 +319,18 @@ impl PolityRef {
         let g = self.0.id() % 8;
         Color::rgb(r as f32 / 8.0, g as f32 / 8.0, b as f32 / 8.0)
     }
+
+    pub fn pops(&self, world: &World) -> Vec<PopRef> {
+        let province_map = world.get_resource::<ProvinceMap>().unwrap();
+        province_map
+            .0
+            .values()
+            .filter_map(|province| province.try_get::<SettlementRef>(world))
+            .flat_map(|settlement| settlement.get::<SettlementPops>(world).0.iter())
+            .filter(|pop| *pop.get::<PolityRef>(world) == *self)
+            .cloned()
+            .collect()
+    }
 }
 
 // pub type PolityQuery<'w> = Query<'w, (&'w Polity)>;
@@ -134,26 +339,57 @@ pub struct Polity {
     pub name: String,
 }
 
+#[derive(Debug, Default)]
 pub struct Hunger {
+    // fraction of last month's food need that went unmet
+    pub shortfall: f32,
+    // sum of monthly shortfalls since the last yearly growth tick
+    pub accumulated: f32,
+}
+
+impl Hunger {
+    pub fn record_month(&mut self, shortfall: f32) {
+        self.shortfall = shortfall;
+        self.accumulated += shortfall;
+    }
 
+    // average shortfall over the past year, 0.0 fed to 1.0 nothing eaten
+    pub fn yearly(&self) -> f32 {
+        (self.accumulated / 12.0).min(1.0)
+    }
 }
 
 pub fn growth_system(
     mut commands: Commands,
     date: Res<CurrentDate>,
-    mut pop_query: Query<(Entity, &mut Pop, &mut KidBuffer)>,
+    formula_system: Res<FormulaSystem<FST>>,
+    mut pop_query: Query<(Entity, &mut Pop, &mut AgeCohorts, &mut Hunger, &CultureRef, &ReligionRef)>,
+    culture_query: Query<&Culture>,
+    religion_query: Query<&Religion>,
 ) {
     if !date.is_year {
         return;
     }
     println!("growth_system {}", *date);
-    for (pop_ent, mut pop, mut kb) in pop_query.iter_mut() {
-        let babies = positive_isample(2, pop.size * 4 / 100);
-        let deaths = positive_isample(2, pop.size / 50);
-        let new = kb.spawn(babies) as isize - deaths as isize;
-        pop.size = pop.size + new;
-        if pop.size < 0 {
-            commands.add(PopDieCommand(PopRef(pop_ent)));
+    for (pop_ent, mut pop, mut ages, mut hunger, culture, religion) in pop_query.iter_mut() {
+        let pop_ref = PopRef(pop_ent);
+        let culture = culture_query.get(culture.0).unwrap();
+        let faith = |factor| religion_query.get(religion.0).map(|r| r.modifier(factor)).unwrap_or(0.0);
+        let famine = hunger.yearly();
+        // hungry people have fewer kids, and the young and old die first
+        let fertility_mod = ((1.0 - famine).powi(2)
+                             + formula_system.get_factor(&pop_ref.fst(FactorType::PopFertility))
+                             + culture.modifier(FactorType::PopFertility)
+                             + faith(FactorType::PopFertility)).max(0.0);
+        let mortality_mod = (1.0 + 4.0 * famine
+                             + formula_system.get_factor(&pop_ref.fst(FactorType::PopMortality))
+                             + culture.modifier(FactorType::PopMortality)
+                             + faith(FactorType::PopMortality)).max(0.0);
+        ages.age_year(fertility_mod, mortality_mod);
+        hunger.accumulated = 0.0;
+        pop.size = ages.size();
+        if pop.size <= 0 {
+            commands.add(PopDieCommand(pop_ref));
         }
     }
 }
@@ -162,6 +398,10 @@ pub struct PopDieCommand(pub PopRef);
 
 impl Command for PopDieCommand {
     fn write(self: Box<Self>, world: &mut World) {
+        // emptied out and despawned earlier in the tick, by conversion, migration or a raid
+        if world.get_entity(self.0.entity()).is_none() {
+            return;
+        }
         let settlement = *self.0
             .get::<SettlementRef>(world);
         settlement
@@ -172,17 +412,34 @@ impl Command for PopDieCommand {
     }
 }
 
+// kg of grain one working-age farmer brings in over a year
+pub const HARVEST_PER_WORKER: f32 = 450.0;
+// kcal a person needs to stay fed for a month
+pub const MONTHLY_CALORIE_NEED: f32 = 2500.0 * 30.0;
+
+// the crop is counted and stored once the last of it is in, and the pressure on the land is felt
 pub fn harvest_system(
     formula_system: Res<FormulaSystem<FST>>,
     date: Res<CurrentDate>,
-    mut farming_pop_query: Query<(Entity, &Pop, &SettlementRef, &FarmingPop)>,
+    mut farming_pop_query: Query<(Entity, &SettlementRef, &CultureRef, &ReligionRef, &PolityRef, &FarmingPop, &Occupations, &mut Fields, &mut GoodStorage)>,
     settlement: Query<&Settlement>,
+    knowledge_query: Query<&Knowledge>,
+    culture_query: Query<&Culture>,
+    religion_query: Query<&Religion>,
+    mut treasury_query: Query<(&Taxation, &mut Treasury, &mut Ledger)>,
 ) {
-    if !date.is_year {
+    if !date.is_day {
         return;
     }
-    for (ent, pop, &settlement_ref, farming_pop) in farming_pop_query.iter_mut() {
-        let mut farmed_amount = pop.size as f32;
+    let mut tithes: HashMap<PolityRef, f32> = HashMap::new();
+    for (ent, &settlement_ref, culture, religion, &polity, farming_pop, occupations, mut fields, mut storage) in farming_pop_query.iter_mut() {
+        if !fields.season(farming_pop.good).is_harvest_over(date.date) {
+            continue;
+        }
+        // what was sown and brought in, grown well or poorly with the weather
+        let mut farmed_amount = fields.reaped * fields.weather;
+        fields.sown = 0.0;
+        fields.reaped = 0.0;
         let carrying_capacity = formula_system.get_factor(&settlement_ref.fst(FactorType::SettlementCarryingCapacity));
         let comfortable_limit = carrying_capacity / 2.0;
         let settlement_size = settlement.get(settlement_ref.0).unwrap().population;
@@ -199,19 +456,57 @@ pub fn harvest_system(
         }
         if settlement_size as f32 > carrying_capacity {
             let old_farmed_amount = farmed_amount;
-            farmed_amount = pop.size as f32 / settlement_size as f32 * (carrying_capacity + (settlement_size as f32 - carrying_capacity).powf(0.85));
+            farmed_amount = farmed_amount / settlement_size as f32 * (carrying_capacity + (settlement_size as f32 - carrying_capacity).powf(0.85));
             println!("less is farmed, would be {}, is {}", old_farmed_amount, farmed_amount);
             formula_system.add_factor(&PopRef(ent).fst(FactorType::PopPressure), 0.4);
         }
-        if random::<f32>() > 0.98 {
-            // println!("failed harvest! halving farmed goods");
-            farmed_amount *= 0.6;
+        let culture_bonus = culture_query.get(culture.0).map(|c| c.modifier(FactorType::PopHarvest)).unwrap_or(0.0);
+        let faith_bonus = religion_query.get(religion.0).map(|r| r.modifier(FactorType::PopHarvest)).unwrap_or(0.0);
+        let tools = occupations.tools() * knowledge_query.get(settlement_ref.0).map(|k| k.tool_quality()).unwrap_or(1.0);
+        let harvest = farmed_amount * HARVEST_PER_WORKER * (1.0 + culture_bonus + faith_bonus + tools);
+        // the polity takes its tithe off the top
+        let tithe = match treasury_query.get_mut(polity.entity()) {
+            Ok((taxation, mut treasury, _)) => {
+                let tithe = harvest * taxation.tithe;
+                treasury.0.add(farming_pop.good, tithe);
+                tithe
+            },
+            Err(_) => 0.0,
+        };
+        *tithes.entry(polity).or_insert(0.0) += tithe * farming_pop.good.base_satiety().base;
+        storage.add(farming_pop.good, harvest - tithe);
+    }
+    for (polity, calories) in tithes.into_iter() {
+        if let Ok((_, _, mut ledger)) = treasury_query.get_mut(polity.entity()) {
+            ledger.record(date.date, "tithe", calories);
         }
-        // world.add_command(Box::new(SetGoodsCommand {
-        //     good_type: farmed_good,
-        //     amount: farmed_amount * 300.0,
-        //     pop: pop.clone(),
-        // }));
+    }
+}
+
+pub fn food_consumption_system(
+    date: Res<CurrentDate>,
+    mut pop_query: Query<(&AgeCohorts, &mut GoodStorage, &mut Hunger)>,
+) {
+    if !date.is_month {
+        return;
+    }
+    for (ages, mut storage, mut hunger) in pop_query.iter_mut() {
+        let eaters = ages.eaters();
+        let need = eaters * MONTHLY_CALORIE_NEED;
+        if need <= 0.0 {
+            continue;
+        }
+        let mut remaining = need;
+        for &good in FOOD_GOODS.iter() {
+            let calories = good.base_satiety().base;
+            if calories <= 0.0 || remaining <= 0.0 {
+                continue;
+            }
+            let wanted = (remaining / calories).min(good.max_consumed_monthly_per_capita() * eaters);
+            let deficit = storage.consume(good, wanted).unwrap_or(0.0);
+            remaining -= (wanted - deficit) * calories;
+        }
+        hunger.record_month((remaining / need).max(0.0));
     }
 }
 
@@ -251,6 +546,46 @@ impl GoodStorage {
         *self.0.get_mut(&good).unwrap() = amount;
     }
 
+    pub fn take_share(&mut self, share: f32) -> GoodStorage {
+        let share = share.max(0.0).min(1.0);
+        let mut taken = HashMap::new();
+        for (&good, stored) in self.0.iter_mut() {
+            let amount = *stored * share;
+            *stored -= amount;
+            taken.insert(good, amount);
+        }
+        GoodStorage(taken)
+    }
+
+    pub fn merge(&mut self, other: GoodStorage) {
+        for (good, amount) in other.0.into_iter() {
+            self.add(good, amount);
+        }
+    }
+
+    pub fn calories(&self) -> f32 {
+        FOOD_GOODS.iter().map(|good| self.amount(*good) * good.base_satiety().base).sum()
+    }
+
+    // take food worth up to `calories`, staples first
+    pub fn take_calories(&mut self, calories: f32) -> GoodStorage {
+        let mut remaining = calories;
+        let mut taken = HashMap::new();
+        for &good in FOOD_GOODS.iter() {
+            let per_unit = good.base_satiety().base;
+            if per_unit <= 0.0 || remaining <= 0.0 {
+                continue;
+            }
+            let amount = (remaining / per_unit).min(self.amount(good));
+            if amount > 0.0 {
+                self.consume(good, amount);
+                taken.insert(good, amount);
+                remaining -= amount * per_unit;
+            }
+        }
+        GoodStorage(taken)
+    }
+
     // pub fn try_eat_diet(&self, diet: Diet) -> Vec<(GoodType, f32)> {
     //     let mut bad_res = Vec::new();
 
@@ -297,22 +632,40 @@ pub fn sample_list(list: &Vec<String>) -> String {
     rand::thread_rng().sample(Slice::new(list).unwrap()).clone()
 }
 
+lazy_static! {
+    pub static ref VOWELS: Vec<String> = map_string(vec![
+        "a", "ae", "e", "i", "ei", "u", "o", "oi", "au", "ou", "ee", "ea", "oa",
+    ]);
+    pub static ref CONSONANTS: Vec<String> = map_string(vec![
+        "b", "c", "d", "f", "g", "h", "j", "k", "l", "m", "n", "p", "r", "s", "t", "v",
+        "w", "z", "ss", "th", "st", "ch", "sh",
+    ]);
+}
+
+// sound change: most sounds survive, a few are lost and a few are picked up from the inventory
+fn shift_sounds(list: &Vec<String>, inventory: &Vec<String>) -> Vec<String> {
+    let mut shifted = list
+        .iter()
+        .filter(|_| individual_event(0.85))
+        .cloned()
+        .collect::<Vec<String>>();
+    for sound in inventory.iter() {
+        if !list.contains(sound) && individual_event(0.1) {
+            shifted.push(sound.clone());
+        }
+    }
+    if shifted.is_empty() {
+        list.clone()
+    } else {
+        shifted
+    }
+}
+
 impl Language {
     pub fn new() -> Self {
         let vowel_chance = 0.75;
-        let vowels = list_filter_chance(
-            &map_string(vec![
-                "a", "ae", "e", "i", "ei", "u", "o", "oi", "au", "ou", "ee", "ea", "oa",
-            ]),
-            0.75,
-        );
-        let consonants = list_filter_chance(
-            &map_string(vec![
-                "b", "c", "d", "f", "g", "h", "j", "k", "l", "m", "n", "p", "r", "s", "t", "v",
-                "w", "z", "ss", "th", "st", "ch", "sh",
-            ]),
-            0.75,
-        );
+        let vowels = list_filter_chance(&VOWELS, 0.75);
+        let consonants = list_filter_chance(&CONSONANTS, 0.75);
 
         let initial_consonants = list_filter_chance(&consonants, 0.50);
         let middle_consonants = list_filter_chance(&consonants, 0.75);
@@ -330,6 +683,20 @@ impl Language {
         newlang
     }
 
+    // a daughter language, recognisably descended from this one
+    pub fn derive(&self) -> Self {
+        let mut newlang = Self {
+            name: "".to_owned(),
+            vowels: shift_sounds(&self.vowels, &VOWELS),
+            initial_consonants: shift_sounds(&self.initial_consonants, &CONSONANTS),
+            middle_consonants: shift_sounds(&self.middle_consonants, &CONSONANTS),
+            end_consonants: shift_sounds(&self.end_consonants, &CONSONANTS),
+        };
+
+        newlang.name = newlang.generate_name(2);
+        newlang
+    }
+
     pub fn maybe_vowel(&self, chance: f32) -> Option<String> {
         if rand::random::<f32>() < chance {
             Some(sample_list(&self.vowels))
@@ -501,173 +868,37 @@ impl GoodType {
 // }
 
 
-pub struct PopSeekMigrationCommand {
-    pub pop: PopRef,
-    pub pressure: f32,
-}
-
-impl Command for PopSeekMigrationCommand {
-    fn write(self: Box<Self>, world: &mut World) {
-        let pop_size = self.pop.get::<Pop>(world).size;
-        let coordinate = *self
-            .pop
-            .accessor(world)
-            .get_ref::<ProvinceRef>()
-            .get::<MapCoordinate>();
-        let random_point = coordinate.random_local();
-        if random_point == coordinate {
-            // got unlucky, just die
-            return;
-        }
-
-        let province_map = world.get_resource::<ProvinceMap>().unwrap();
-        let province_maybe = province_map.0.get(&random_point);
-        // missed the map?
-        if province_maybe.is_none() {
-            return;
-        }
-        let pref = province_maybe.unwrap();
-        if !pref.get::<MapTile>(world).tile_type.inhabitable() {
-            return;
-        }
-        let mut target_value = -2.0 + self.pressure;
-        if let Some(settlement) = pref.try_get::<SettlementRef>(world) {
-            /*
-            println!("settlement?? {:?}", settlement);
-            */
-            target_value -= 1.0;
-            if settlement.get::<CultureRef>(world) != self.pop.get::<CultureRef>(world) {
-                target_value -= 2.0;
-            } else {
-                println!("meld");
-
-            }
-        }
-        if individual_event(logistic(target_value)) {
-            // println!("really migrate?? {:?}", self.pop);
-            let migration_status = {
-                let arrival = world.get_resource::<CurrentDate>().unwrap().date.days_after(30);
-                let migrating = pop_size / 5;
-                MigrationStatus {
-                    dest: *pref,
-                    migrating,
-                    settlement: None,
-                    arrival,
-                }
-            };
-            // println!("lose {} people of {}", migration_status.migrating, pop_size);
-            self.pop.set_factor(world, FactorType::PopPressure, 0.0);
-            world
-                .get_mut::<Pop>(self.pop.entity())
-                .unwrap()
-                .size -= migration_status.migrating;
-            world
-                .entity_mut(self.pop.entity())
-                .insert(migration_status);
-        }
-
-            //             let settlement_carrying_capacity = settlement.get().carrying_capacity(world);
-            //             if (settlement.get().population(world) as f32) < settlement_carrying_capacity / 4.0 {
-            //                 let size = (self.pop.get().size / 4).min((settlement_carrying_capacity / 4.0).round() as isize);
-            //                 self.pop.get_mut().migration_status = Some(MigrationStatus {
-            //                     migrating: size,
-            //                     dest: target_province_id.clone(),
-            //                     date: world.date.day + 60,
-            //                     settlement: Some(settlement.clone()),
-            //                 });
-            //                 world.events.add_deferred(Rc::new(MigrationDoneEvent(self.pop.clone())), world.date.day + 60);
-            //                 return;
-            //             }
-            //         }
-            //     }
-            //     if individual_event(logistic(target_value)) {
-            //         // println!("migrate {:?} to {}", self.pop, random_point);
-            //         let size = self.pop.get().size / 5;
-            //         self.pop.get_mut().migration_status = Some(MigrationStatus {
-            //             migrating: size,
-            //             dest: target_province_id.clone(),
-            //             date: world.date.day + 60,
-            //             settlement: None,
-            //         });
-            //         world.events.add_deferred(Rc::new(MigrationDoneEvent(self.pop.clone())), world.date.day + 60);
-            //     }
-            // }
-    }
-}
-
-
-
-pub struct MigrationStatus {
-    pub dest: ProvinceRef,
-    pub migrating: isize,
-    pub settlement: Option<SettlementRef>,
-    pub arrival: Date,
-}
-
-
-fn pop_migration_system(
-    mut commands: Commands,
-    mut migrating_pops: Query<(Entity, &mut Pop, &MigrationStatus, &PopLanguage, &CultureRef, &PolityRef)>,
-    date: Res<CurrentDate>,
-) {
-    if !date.is_day {
-        return;
-    }
-    for (pop_ent, mut pop, migration_status, language, &culture, &polity) in migrating_pops.iter_mut() {
-        if migration_status.arrival.is_after(date.date) {
-            if let Some(settlement) = migration_status.settlement {
-                commands.add(SpawnPopCommand {
-                    province: migration_status.dest,
-                    settlement,
-                    language: language.language,
-                    culture,
-                    polity,
-                    size: migration_status.migrating,
-                })
-            } else {
-                commands.add(SpawnSettlementCommand {
-                    province: migration_status.dest,
-                    language: language.language,
-                    culture,
-                    polity,
-                    size: migration_status.migrating,
-                })
-            }
-            commands
-                .entity(pop_ent)
-                .remove::<MigrationStatus>();
-        }
-    }
-}
-
-pub struct PopMigrateCommand {
-    pub pop: PopRef,
-}
-
-impl Command for PopMigrateCommand {
-
-    fn write(self: Box<Self>, world: &mut World) {
-        // // println!("finally migrate {:?}", self.pop);
-        // if let Some(settlement_id) = self.settlement.clone() {
-        //     settlement_id.get_mut().accept_migrants(world, self.pop.clone(), self.migrating);
-        // } else {
-        //     add_settlement(world, self.pop.get().culture.clone(), self.dest.clone(), self.pop.get().polity.clone(), self.migrating);
-        // }
-        // self.pop.get_mut().size -= self.migrating;
-    }
-}
-
 pub struct GlobalPopulation(pub isize);
 pub struct MaxProvincePopulation(pub isize);
 
 fn global_population_system(
     mut global_pop: ResMut<GlobalPopulation>,
     pops: Query<&Pop>,
+    migrants: Query<&AgeCohorts, With<Migrants>>,
+    armies: Query<&Army>,
 ) {
     global_pop.0 = 0;
     for pop in pops.iter() {
         global_pop.0 += pop.size;
     }
+    for ages in migrants.iter() {
+        global_pop.0 += ages.size();
+    }
+    for army in armies.iter() {
+        global_pop.0 += army.size();
+    }
+}
+
+// everyone alive, settled or on the road
+pub fn total_population(world: &mut World) -> isize {
+    let settled: isize = world.query::<&Pop>().iter(world).map(|pop| pop.size).sum();
+    let travelling: isize = world
+        .query_filtered::<&AgeCohorts, With<Migrants>>()
+        .iter(world)
+        .map(|ages| ages.size())
+        .sum();
+    let fighting: isize = world.query::<&Army>().iter(world).map(|army| army.size()).sum();
+    settled + travelling + fighting
 }
 
 pub struct PopPlugin;
@@ -677,14 +908,8 @@ impl Plugin for PopPlugin {
         let pop_systems = SystemSet::new()
             .with_system(harvest_system.system().label(DAY_LABEL))
             .with_system(growth_system.system().label(DAY_LABEL))
-            .with_system(pop_migration_system.system().label(DAY_LABEL))
+            .with_system(food_consumption_system.system().label(DAY_LABEL))
             .with_system(global_population_system.system().label(DAY_LABEL));
-            // .with_run_criteria(
-            //     FixedTimestep::step(0.0001)
-            //         // labels are optional. they provide a way to access the current
-            //         // FixedTimestep state from within a system
-            //         .with_label(DAY_TIMESTEP),
-            // )
         app
             .add_startup_stage_after(InitStage::LoadMap, InitStage::LoadPops, SystemStage::single_threaded())
             .add_system_set_to_stage(DayStage::Main, pop_systems)
@@ -699,3 +924,29 @@ impl Plugin for PopPlugin {
 
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aging_only_changes_population_by_births_and_deaths() {
+        let mut ages = AgeCohorts::with_size(10000);
+        assert_eq!(ages.size(), 10000);
+        for _ in 0..100 {
+            let before = ages.size();
+            let (births, deaths) = ages.age_year(1.0, 1.5);
+            assert_eq!(ages.size(), before + births - deaths);
+        }
+    }
+
+    #[test]
+    fn splitting_cohorts_keeps_everyone() {
+        let mut ages = AgeCohorts::with_size(777);
+        let taken = ages.split(123);
+        assert_eq!(taken.size(), 123);
+        assert_eq!(ages.size(), 654);
+        ages.merge(&taken);
+        assert_eq!(ages.size(), 777);
+    }
+}
@@ -40,3 +40,14 @@ pub fn positive_isample(stddev: isize, mean: isize) -> isize {
 pub fn sample(stddev: f32) -> f32 {
     dev_mean_sample(stddev, 0.0)
 }
+
+// normal approximation of how many of n individuals an event with probability p hits
+pub fn binomial_isample(n: isize, p: f32) -> isize {
+    if n <= 0 || p <= 0.0 {
+        return 0;
+    }
+    let p = p.min(1.0);
+    let mean = n as f32 * p;
+    let stddev = (mean * (1.0 - p)).sqrt();
+    dev_mean_sample(stddev, mean).round().max(0.0).min(n as f32) as isize
+}
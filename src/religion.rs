@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+
+use bevy::{ecs::system::Command, prelude::*};
+use bevy_tilemap::prelude::*;
+use rand::{prelude::SliceRandom, thread_rng};
+use strum::{EnumIter, IntoEnumIterator};
+
+use crate::prelude::*;
+use crate::factor::FactorRef;
+use crate::input::CurrentOverlayType;
+use crate::map::{MapTileType, TileSpriteIndices};
+use crate::notification::notify;
+use crate::polity::PolityCapital;
+use crate::pops::{Language, PopLanguage};
+use crate::probability::binomial_isample;
+use crate::settlement::SettlementPops;
+
+// yearly share of a minority that takes up the faith of the local majority
+const LOCAL_CONVERSION_RATE: f32 = 0.02;
+// yearly share that takes up the faith of believers nearby
+const NEIGHBOUR_CONVERSION_RATE: f32 = 0.01;
+// other believers further away than this don't count
+const CONTACT_RADIUS: isize = 3;
+// yearly share that takes up the faith of their rulers
+const STATE_CONVERSION_RATE: f32 = 0.005;
+// yearly share drawn in by a holy site within its radius
+const HOLY_SITE_CONVERSION_RATE: f32 = 0.005;
+const HOLY_SITE_RADIUS: isize = 2;
+// yearly chance two big congregations in one settlement grow into a faith of their own
+const SYNCRETISM_CHANCE: f32 = 0.01;
+// both faiths need at least this share of a settlement to blend
+const SYNCRETISM_MIN_SHARE: f32 = 0.3;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, EnumIter)]
+pub enum Tenet {
+    Proselytizing,
+    Orthodox,
+    Syncretic,
+    Fertility,
+    Harvest,
+    Healing,
+}
+
+impl Tenet {
+    pub fn modifier(&self, factor: FactorType) -> f32 {
+        match (*self, factor) {
+            (Tenet::Fertility, FactorType::PopFertility) => 0.1,
+            (Tenet::Harvest, FactorType::PopHarvest) => 0.05,
+            (Tenet::Healing, FactorType::PopMortality) => -0.05,
+            _ => 0.0,
+        }
+    }
+
+    pub fn excludes(&self, other: Tenet) -> bool {
+        match (*self, other) {
+            (Tenet::Orthodox, Tenet::Syncretic) => true,
+            (Tenet::Syncretic, Tenet::Orthodox) => true,
+            _ => *self == other,
+        }
+    }
+}
+
+#[game_ref]
+pub struct ReligionRef(pub Entity);
+
+pub struct Religion {
+    pub name: String,
+    pub tenets: Vec<Tenet>,
+}
+
+// faiths that came out of two others remember where they came from
+pub struct ReligionOrigin {
+    pub parents: Vec<ReligionRef>,
+    pub founded: Date,
+}
+
+// a province sacred to a faith, drawing people to it
+pub struct HolySite(pub ReligionRef);
+
+// the faith a polity's rulers hold and push on their people
+pub struct StateReligion(pub ReligionRef);
+
+impl Religion {
+    pub fn new(name: String) -> Self {
+        let mut candidates = Tenet::iter().collect::<Vec<_>>();
+        candidates.shuffle(&mut thread_rng());
+        Self {
+            name,
+            tenets: Self::pick_tenets(candidates, 2),
+        }
+    }
+
+    // takes a tenet or two from each parent, but never refuses to mix
+    pub fn blend(name: String, a: &Religion, b: &Religion) -> Self {
+        let mut candidates = a.tenets.iter().chain(b.tenets.iter()).cloned().collect::<Vec<_>>();
+        candidates.shuffle(&mut thread_rng());
+        candidates.retain(|t| *t != Tenet::Orthodox);
+        Self {
+            name,
+            tenets: Self::pick_tenets(candidates, 3),
+        }
+    }
+
+    fn pick_tenets(candidates: Vec<Tenet>, max: usize) -> Vec<Tenet> {
+        let mut tenets: Vec<Tenet> = Vec::new();
+        for candidate in candidates.into_iter() {
+            if tenets.len() >= max {
+                break;
+            }
+            if !tenets.iter().any(|t| t.excludes(candidate)) {
+                tenets.push(candidate);
+            }
+        }
+        tenets
+    }
+
+    pub fn has_tenet(&self, tenet: Tenet) -> bool {
+        self.tenets.contains(&tenet)
+    }
+
+    pub fn modifier(&self, factor: FactorType) -> f32 {
+        self.tenets.iter().map(|t| t.modifier(factor)).sum()
+    }
+
+    // how hard believers push their faith on others
+    pub fn zeal(&self) -> f32 {
+        if self.has_tenet(Tenet::Proselytizing) { 1.5 } else { 1.0 }
+    }
+
+    // how readily believers give it up
+    pub fn convertibility(&self) -> f32 {
+        if self.has_tenet(Tenet::Orthodox) { 0.5 } else { 1.0 }
+    }
+
+    pub fn syncretism(&self) -> f32 {
+        if self.has_tenet(Tenet::Syncretic) { 2.0 } else { 1.0 }
+    }
+}
+
+impl ReligionRef {
+    pub fn name<'a>(&self, world: &'a World) -> &'a String {
+        &self.get::<Religion>(world).name
+    }
+}
+
+// a new faith named in the tongue of its first believers, holy wherever it began
+pub fn found_religion(world: &mut World, language: LanguageRef, site: Option<ProvinceRef>) -> ReligionRef {
+    let name = language.get::<Language>(world).generate_name(2);
+    let religion = ReligionRef(world.spawn().insert(Religion::new(name)).id());
+    if let Some(site) = site {
+        world.entity_mut(site.entity()).insert(HolySite(religion));
+    }
+    religion
+}
+
+fn conversion_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    pop_query: Query<(Entity, &Pop, &ReligionRef, &SettlementRef, &PolityRef)>,
+    settlement_query: Query<&MapCoordinate>,
+    religion_query: Query<&Religion>,
+    holy_site_query: Query<(&MapCoordinate, &HolySite)>,
+    polity_query: Query<(Entity, Option<&StateReligion>, Option<&PolityCapital>)>,
+) {
+    if !date.is_year {
+        return;
+    }
+    let mut settlement_faiths: HashMap<SettlementRef, HashMap<ReligionRef, isize>> = HashMap::new();
+    for (_, pop, &religion, &settlement, _) in pop_query.iter() {
+        *settlement_faiths.entry(settlement).or_default().entry(religion).or_insert(0) += pop.size;
+    }
+    let majority = |sizes: &HashMap<ReligionRef, isize>| {
+        sizes.iter().max_by_key(|(_, size)| **size).map(|(religion, size)| (*religion, *size))
+    };
+
+    // rulers take up the faith of their capital
+    for (polity, state_religion, capital) in polity_query.iter() {
+        if state_religion.is_some() {
+            continue;
+        }
+        let faith = capital
+            .and_then(|capital| settlement_faiths.get(&capital.0))
+            .and_then(|sizes| majority(sizes));
+        if let Some((religion, _)) = faith {
+            commands.entity(polity).insert(StateReligion(religion));
+        }
+    }
+
+    let coordinates = settlement_faiths
+        .keys()
+        .filter_map(|&settlement| settlement_query.get(settlement.entity()).ok().map(|&coord| (settlement, coord)))
+        .collect::<HashMap<_, _>>();
+    for (pop_ent, pop, &religion, &settlement, &polity) in pop_query.iter() {
+        let home = match coordinates.get(&settlement) {
+            Some(&coord) => coord,
+            None => continue,
+        };
+        let mut pressure: HashMap<ReligionRef, f32> = HashMap::new();
+        let local = &settlement_faiths[&settlement];
+        let local_total: isize = local.values().sum();
+        for (&other, &size) in local.iter() {
+            *pressure.entry(other).or_insert(0.0) += LOCAL_CONVERSION_RATE * size as f32 / local_total.max(1) as f32;
+        }
+        // believers nearby, weighted by how close and how many they are
+        let mut nearby: HashMap<ReligionRef, f32> = HashMap::new();
+        for (other_settlement, &coord) in coordinates.iter() {
+            let distance = home.distance(coord);
+            if *other_settlement == settlement || distance > CONTACT_RADIUS {
+                continue;
+            }
+            for (&other, &size) in settlement_faiths[other_settlement].iter() {
+                *nearby.entry(other).or_insert(0.0) += size as f32 / distance.max(1) as f32;
+            }
+        }
+        let nearby_total: f32 = nearby.values().sum();
+        for (other, weight) in nearby.into_iter() {
+            *pressure.entry(other).or_insert(0.0) += NEIGHBOUR_CONVERSION_RATE * weight / nearby_total.max(1.0);
+        }
+        if let Ok((_, Some(state_religion), _)) = polity_query.get(polity.entity()) {
+            *pressure.entry(state_religion.0).or_insert(0.0) += STATE_CONVERSION_RATE;
+        }
+        for (&coord, site) in holy_site_query.iter() {
+            if home.distance(coord) <= HOLY_SITE_RADIUS {
+                *pressure.entry(site.0).or_insert(0.0) += HOLY_SITE_CONVERSION_RATE;
+            }
+        }
+
+        // people hold on to their own faith as hard as it pulls on them
+        let own = pressure.remove(&religion).unwrap_or(0.0);
+        let target = pressure
+            .into_iter()
+            .map(|(other, rate)| {
+                let zeal = religion_query.get(other.entity()).map(|r| r.zeal()).unwrap_or(1.0);
+                (other, rate * zeal - own)
+            })
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        let (target, rate) = match target {
+            Some((target, rate)) if rate > 0.0 => (target, rate),
+            _ => continue,
+        };
+        let convertibility = religion_query.get(religion.entity()).map(|r| r.convertibility()).unwrap_or(1.0);
+        let amount = binomial_isample(pop.size, rate * convertibility);
+        if amount > 0 {
+            commands.add(PopConvertFaithCommand {
+                pop: PopRef(pop_ent),
+                amount,
+                religion: target,
+            });
+        }
+    }
+
+    for (&settlement, sizes) in settlement_faiths.iter() {
+        let total: isize = sizes.values().sum();
+        let mut ranked = sizes.iter().map(|(r, s)| (*r, *s)).collect::<Vec<_>>();
+        ranked.sort_by_key(|(_, size)| -*size);
+        if ranked.len() < 2 || total <= 0 {
+            continue;
+        }
+        let syncretism = ranked[..2]
+            .iter()
+            .map(|(religion, _)| religion_query.get(religion.entity()).map(|r| r.syncretism()).unwrap_or(1.0))
+            .product::<f32>();
+        let big_enough = ranked[1].1 as f32 / total as f32 >= SYNCRETISM_MIN_SHARE;
+        if big_enough && individual_event(SYNCRETISM_CHANCE * syncretism) {
+            commands.add(BlendReligionsCommand {
+                settlement,
+                a: ranked[0].0,
+                b: ranked[1].0,
+            });
+        }
+    }
+}
+
+// move people from a pop to one of another faith in the same settlement, founding it if needed
+pub struct PopConvertFaithCommand {
+    pub pop: PopRef,
+    pub amount: isize,
+    pub religion: ReligionRef,
+}
+
+impl Command for PopConvertFaithCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        self.pop.convert_people(world, self.amount, None, None, Some(self.religion));
+    }
+}
+
+// two faiths worshipped side by side grow into a new one, or into the one they grew into before
+pub struct BlendReligionsCommand {
+    pub settlement: SettlementRef,
+    pub a: ReligionRef,
+    pub b: ReligionRef,
+}
+
+impl Command for BlendReligionsCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let pops = self.settlement.get::<SettlementPops>(world).0.clone();
+        let language = match pops.iter().max_by_key(|pop| pop.get::<Pop>(world).size) {
+            Some(pop) => pop.get::<PopLanguage>(world).language,
+            None => return,
+        };
+        // a pair that has blended before takes up the faith it made then
+        let existing = world
+            .query::<(Entity, &ReligionOrigin)>()
+            .iter(world)
+            .find(|(_, origin)| origin.parents.len() == 2 && origin.parents.contains(&self.a) && origin.parents.contains(&self.b))
+            .map(|(ent, _)| ReligionRef(ent));
+        let (religion, text) = match existing {
+            Some(religion) => (religion, None),
+            None => {
+                let name = language.get::<Language>(world).generate_name(2);
+                let blended = Religion::blend(name, self.a.get::<Religion>(world), self.b.get::<Religion>(world));
+                let text = format!("{} and {} blend into {}", self.a.name(world), self.b.name(world), blended.name);
+                let founded = world.get_resource::<CurrentDate>().unwrap().date;
+                let religion = ReligionRef(
+                    world
+                        .spawn()
+                        .insert(blended)
+                        .insert(ReligionOrigin {
+                            parents: vec![self.a, self.b],
+                            founded,
+                        })
+                        .id()
+                );
+                // a place already holy to an older faith stays that faith's
+                let province = *self.settlement.get::<ProvinceRef>(world);
+                if province.try_get::<HolySite>(world).is_none() {
+                    world.entity_mut(province.entity()).insert(HolySite(religion));
+                }
+                (religion, Some(text))
+            },
+        };
+        let (a, b) = (self.a, self.b);
+        self.settlement.blend_people(
+            world,
+            |world, pop| {
+                let pop_religion = *pop.get::<ReligionRef>(world);
+                pop_religion == a || pop_religion == b
+            },
+            None,
+            None,
+            Some(religion),
+        );
+        if let Some(text) = text {
+            notify(world, text);
+        }
+    }
+}
+
+// each faith gets its own hue, brighter where it's held more widely
+fn religion_color(religion: ReligionRef, share: f32) -> Color {
+    let hue = (religion.0.id() * 71 % 360) as f32;
+    Color::hsl(hue, 0.3 + 0.6 * share, 0.5)
+}
+
+pub fn religion_overlay_system(
+    mut frame: Local<isize>,
+    tile_sprite_indices: Res<TileSpriteIndices>,
+    pop_query: Query<(&Pop, &ReligionRef, &SettlementRef)>,
+    settlement_query: Query<&MapCoordinate>,
+    current_overlay: Res<CurrentOverlayType>,
+    mut tile_map_query: Query<&mut Tilemap>,
+) {
+    *frame += 1;
+    if *frame % 20 == 0 && *current_overlay == CurrentOverlayType::Religion {
+        // colour each settlement by its most held faith
+        let mut faiths: HashMap<SettlementRef, HashMap<ReligionRef, isize>> = HashMap::new();
+        for (pop, &religion, &settlement) in pop_query.iter() {
+            *faiths.entry(settlement).or_default().entry(religion).or_insert(0) += pop.size;
+        }
+        for (settlement, sizes) in faiths.iter() {
+            let total: isize = sizes.values().sum();
+            let (religion, size) = match sizes.iter().max_by_key(|(_, size)| **size) {
+                Some((&religion, &size)) => (religion, size),
+                None => continue,
+            };
+            if let Ok(coordinate) = settlement_query.get(settlement.0) {
+                let color = religion_color(religion, size as f32 / total.max(1) as f32);
+                let point = coordinate.point3();
+                for mut tile_map in tile_map_query.iter_mut() {
+                    let mut tile = tile_map.get_tile_mut(point, 0).unwrap();
+                    tile.color = color;
+                    let sprite_index = *tile_sprite_indices.0.get(&MapTileType::None).unwrap();
+                    tile.index = sprite_index;
+                }
+            }
+        }
+    }
+}
+
+pub struct ReligionPlugin;
+
+impl Plugin for ReligionPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_system_to_day(conversion_system.system())
+            .add_system(religion_overlay_system.system());
+    }
+}
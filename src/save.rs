@@ -1,10 +1,12 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
 use bevy::prelude::*;
 use bevy::ecs::system::Command;
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
 
 use crate::prelude::*;
+use crate::time::{DeferredCommands, DeferredSaveData};
 
 use super::map::*;
 
@@ -17,6 +19,14 @@ pub struct MapEntitySaveData {
     pub districts: Option<Districts>,
 }
 
+// the map along with the date and the work scheduled from it, fresh maps are only the entities
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GameSaveData {
+    pub date: Date,
+    pub schedule: Vec<DeferredSaveData>,
+    pub entities: Vec<MapEntitySaveData>,
+}
+
 /// Saves teh world, one entity at a time
 impl Command for SaveMapCommand {
     fn write(
@@ -25,6 +35,8 @@ impl Command for SaveMapCommand {
     ) {
         let save_file_name = "map.ron";
         let mut file = File::create(save_file_name).unwrap();
+        let date = world.get_resource::<CurrentDate>().unwrap().date;
+        let schedule = world.get_resource::<DeferredCommands>().unwrap().save_data();
         let mut entities = Vec::new();
         for ent in world.query::<Entity>().iter(world) {
             macro_rules! component {
@@ -44,7 +56,7 @@ impl Command for SaveMapCommand {
             };
             entities.push(esd);
         }
-        let json = serde_json::to_string(&entities).unwrap();
+        let json = serde_json::to_string(&GameSaveData { date, schedule, entities }).unwrap();
         file.write_all(json.as_bytes()).unwrap();
     }
 }
@@ -58,3 +70,57 @@ pub fn load_map_system(
     }
     load_map.0 = Some("map.ron".to_string());
 }
+
+// commands that can be written into a save, and read back by name through the CommandRegistry
+pub trait SaveableCommand: Command + Serialize + DeserializeOwned {
+    const NAME: &'static str;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedCommand {
+    pub name: String,
+    pub data: serde_json::Value,
+}
+
+impl SavedCommand {
+    pub fn of<T: SaveableCommand>(command: &T) -> Self {
+        Self {
+            name: T::NAME.to_owned(),
+            data: serde_json::to_value(command).unwrap(),
+        }
+    }
+}
+
+fn build_command<T: SaveableCommand>(data: serde_json::Value) -> serde_json::Result<Box<dyn Command>> {
+    Ok(Box::new(serde_json::from_value::<T>(data)?))
+}
+
+// every command that can be saved, by name
+#[derive(Default)]
+pub struct CommandRegistry(HashMap<&'static str, fn(serde_json::Value) -> serde_json::Result<Box<dyn Command>>>);
+
+impl CommandRegistry {
+    pub fn register<T: SaveableCommand>(&mut self) {
+        self.0.insert(T::NAME, build_command::<T>);
+    }
+
+    pub fn build(&self, saved: &SavedCommand) -> Option<Box<dyn Command>> {
+        let build = self.0.get(saved.name.as_str())?;
+        build(saved.data.clone())
+            .map_err(|e| eprintln!("error loading command {}: {}", saved.name, e))
+            .ok()
+    }
+}
+
+pub trait CommandRegistryBuilder {
+    fn register_command<T: SaveableCommand>(&mut self) -> &mut AppBuilder;
+}
+
+impl CommandRegistryBuilder for AppBuilder {
+    fn register_command<T: SaveableCommand>(&mut self) -> &mut AppBuilder {
+        self.world_mut()
+            .get_resource_or_insert_with(CommandRegistry::default)
+            .register::<T>();
+        self
+    }
+}
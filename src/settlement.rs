@@ -7,7 +7,14 @@ use crate::province::*;
 use crate::factor::*;
 use crate::stage::DayStage;
 use crate::time::Date;
+use crate::fortification::Fortifications;
+use crate::unrest::Grievances;
+use crate::technology::Knowledge;
+use crate::buildings::Buildings;
+use crate::urbanization::SettlementTier;
+use crate::agriculture::Fields;
 use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct District {
@@ -54,6 +61,79 @@ impl SettlementRef {
     pub fn add_pop(&self, world: &mut World, pop: PopRef) {
         world.get_mut::<SettlementPops>(self.0).unwrap().add_pop(pop);
     }
+
+    pub fn culture_sizes(&self, world: &World) -> HashMap<CultureRef, isize> {
+        let mut sizes = HashMap::new();
+        for pop in self.get::<SettlementPops>(world).0.iter() {
+            *sizes.entry(*pop.get::<CultureRef>(world)).or_insert(0) += pop.get::<Pop>(world).size;
+        }
+        sizes
+    }
+
+    // people arriving or converting join kin of the same culture, language and faith, or become a new pop
+    pub fn settle_people(&self, world: &mut World, culture: CultureRef, language: LanguageRef, religion: ReligionRef, ages: AgeCohorts, storage: GoodStorage, fields: Option<Fields>) -> PopRef {
+        let kin = self
+            .get::<SettlementPops>(world)
+            .0
+            .iter()
+            .find(|p| {
+                *p.get::<CultureRef>(world) == culture
+                    && p.get::<PopLanguage>(world).language == language
+                    && *p.get::<ReligionRef>(world) == religion
+            })
+            .cloned();
+        if let Some(kin) = kin {
+            kin.add_people(world, &ages, storage, fields);
+            kin
+        } else {
+            let province = *self.get::<ProvinceRef>(world);
+            let polity = *self.get::<PolityRef>(world);
+            let pop = SpawnPopCommand {
+                province,
+                settlement: *self,
+                language,
+                culture,
+                religion,
+                size: ages.size(),
+                polity,
+                ages: Some(ages),
+            }.spawn(world);
+            pop.get_mut::<GoodStorage>(world).merge(storage);
+            if let Some(fields) = fields {
+                pop.get_mut::<Fields>(world).merge(fields);
+            }
+            pop
+        }
+    }
+
+    // a third of each pop `joins` picks out takes up a new culture, language or faith
+    pub fn blend_people(
+        &self,
+        world: &mut World,
+        joins: impl Fn(&World, PopRef) -> bool,
+        culture: Option<CultureRef>,
+        language: Option<LanguageRef>,
+        religion: Option<ReligionRef>,
+    ) {
+        let converts = self
+            .get::<SettlementPops>(world)
+            .0
+            .iter()
+            .filter(|p| joins(world, **p))
+            .map(|p| (*p, p.get::<Pop>(world).size / 3))
+            .filter(|(_, amount)| *amount > 0)
+            .collect::<Vec<_>>();
+        for (pop, amount) in converts.into_iter() {
+            pop.convert_people(world, amount, culture, language, religion);
+        }
+    }
+
+    pub fn majority_culture(&self, world: &World) -> Option<CultureRef> {
+        self.culture_sizes(world)
+            .into_iter()
+            .max_by_key(|(_, size)| *size)
+            .map(|(culture, _)| culture)
+    }
 }
 
 #[derive(Bundle)]
@@ -63,6 +143,11 @@ pub struct SettlementBundle {
     pub province: ProvinceRef,
     pub polity: PolityRef,
     pub coordinate: MapCoordinate,
+    pub fortifications: Fortifications,
+    pub grievances: Grievances,
+    pub knowledge: Knowledge,
+    pub buildings: Buildings,
+    pub tier: SettlementTier,
 }
 
 fn settlement_info_system(
@@ -0,0 +1,303 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{ecs::system::Command, prelude::*};
+use rand::{prelude::SliceRandom, thread_rng};
+use strum::{EnumIter, IntoEnumIterator};
+
+use crate::prelude::*;
+use crate::buildings::{BuildingKind, Buildings};
+use crate::decision::PlayerPolity;
+use crate::diplomacy::{Relations, TreatyKind};
+use crate::factor::FST;
+use crate::formula::FormulaSystem;
+use crate::fortification::coastal;
+use crate::notification::notify;
+use crate::polity::PolityCapital;
+use crate::pops::{AgeCohorts, GoodStorage, GoodType, MONTHLY_CALORIE_NEED};
+use crate::settlement::{Settlement, SettlementPops};
+use crate::urbanization::Occupations;
+
+// yearly chance a settlement of a thousand people with no contacts or knowledge works out the easiest innovation
+const BASE_DISCOVERY_CHANCE: f32 = 0.002;
+// each thing already known makes the next easier to find
+const KNOWLEDGE_BONUS: f32 = 0.25;
+// each settlement in contact brings new ideas
+const CONTACT_BONUS: f32 = 0.1;
+// settlements further away than this don't talk
+const CONTACT_RADIUS: isize = 4;
+// yearly chance an innovation known next door is picked up, less with distance
+const NEIGHBOUR_DIFFUSION_CHANCE: f32 = 0.2;
+// yearly chance it spreads from the capital through the realm
+const POLITY_DIFFUSION_CHANCE: f32 = 0.15;
+// yearly chance it comes with traders from a partner polity
+const TRADE_DIFFUSION_CHANCE: f32 = 0.1;
+// yearly food each fishing worker brings in, a tenth of them go out in boats
+const FISHING_CALORIES_PER_WORKER: f32 = MONTHLY_CALORIE_NEED * 12.0;
+const FISHING_SHARE: f32 = 0.1;
+const HARBOR_CATCH: f32 = 2.0;
+// yearly food an artisan turns out working a craft they've learned
+const CRAFT_CALORIES_PER_ARTISAN: f32 = MONTHLY_CALORIE_NEED * 6.0;
+// iron tools help the farmers along this much more than bronze and wood
+const IRON_TOOLS: f32 = 2.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, EnumIter)]
+pub enum Technology {
+    Irrigation,
+    Plough,
+    Masonry,
+    IronWorking,
+    Sailing,
+}
+
+impl Technology {
+    pub fn prerequisites(&self) -> &'static [Technology] {
+        match self {
+            Technology::Plough => &[Technology::Irrigation],
+            Technology::IronWorking => &[Technology::Masonry],
+            _ => &[],
+        }
+    }
+
+    // how much harder than the easiest this is to work out
+    pub fn difficulty(&self) -> f32 {
+        match self {
+            Technology::Irrigation => 1.0,
+            Technology::Masonry => 1.0,
+            Technology::Sailing => 1.5,
+            Technology::Plough => 2.0,
+            Technology::IronWorking => 3.0,
+        }
+    }
+
+    // more mouths fed where it's known
+    pub fn carrying_capacity(&self, coastal: bool) -> f32 {
+        match self {
+            Technology::Irrigation => 30.0,
+            Technology::Plough => 20.0,
+            Technology::Sailing if coastal => 10.0,
+            _ => 0.0,
+        }
+    }
+
+    // the good artisans can make once it's known, watered vineyards give wine and stone presses oil
+    pub fn craft(&self) -> Option<GoodType> {
+        match self {
+            Technology::Irrigation => Some(GoodType::Wine),
+            Technology::Masonry => Some(GoodType::OliveOil),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Technology::Irrigation => "irrigation",
+            Technology::Plough => "the plough",
+            Technology::Masonry => "masonry",
+            Technology::IronWorking => "iron working",
+            Technology::Sailing => "sailing",
+        }
+    }
+}
+
+// what the people of a settlement know how to do
+#[derive(Debug, Default)]
+pub struct Knowledge(pub HashSet<Technology>);
+
+impl Knowledge {
+    pub fn knows(&self, technology: Technology) -> bool {
+        self.0.contains(&technology)
+    }
+
+    // how much the artisans' tools are worth
+    pub fn tool_quality(&self) -> f32 {
+        if self.knows(Technology::IronWorking) { IRON_TOOLS } else { 1.0 }
+    }
+
+    pub fn can_learn(&self, technology: Technology) -> bool {
+        !self.knows(technology) && technology.prerequisites().iter().all(|t| self.knows(*t))
+    }
+}
+
+impl SettlementRef {
+    pub fn knows(&self, world: &World, technology: Technology) -> bool {
+        self.try_get::<Knowledge>(world).map(|knowledge| knowledge.knows(technology)).unwrap_or(false)
+    }
+}
+
+// a settlement picks up an innovation, by working it out or from someone else
+pub struct LearnTechnologyCommand {
+    pub settlement: SettlementRef,
+    pub technology: Technology,
+    pub discovered: bool,
+}
+
+impl Command for LearnTechnologyCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        if world.get_entity(self.settlement.entity()).is_none() {
+            return;
+        }
+        match self.settlement.try_get_mut::<Knowledge>(world) {
+            Some(mut knowledge) if knowledge.can_learn(self.technology) => {
+                knowledge.0.insert(self.technology);
+            },
+            _ => return,
+        }
+        let coastal = coastal(world, *self.settlement.get::<MapCoordinate>(world));
+        world
+            .get_resource::<FormulaSystem<FST>>()
+            .unwrap()
+            .add_factor(&self.settlement.fst(FactorType::SettlementCarryingCapacity), self.technology.carrying_capacity(coastal));
+        let polity = *self.settlement.get::<PolityRef>(world);
+        let player = world.get_resource::<PlayerPolity>().unwrap().0;
+        if self.discovered || player == Some(polity) {
+            let text = format!(
+                "{} {} {}",
+                self.settlement.get::<Settlement>(world).name,
+                if self.discovered { "discovers" } else { "learns" },
+                self.technology.name(),
+            );
+            notify(world, text);
+        }
+    }
+}
+
+// innovations are worked out where there are many people, much known and many to talk to,
+// and spread to neighbours, through the realm, and along trade routes
+fn innovation_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    relations: Res<Relations>,
+    settlement_query: Query<(Entity, &Settlement, &Knowledge, &MapCoordinate, &PolityRef)>,
+    capital_query: Query<&PolityCapital>,
+) {
+    if !date.is_year {
+        return;
+    }
+    let settlements = settlement_query.iter().collect::<Vec<_>>();
+    let mut polity_knowledge: HashMap<PolityRef, HashSet<Technology>> = HashMap::new();
+    for &(_, _, knowledge, _, &polity) in settlements.iter() {
+        polity_knowledge.entry(polity).or_default().extend(knowledge.0.iter());
+    }
+    for &(ent, info, knowledge, &coord, &polity) in settlements.iter() {
+        let settlement = SettlementRef(ent);
+        // chances to pick up each innovation from someone who knows it
+        let mut chances: HashMap<Technology, f32> = HashMap::new();
+        let mut contacts = 0;
+        for &(other, _, other_knowledge, &other_coord, _) in settlements.iter() {
+            let distance = coord.distance(other_coord);
+            if other == ent || distance > CONTACT_RADIUS {
+                continue;
+            }
+            contacts += 1;
+            for &technology in other_knowledge.0.iter() {
+                let chance = chances.entry(technology).or_insert(0.0);
+                *chance = chance.max(NEIGHBOUR_DIFFUSION_CHANCE / distance.max(1) as f32);
+            }
+        }
+        if let Some(capital) = capital_query.get(polity.entity()).ok().map(|capital| capital.0) {
+            if let Ok((_, _, capital_knowledge, _, _)) = settlement_query.get(capital.entity()) {
+                for &technology in capital_knowledge.0.iter() {
+                    let chance = chances.entry(technology).or_insert(0.0);
+                    *chance = chance.max(POLITY_DIFFUSION_CHANCE);
+                }
+            }
+        }
+        for treaty in relations.treaties_of(polity).filter(|treaty| treaty.kind == TreatyKind::TradeAccess) {
+            let partner = if treaty.a == polity { treaty.b } else { treaty.a };
+            contacts += 1;
+            for &technology in polity_knowledge.get(&partner).into_iter().flatten() {
+                let chance = chances.entry(technology).or_insert(0.0);
+                *chance = chance.max(TRADE_DIFFUSION_CHANCE);
+            }
+        }
+
+        let mut learnable = Technology::iter().filter(|t| knowledge.can_learn(*t)).collect::<Vec<_>>();
+        learnable.shuffle(&mut thread_rng());
+        // only one new thing a year takes hold
+        let learned = learnable
+            .iter()
+            .find(|technology| individual_event(chances.get(technology).cloned().unwrap_or(0.0)));
+        if let Some(&technology) = learned {
+            commands.add(LearnTechnologyCommand { settlement, technology, discovered: false });
+            continue;
+        }
+        let inventiveness = info.population as f32 / 1000.0
+            * (1.0 + KNOWLEDGE_BONUS * knowledge.0.len() as f32)
+            * (1.0 + CONTACT_BONUS * contacts as f32);
+        let discovered = learnable
+            .iter()
+            .find(|technology| individual_event(BASE_DISCOVERY_CHANCE * inventiveness / technology.difficulty()));
+        if let Some(&technology) = discovered {
+            commands.add(LearnTechnologyCommand { settlement, technology, discovered: true });
+        }
+    }
+}
+
+// coastal settlements that know how to sail send some of their people out to fish
+pub struct FishingCommand(pub SettlementRef);
+
+impl Command for FishingCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        if !coastal(world, *self.0.get::<MapCoordinate>(world)) {
+            return;
+        }
+        // boats go further out from a harbor
+        let harbor = self.0.try_get::<Buildings>(world).map(|b| b.has(BuildingKind::Harbor)).unwrap_or(false);
+        let harbor = if harbor { HARBOR_CATCH } else { 1.0 };
+        for pop in self.0.get::<SettlementPops>(world).0.clone().into_iter() {
+            let catch = pop.get::<AgeCohorts>(world).working() as f32 * FISHING_SHARE * FISHING_CALORIES_PER_WORKER * harbor;
+            pop.get_mut::<GoodStorage>(world).add(GoodType::Fish, catch / GoodType::Fish.base_satiety().base);
+        }
+    }
+}
+
+fn fishing_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    settlement_query: Query<(Entity, &Knowledge)>,
+) {
+    if !date.is_year {
+        return;
+    }
+    for (settlement, knowledge) in settlement_query.iter() {
+        if knowledge.knows(Technology::Sailing) {
+            commands.add(FishingCommand(SettlementRef(settlement)));
+        }
+    }
+}
+
+// artisans split their time between the crafts their settlement knows
+fn crafting_system(
+    date: Res<CurrentDate>,
+    settlement_query: Query<(&Knowledge, &SettlementPops)>,
+    mut pop_query: Query<(&AgeCohorts, &Occupations, &mut GoodStorage)>,
+) {
+    if !date.is_year {
+        return;
+    }
+    for (knowledge, pops) in settlement_query.iter() {
+        let crafts = knowledge.0.iter().filter_map(|t| t.craft()).collect::<Vec<_>>();
+        if crafts.is_empty() {
+            continue;
+        }
+        for pop in pops.0.iter() {
+            if let Ok((ages, occupations, mut storage)) = pop_query.get_mut(pop.entity()) {
+                let calories = ages.working() as f32 * occupations.artisans * CRAFT_CALORIES_PER_ARTISAN / crafts.len() as f32;
+                for good in crafts.iter() {
+                    storage.add(*good, calories / good.base_satiety().base);
+                }
+            }
+        }
+    }
+}
+
+pub struct TechnologyPlugin;
+
+impl Plugin for TechnologyPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_system_to_day(innovation_system.system())
+            .add_system_to_day(fishing_system.system())
+            .add_system_to_day(crafting_system.system());
+    }
+}
@@ -1,8 +1,11 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
-use bevy::{core::FixedTimesteps, ecs::{schedule::ShouldRun, system::Command}, prelude::*};
+use bevy::{ecs::{schedule::ShouldRun, system::Command}, prelude::*};
 
-use crate::{constant::{DAY_LABEL, DAY_TIMESTEP}, stage::DayStage, tag::DateDisplay};
+use serde::{Serialize, Deserialize};
+
+use crate::save::{CommandRegistry, SaveableCommand, SavedCommand};
+use crate::{calendar::{CALENDAR, Month, Season}, constant::DAY_LABEL, stage::{DayStage, DayStageBuilder}};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TimeEvent {
@@ -12,13 +15,23 @@ pub enum TimeEvent {
     Year,
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Date {
     pub day: usize,
     pub month: usize,
     pub year: usize,
 }
 
+impl Default for Date {
+    fn default() -> Self {
+        Self {
+            day: 1,
+            month: 1,
+            year: 0,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
 pub struct CurrentDate {
     pub date: Date,
@@ -32,7 +45,7 @@ impl CurrentDate {
     pub fn next_day(&mut self) {
         self.date.next_day();
         self.is_day = true;
-        self.is_week = self.date.day % 7 == 0;
+        self.is_week = self.date.abs_day() % 7 == 0;
         self.is_month = self.date.day == 1;
         self.is_year = self.date.day == 1 && self.date.month == 1;
     }
@@ -46,37 +59,31 @@ impl std::fmt::Display for CurrentDate {
 
 impl Date {
     pub fn next_day(&mut self) {
-        self.day += 1;
-        if self.day > 30 {
-            self.month += 1;
-            self.day = 1;
-            if self.month > 12 {
-                self.month = 1;
-                self.year += 1;
-            }
-        }
+        *self = CALENDAR.next_day(*self);
     }
 
+    pub fn month_name(&self) -> Month {
+        Month::from_number(self.month).unwrap()
+    }
+
+    pub fn season(&self) -> Season {
+        self.month_name().season()
+    }
+
+    // days since the day of year last came around
     pub fn days_after_doy(&self, day_of_year: DayOfYear) -> usize {
-        let mut res = (self.month as isize - day_of_year.month as isize) * 30 + (self.day as isize - day_of_year.day as isize);
-        if res < 0 {
-            res += 360;
-            if res < 0 {
-                panic!("failed to diff dates: {:?} {:?}", self, day_of_year);
-            } else {
-                res as usize
-            }
-        } else {
-            res as usize
-        }
+        let other = Date { day: day_of_year.day, month: day_of_year.month, year: self.year };
+        let diff = CALENDAR.day_of_year(*self) as isize - CALENDAR.day_of_year(other) as isize;
+        diff.rem_euclid(CALENDAR.days_in_year() as isize) as usize
     }
 
+    // days since the first day of year 0
     pub fn abs_day(&self) -> usize {
-        self.day + (self.month + self.year * 12) * 30
+        CALENDAR.to_abs(*self)
     }
 
     pub fn is_after(&self, other: Date) -> bool {
-        self.abs_day() > other.abs_day()
+        *self > other
     }
 
     pub fn is_day_of_year(&self, day_of_year: DayOfYear) -> bool {
@@ -87,21 +94,58 @@ impl Date {
         Self::from_abs(self.abs_day() + days)
     }
 
-    pub fn from_abs(abs: usize) -> Self {
-        let year = abs / 360;
-        let month = (abs - year / 360) / 30;
-        let day = abs % 30;
+    pub fn days_since(&self, earlier: Date) -> usize {
+        self.abs_day().saturating_sub(earlier.abs_day())
+    }
+
+    // every year has the same months, so the same day comes around again
+    pub fn years_after(&self, years: usize) -> Self {
         Self {
-            year,
-            month,
-            day,
+            year: self.year + years,
+            ..*self
         }
     }
+
+    pub fn years_before(&self, years: usize) -> Self {
+        Self {
+            year: self.year.saturating_sub(years),
+            ..*self
+        }
+    }
+
+    // whole years gone by, as for an age
+    pub fn years_since(&self, earlier: Date) -> usize {
+        if *self < earlier {
+            return 0;
+        }
+        let years = self.year - earlier.year;
+        if (self.month, self.day) < (earlier.month, earlier.day) {
+            years - 1
+        } else {
+            years
+        }
+    }
+
+    pub fn from_abs(abs: usize) -> Self {
+        CALENDAR.from_abs(abs)
+    }
+}
+
+impl Ord for Date {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.year, self.month, self.day).cmp(&(other.year, other.month, other.day))
+    }
+}
+
+impl PartialOrd for Date {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 impl std::fmt::Display for Date {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!("{:02}/{:02}/{:02}", self.month, self.day, self.year))
+        f.write_fmt(format_args!("{} {:?}, year {}", self.day, self.month_name(), self.year))
     }
 }
 
@@ -111,59 +155,288 @@ pub struct DayOfYear {
     pub month: usize,
 }
 
+// days run each second of real time at each game speed, the last as many as the frame budget allows
+pub const GAME_SPEEDS: [f32; 9] = [1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, f32::INFINITY];
+// how quickly the measured speed follows the real one
+const RATE_SMOOTHING: f32 = 0.05;
+
+// days actually run per second, measured over the last frames
+#[derive(Default)]
 pub struct DatesPerSecond(pub f32);
+// most days run in a single frame, so the game stays responsive however fast it goes
+pub struct MaxDaysPerFrame(pub usize);
+// index into GAME_SPEEDS
 pub struct GameSpeed(pub usize);
 pub struct GamePaused(pub bool);
 
-fn time_system(
-    mut frame: Local<usize>,
-    mut date: ResMut<CurrentDate>,
+impl GameSpeed {
+    pub fn days_per_second(&self) -> f32 {
+        GAME_SPEEDS[self.0.min(GAME_SPEEDS.len() - 1)]
+    }
+
+    pub fn is_fastest(&self) -> bool {
+        self.days_per_second().is_infinite()
+    }
+
+    pub fn faster(&mut self) {
+        self.0 = (self.0 + 1).min(GAME_SPEEDS.len() - 1);
+    }
+
+    pub fn slower(&mut self) {
+        self.0 = self.0.saturating_sub(1);
+    }
+
+    pub fn fastest(&mut self) {
+        self.0 = GAME_SPEEDS.len() - 1;
+    }
+
+    // for the date display, with the measured speed when the frame budget holds it back
+    pub fn label(&self, paused: &GamePaused, measured: &DatesPerSecond) -> String {
+        if paused.0 {
+            "paused".to_owned()
+        } else if self.is_fastest() {
+            format!("max, {:.0} days/s", measured.0)
+        } else if measured.0 < self.days_per_second() * 0.9 {
+            format!("{} days/s, running {:.0}", self.days_per_second(), measured.0)
+        } else {
+            format!("{} days/s", self.days_per_second())
+        }
+    }
+}
+
+// days owed to the game by the real time that's passed
+#[derive(Default)]
+pub struct DayClock {
+    owed: f32,
+    // days run this frame
+    run: usize,
+}
+
+// every frame, time passed at the game speed adds days to be run in the day stage
+fn day_clock_system(
     time: Res<Time>,
     game_speed: Res<GameSpeed>,
-    mut date_texts: Query<(&DateDisplay, &mut Text)>,
     game_paused: Res<GamePaused>,
+    max_days: Res<MaxDaysPerFrame>,
+    mut clock: ResMut<DayClock>,
+    mut measured: ResMut<DatesPerSecond>,
+    mut date: ResMut<CurrentDate>,
 ) {
-    *frame = *frame + 1;
+    let delta = time.delta_seconds();
+    if delta > 0.0 {
+        measured.0 += (clock.run as f32 / delta - measured.0) * RATE_SMOOTHING;
+    }
+    clock.run = 0;
+    // only systems in the day stage see a new day
     date.is_day = false;
     date.is_week = false;
     date.is_month = false;
     date.is_year = false;
-    // println!("{}", *frame);
-    if !game_paused.0 && *frame % 2usize.pow(11 - game_speed.0 as u32) == 0 {
-        date.next_day();
-        if date.is_year {
-            println!("next year! {}", *date);
-        }
+    if game_paused.0 {
+        clock.owed = 0.0;
+        return;
+    }
+    // days that don't fit in the budget are dropped rather than piling up
+    clock.owed = if game_speed.is_fastest() {
+        max_days.0 as f32
+    } else {
+        (clock.owed + delta * game_speed.days_per_second()).min(max_days.0 as f32)
+    };
+}
 
+// runs the day stage once for each day owed
+pub fn day_run_criteria_system(
+    game_paused: Res<GamePaused>,
+    mut clock: ResMut<DayClock>,
+) -> ShouldRun {
+    if !game_paused.0 && clock.owed >= 1.0 {
+        clock.owed -= 1.0;
+        clock.run += 1;
+        ShouldRun::YesAndCheckAgain
+    } else {
+        ShouldRun::No
+    }
+}
+
+fn time_system(
+    mut date: ResMut<CurrentDate>,
+) {
+    date.next_day();
+    if date.is_year {
+        println!("next year! {}", *date);
+    }
+}
 
-        for (_, mut text) in date_texts.iter_mut() {
-            text.sections[0].value = format!("{}", *date);
+// kept to cancel scheduled work with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ScheduleHandle(u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Recurrence {
+    Once,
+    EveryDays(usize),
+    // on this day of each month, or the last day of shorter months
+    Monthly(usize),
+    Yearly,
+}
+
+impl Recurrence {
+    pub fn next(&self, date: Date) -> Option<Date> {
+        match self {
+            Recurrence::Once => None,
+            Recurrence::EveryDays(days) => Some(date.days_after((*days).max(1))),
+            Recurrence::Monthly(day) => {
+                let (month, year) = if date.month < CALENDAR.months() { (date.month + 1, date.year) } else { (1, date.year + 1) };
+                Some(Date { day: (*day).max(1).min(CALENDAR.days_in_month(month)), month, year })
+            },
+            Recurrence::Yearly => Some(date.years_after(1)),
         }
-    // } else {
-    //     println!("not next day! {:?}", *date);
     }
 }
 
-pub struct DeferredCommands(HashMap<Date, Vec<Box<dyn Command>>>);
+enum DeferredWork {
+    // only lives as long as the session
+    Boxed(Box<dyn Command>),
+    // rebuilt through the CommandRegistry each time it runs, so it can repeat and be saved
+    Saved(SavedCommand),
+}
+
+struct Deferred {
+    handle: ScheduleHandle,
+    recurrence: Recurrence,
+    work: DeferredWork,
+}
+
+// one piece of scheduled work as it's written into a save
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeferredSaveData {
+    pub handle: ScheduleHandle,
+    pub date: Date,
+    pub recurrence: Recurrence,
+    pub command: SavedCommand,
+}
+
+// commands waiting for their date, run at the start of that day
+#[derive(Default)]
+pub struct DeferredCommands {
+    next_handle: u64,
+    scheduled: BTreeMap<Date, Vec<Deferred>>,
+}
 
 impl DeferredCommands {
-    pub fn add(&mut self, date: Date, command: Box<dyn Command>) {
-        if !self.0.contains_key(&date) {
-            self.0.insert(date, vec![command]);
-        } else {
-            self.0.get_mut(&date).unwrap().push(command);
+    fn schedule(&mut self, date: Date, recurrence: Recurrence, work: DeferredWork) -> ScheduleHandle {
+        let handle = ScheduleHandle(self.next_handle);
+        self.next_handle += 1;
+        self.scheduled.entry(date).or_default().push(Deferred { handle, recurrence, work });
+        handle
+    }
+
+    pub fn add(&mut self, date: Date, command: Box<dyn Command>) -> ScheduleHandle {
+        self.schedule(date, Recurrence::Once, DeferredWork::Boxed(command))
+    }
+
+    pub fn add_saved<T: SaveableCommand>(&mut self, date: Date, command: &T) -> ScheduleHandle {
+        self.repeat(date, command, Recurrence::Once)
+    }
+
+    pub fn repeat<T: SaveableCommand>(&mut self, date: Date, command: &T, recurrence: Recurrence) -> ScheduleHandle {
+        self.schedule(date, recurrence, DeferredWork::Saved(SavedCommand::of(command)))
+    }
+
+    // false if it already ran or was never scheduled
+    pub fn cancel(&mut self, handle: ScheduleHandle) -> bool {
+        let mut found = false;
+        for deferred in self.scheduled.values_mut() {
+            let before = deferred.len();
+            deferred.retain(|d| d.handle != handle);
+            found |= deferred.len() != before;
+        }
+        self.scheduled.retain(|_, deferred| !deferred.is_empty());
+        found
+    }
+
+    pub fn is_scheduled(&self, handle: ScheduleHandle) -> bool {
+        self.scheduled.values().flatten().any(|d| d.handle == handle)
+    }
+
+    // takes everything due by today, putting repeating work back for its next date
+    fn take_due(&mut self, today: Date) -> Vec<DeferredWork> {
+        let later = self.scheduled.split_off(&today.days_after(1));
+        let due = std::mem::replace(&mut self.scheduled, later);
+        let mut work = Vec::new();
+        for (date, deferred) in due.into_iter() {
+            for d in deferred.into_iter() {
+                if let (DeferredWork::Saved(saved), Some(next)) = (&d.work, d.recurrence.next(date)) {
+                    let repeat = Deferred { handle: d.handle, recurrence: d.recurrence, work: DeferredWork::Saved(saved.clone()) };
+                    self.scheduled.entry(next.max(today.days_after(1))).or_default().push(repeat);
+                }
+                work.push(d.work);
+            }
+        }
+        work
+    }
+
+    // commands that only live in the session aren't saved
+    pub fn save_data(&self) -> Vec<DeferredSaveData> {
+        self.scheduled
+            .iter()
+            .flat_map(|(&date, deferred)| deferred.iter().map(move |d| (date, d)))
+            .filter_map(|(date, d)| match &d.work {
+                DeferredWork::Saved(command) => Some(DeferredSaveData {
+                    handle: d.handle,
+                    date,
+                    recurrence: d.recurrence,
+                    command: command.clone(),
+                }),
+                DeferredWork::Boxed(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn load(&mut self, save_data: Vec<DeferredSaveData>) {
+        self.scheduled.clear();
+        for data in save_data.into_iter() {
+            self.next_handle = self.next_handle.max(data.handle.0 + 1);
+            self.scheduled.entry(data.date).or_default().push(Deferred {
+                handle: data.handle,
+                recurrence: data.recurrence,
+                work: DeferredWork::Saved(data.command),
+            });
         }
     }
 }
 
-pub fn day_run_criteria_system(
-    day: Res<CurrentDate>,
-) -> ShouldRun {
-    println!("day_run_criteria_system?? {}", *day);
-    if day.is_day {
-        ShouldRun::Yes
-    } else {
-        ShouldRun::No
+struct RunDeferredCommand(Vec<DeferredWork>);
+
+impl Command for RunDeferredCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        for work in self.0.into_iter() {
+            let command = match work {
+                DeferredWork::Boxed(command) => command,
+                DeferredWork::Saved(saved) => match world.get_resource::<CommandRegistry>().and_then(|registry| registry.build(&saved)) {
+                    Some(command) => command,
+                    None => {
+                        eprintln!("can't run scheduled command {}", saved.name);
+                        continue;
+                    },
+                },
+            };
+            command.write(world);
+        }
+    }
+}
+
+fn deferred_command_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    mut deferred: ResMut<DeferredCommands>,
+) {
+    if !date.is_day {
+        return;
+    }
+    let due = deferred.take_due(date.date);
+    if !due.is_empty() {
+        commands.add(RunDeferredCommand(due));
     }
 }
 
@@ -180,9 +453,153 @@ impl Plugin for TimePlugin {
                 },
                 ..Default::default()
             })
-            .insert_resource(GameSpeed(5))
+            .init_resource::<DeferredCommands>()
+            .init_resource::<CommandRegistry>()
+            .insert_resource(GameSpeed(4))
             .insert_resource(GamePaused(true))
+            .insert_resource(MaxDaysPerFrame(10))
+            .init_resource::<DatesPerSecond>()
+            .init_resource::<DayClock>()
+            .add_system_to_stage(CoreStage::PreUpdate, day_clock_system.system())
             .add_event::<TimeEvent>()
-            .add_system_to_stage(DayStage::Main, time_system.system().before(DAY_LABEL));
+            .add_system_to_stage(DayStage::Main, time_system.system().before(DAY_LABEL))
+            .add_system_to_day(deferred_command_system.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{thread_rng, Rng};
+    use serde::{Serialize, Deserialize};
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct TestCommand(u32);
+
+    impl Command for TestCommand {
+        fn write(self: Box<Self>, _world: &mut World) {}
+    }
+
+    impl SaveableCommand for TestCommand {
+        const NAME: &'static str = "test";
+    }
+
+    fn date(day: usize, month: usize, year: usize) -> Date {
+        Date { day, month, year }
+    }
+
+    #[test]
+    fn dates_order_like_their_days() {
+        let mut rng = thread_rng();
+        for _ in 0..1000 {
+            let a = rng.gen_range(0..1_000_000);
+            let b = rng.gen_range(0..1_000_000);
+            assert_eq!(Date::from_abs(a).cmp(&Date::from_abs(b)), a.cmp(&b));
+            assert_eq!(Date::from_abs(a).is_after(Date::from_abs(b)), a > b);
+        }
+    }
+
+    #[test]
+    fn days_after_day_of_year_wraps() {
+        let new_year = DayOfYear { day: 1, month: 1 };
+        let last_day = DayOfYear { day: 31, month: 12 };
+        assert_eq!(date(1, 1, 3).days_after_doy(new_year), 0);
+        assert!(date(1, 1, 3).is_day_of_year(new_year));
+        assert_eq!(date(1, 1, 3).days_after_doy(last_day), 1);
+        assert_eq!(date(31, 12, 3).days_after_doy(new_year), CALENDAR.days_in_year() - 1);
+    }
+
+    #[test]
+    fn years_between_dates() {
+        let born = date(15, 6, 10);
+        assert_eq!(date(14, 6, 30).years_since(born), 19);
+        assert_eq!(date(15, 6, 30).years_since(born), 20);
+        assert_eq!(born.years_after(20).years_before(20), born);
+        assert_eq!(born.years_since(born.years_after(1)), 0);
+    }
+
+    #[test]
+    fn dates_show_the_month_name() {
+        assert_eq!(date(3, 3, 12).to_string(), "3 March, year 12");
+    }
+
+    #[test]
+    fn only_due_work_is_taken() {
+        let mut deferred = DeferredCommands::default();
+        deferred.add_saved(date(1, 3, 1), &TestCommand(0));
+        deferred.add_saved(date(2, 3, 1), &TestCommand(1));
+        let later = deferred.add_saved(date(3, 3, 1), &TestCommand(2));
+        assert_eq!(deferred.take_due(date(28, 2, 1)).len(), 0);
+        // a missed day still runs
+        assert_eq!(deferred.take_due(date(2, 3, 1)).len(), 2);
+        assert_eq!(deferred.take_due(date(2, 3, 1)).len(), 0);
+        assert!(deferred.is_scheduled(later));
+        assert_eq!(deferred.take_due(date(3, 3, 1)).len(), 1);
+        assert!(!deferred.is_scheduled(later));
+    }
+
+    #[test]
+    fn cancelled_work_never_runs() {
+        let mut deferred = DeferredCommands::default();
+        let cancelled = deferred.add(date(1, 3, 1), Box::new(TestCommand(0)));
+        let kept = deferred.add(date(1, 3, 1), Box::new(TestCommand(1)));
+        assert!(deferred.cancel(cancelled));
+        assert!(!deferred.cancel(cancelled));
+        assert!(!deferred.is_scheduled(cancelled));
+        assert!(deferred.is_scheduled(kept));
+        assert_eq!(deferred.take_due(date(1, 3, 1)).len(), 1);
+    }
+
+    #[test]
+    fn recurrences_find_their_next_date() {
+        assert_eq!(Recurrence::Once.next(date(1, 1, 1)), None);
+        assert_eq!(Recurrence::EveryDays(10).next(date(25, 12, 1)), Some(date(4, 1, 2)));
+        assert_eq!(Recurrence::Yearly.next(date(28, 2, 1)), Some(date(28, 2, 2)));
+        let monthly = Recurrence::Monthly(31);
+        let mut next = date(31, 1, 1);
+        for &(day, month, year) in [(28, 2, 1), (31, 3, 1), (30, 4, 1), (31, 5, 1)].iter() {
+            next = monthly.next(next).unwrap();
+            assert_eq!(next, date(day, month, year));
+        }
+        assert_eq!(monthly.next(date(31, 12, 1)), Some(date(31, 1, 2)));
+    }
+
+    #[test]
+    fn repeating_work_keeps_its_handle() {
+        let mut deferred = DeferredCommands::default();
+        let handle = deferred.repeat(date(31, 1, 1), &TestCommand(0), Recurrence::Monthly(31));
+        assert_eq!(deferred.take_due(date(31, 1, 1)).len(), 1);
+        assert!(deferred.is_scheduled(handle));
+        assert_eq!(deferred.take_due(date(27, 2, 1)).len(), 0);
+        assert_eq!(deferred.take_due(date(28, 2, 1)).len(), 1);
+        assert_eq!(deferred.take_due(date(30, 3, 1)).len(), 0);
+        assert_eq!(deferred.take_due(date(31, 3, 1)).len(), 1);
+        assert!(deferred.cancel(handle));
+        assert_eq!(deferred.take_due(date(31, 12, 1)).len(), 0);
+    }
+
+    #[test]
+    fn saved_work_loads_back() {
+        let mut deferred = DeferredCommands::default();
+        deferred.add(date(1, 3, 1), Box::new(TestCommand(0)));
+        let once = deferred.add_saved(date(2, 3, 1), &TestCommand(1));
+        let yearly = deferred.repeat(date(3, 3, 1), &TestCommand(2), Recurrence::Yearly);
+        let save_data = deferred.save_data();
+        // work that only lives in the session isn't saved
+        assert_eq!(save_data.len(), 2);
+        let json = serde_json::to_string(&save_data).unwrap();
+
+        let mut loaded = DeferredCommands::default();
+        loaded.load(serde_json::from_str(&json).unwrap());
+        assert!(loaded.is_scheduled(once) && loaded.is_scheduled(yearly));
+        let fresh = loaded.add_saved(date(4, 3, 1), &TestCommand(3));
+        assert!(fresh != once && fresh != yearly);
+        let reloaded = loaded.save_data();
+        let saved = reloaded.iter().find(|d| d.handle == yearly).unwrap();
+        assert_eq!((saved.date, saved.recurrence), (date(3, 3, 1), Recurrence::Yearly));
+        assert_eq!(saved.command.name, "test");
+        assert_eq!(loaded.take_due(date(3, 3, 1)).len(), 2);
+        assert!(loaded.is_scheduled(yearly));
     }
 }
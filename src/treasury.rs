@@ -0,0 +1,269 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::{ecs::system::Command, prelude::*};
+
+use crate::prelude::*;
+use crate::agent::{AgentOption, Value};
+use crate::buildings::Buildings;
+use crate::decision::PlayerPolity;
+use crate::diplomacy::{Relations, TreatyKind};
+use crate::factor::FST;
+use crate::formula::FormulaSystem;
+use crate::notification::notify;
+use crate::polity::ProvinceOwner;
+use crate::pops::{GoodStorage, Polity, MONTHLY_CALORIE_NEED};
+use crate::province::ProvinceMap;
+use crate::tag::Selected;
+use crate::ui::InfoTag;
+use crate::urbanization::Occupations;
+
+// share of every harvest that goes to the polity
+pub const DEFAULT_TITHE: f32 = 0.1;
+// share of goods changing hands with trade partners that's taken at the markets
+pub const DEFAULT_TOLL: f32 = 0.05;
+const MAX_TAX: f32 = 0.5;
+// how far the council moves a tax at once
+const TAX_STEP: f32 = 0.05;
+// a tithe people pay without grumbling
+pub const FAIR_TITHE: f32 = 0.1;
+// yearly pressure to leave per point of tithe above fair, the harvest eases it off again
+const TAX_PRESSURE: f32 = 0.5;
+// share of a pop's stores traded each year with each trade partner, at most three partners
+const TRADED_SHARE: f32 = 0.1;
+const MAX_TRADE_PARTNERS: usize = 3;
+// the treasury should cover this many months of the whole polity's food
+const TREASURY_TARGET_MONTHS: f32 = 1.0;
+const LEDGER_LENGTH: usize = 10;
+
+pub struct Taxation {
+    pub tithe: f32,
+    pub toll: f32,
+}
+
+impl Default for Taxation {
+    fn default() -> Self {
+        Self {
+            tithe: DEFAULT_TITHE,
+            toll: DEFAULT_TOLL,
+        }
+    }
+}
+
+// what the polity itself owns
+pub struct Treasury(pub GoodStorage);
+
+impl Default for Treasury {
+    fn default() -> Self {
+        Self(GoodStorage(HashMap::new()))
+    }
+}
+
+// money in and out, in kcal of food, newest first
+pub struct LedgerEntry {
+    pub date: Date,
+    pub item: &'static str,
+    pub calories: f32,
+}
+
+#[derive(Default)]
+pub struct Ledger(pub VecDeque<LedgerEntry>);
+
+impl Ledger {
+    pub fn record(&mut self, date: Date, item: &'static str, calories: f32) {
+        if calories.abs() < 1.0 {
+            return;
+        }
+        self.0.push_front(LedgerEntry { date, item, calories });
+        self.0.truncate(LEDGER_LENGTH);
+    }
+}
+
+impl PolityRef {
+    pub fn treasury_calories(&self, world: &World) -> f32 {
+        self.try_get::<Treasury>(world).map(|treasury| treasury.0.calories()).unwrap_or(0.0)
+    }
+
+    pub fn deposit(&self, world: &mut World, goods: GoodStorage, item: &'static str) {
+        let date = world.get_resource::<CurrentDate>().unwrap().date;
+        let calories = goods.calories();
+        if let Some(mut treasury) = self.try_get_mut::<Treasury>(world) {
+            treasury.0.merge(goods);
+        }
+        if let Some(mut ledger) = self.try_get_mut::<Ledger>(world) {
+            ledger.record(date, item, calories);
+        }
+    }
+
+    // pay for something in food, as much as the treasury has
+    pub fn spend_calories(&self, world: &mut World, calories: f32, item: &'static str) -> GoodStorage {
+        let date = world.get_resource::<CurrentDate>().unwrap().date;
+        let spent = match self.try_get_mut::<Treasury>(world) {
+            Some(mut treasury) => treasury.0.take_calories(calories),
+            None => return GoodStorage(HashMap::new()),
+        };
+        if let Some(mut ledger) = self.try_get_mut::<Ledger>(world) {
+            ledger.record(date, item, -spent.calories());
+        }
+        spent
+    }
+}
+
+pub struct SetTaxCommand {
+    pub polity: PolityRef,
+    pub tithe: f32,
+}
+
+impl Command for SetTaxCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let tithe = self.tithe.max(0.0).min(MAX_TAX);
+        match self.polity.try_get_mut::<Taxation>(world) {
+            Some(mut taxation) => taxation.tithe = tithe,
+            None => return,
+        }
+        let text = format!("{} sets its tithe to {:.0}%", self.polity.name(world), tithe * 100.0);
+        notify(world, text);
+    }
+}
+
+// the council weighs a full treasury against a happy people
+pub fn taxation_options(world: &World, polity: PolityRef) -> Vec<AgentOption> {
+    let tithe = match polity.try_get::<Taxation>(world) {
+        Some(taxation) => taxation.tithe,
+        None => return Vec::new(),
+    };
+    let pops = polity.pops(world);
+    let loyalty = pops.iter().map(|pop| pop.get_factor(world, FactorType::PopLoyalty)).sum::<f32>() / pops.len().max(1) as f32;
+    let monthly_need = polity.population(world).max(1) as f32 * MONTHLY_CALORIE_NEED;
+    let stored_months = polity.treasury_calories(world) / monthly_need;
+    let shortfall = (TREASURY_TARGET_MONTHS - stored_months) / TREASURY_TARGET_MONTHS;
+    let mut options = Vec::new();
+    if tithe < MAX_TAX {
+        options.push(
+            AgentOption::new("Raise taxes")
+                .consider(Value::Prosperity, 0.5 * shortfall, format!("{:.1} months in the treasury", stored_months))
+                .consider(Value::Security, -0.3 - 2.0 * (tithe - FAIR_TITHE).max(0.0), format!("tithe {:.0}%", tithe * 100.0))
+                .command(Box::new(SetTaxCommand { polity, tithe: tithe + TAX_STEP }))
+        );
+    }
+    if tithe > 0.0 {
+        options.push(
+            AgentOption::new("Lower taxes")
+                .consider(Value::Security, -loyalty, format!("loyalty {:.2}", loyalty))
+                .consider(Value::Prosperity, -0.5 * shortfall - 0.1, format!("{:.1} months in the treasury", stored_months))
+                .command(Box::new(SetTaxCommand { polity, tithe: tithe - TAX_STEP }))
+        );
+    }
+    options
+}
+
+// traders with partner polities pay tolls at the markets
+fn toll_system(
+    date: Res<CurrentDate>,
+    relations: Res<Relations>,
+    mut polity_query: Query<(Entity, &Taxation, &mut Treasury, &mut Ledger), With<Polity>>,
+    mut pop_query: Query<(&PolityRef, &SettlementRef, &Occupations, &mut GoodStorage)>,
+    buildings_query: Query<&Buildings>,
+) {
+    if !date.is_year {
+        return;
+    }
+    let mut tolls: HashMap<PolityRef, f32> = HashMap::new();
+    for (ent, taxation, _, _) in polity_query.iter_mut() {
+        let polity = PolityRef(ent);
+        let partners = relations
+            .treaties_of(polity)
+            .filter(|treaty| treaty.kind == TreatyKind::TradeAccess)
+            .count()
+            .min(MAX_TRADE_PARTNERS);
+        if partners > 0 {
+            tolls.insert(polity, taxation.toll * TRADED_SHARE * partners as f32);
+        }
+    }
+    let mut collected: HashMap<PolityRef, GoodStorage> = HashMap::new();
+    for (polity, settlement, occupations, mut storage) in pop_query.iter_mut() {
+        if let Some(&toll) = tolls.get(polity) {
+            let trade = buildings_query.get(settlement.entity()).map(|b| b.trade()).unwrap_or(1.0) * occupations.trade();
+            let taken = storage.take_share((toll * trade).min(1.0));
+            collected.entry(*polity).or_insert_with(|| GoodStorage(HashMap::new())).merge(taken);
+        }
+    }
+    for (polity, goods) in collected.into_iter() {
+        if let Ok((_, _, mut treasury, mut ledger)) = polity_query.get_mut(polity.entity()) {
+            ledger.record(date.date, "tolls", goods.calories());
+            treasury.0.merge(goods);
+        }
+    }
+}
+
+// heavy taxes make people look elsewhere, the grudge against their rulers is counted with the rest of unrest
+fn tax_mood_system(
+    date: Res<CurrentDate>,
+    formula_system: Res<FormulaSystem<FST>>,
+    taxation_query: Query<&Taxation>,
+    pop_query: Query<(Entity, &PolityRef), With<Pop>>,
+) {
+    if !date.is_year {
+        return;
+    }
+    for (ent, polity) in pop_query.iter() {
+        let tithe = match taxation_query.get(polity.entity()) {
+            Ok(taxation) => taxation.tithe,
+            Err(_) => continue,
+        };
+        let excess = tithe - FAIR_TITHE;
+        if excess > 0.0 {
+            formula_system.add_factor(&PopRef(ent).fst(FactorType::PopPressure), excess * TAX_PRESSURE);
+        }
+    }
+}
+
+// the books of the selected province's polity, or the player's
+fn ledger_panel_system(
+    player: Res<PlayerPolity>,
+    province_map: Res<ProvinceMap>,
+    selected_query: Query<&MapCoordinate, With<Selected>>,
+    owner_query: Query<&ProvinceOwner>,
+    polity_query: Query<(&Polity, &Taxation, &Treasury, &Ledger)>,
+    mut info_tag_query: Query<(&InfoTag, &mut Text)>,
+) {
+    for (info_tag, mut text) in info_tag_query.iter_mut() {
+        if *info_tag != InfoTag::PolityLedger {
+            continue;
+        }
+        let polity = selected_query
+            .iter()
+            .next()
+            .and_then(|coordinate| province_map.0.get(coordinate))
+            .and_then(|province| owner_query.get(province.entity()).ok())
+            .map(|owner| owner.0)
+            .or(player.0);
+        let (info, taxation, treasury, ledger) = match polity.and_then(|polity| polity_query.get(polity.entity()).ok()) {
+            Some(polity) => polity,
+            None => continue,
+        };
+        let entries = ledger
+            .0
+            .iter()
+            .map(|entry| format!("{} {}: {:+.1}M kcal", entry.date, entry.item, entry.calories / 1_000_000.0))
+            .collect::<Vec<_>>();
+        text.sections[0].value = format!(
+            "{}\ntithe {:.0}%, tolls {:.0}%\ntreasury {:.1}M kcal\n{}",
+            info.name,
+            taxation.tithe * 100.0,
+            taxation.toll * 100.0,
+            treasury.0.calories() / 1_000_000.0,
+            entries.join("\n"),
+        );
+    }
+}
+
+pub struct TreasuryPlugin;
+
+impl Plugin for TreasuryPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_system_to_day(toll_system.system())
+            .add_system_to_day(tax_mood_system.system())
+            .add_system(ledger_panel_system.system());
+    }
+}
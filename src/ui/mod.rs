@@ -2,8 +2,8 @@ use bevy::{
     prelude::*,
 };
 use std::{borrow::BorrowMut, cell::{RefCell, RefMut}, rc::Rc, sync::{Arc, RwLock}};
-use crate::{pops::GlobalPopulation, prelude::*};
-use crate::{PopRef, pops::{Pop}, province::{Province, ProvinceMap}, time::{GamePaused, GameSpeed}};
+use crate::{agent::AgentDebug, decision::{EventQueue, PlayerPolity, ResolveEventCommand}, settlement::SettlementPops, notification::{Notifications, NotifyCommand}, pops::GlobalPopulation, prelude::*};
+use crate::{PopRef, pops::{Pop}, province::{Province, ProvinceMap}, time::{DatesPerSecond, GamePaused, GameSpeed}};
 use crate::time::Date;
 use super::tag::*;
 use super::map::{MapCoordinate, MapTileType, MapTile, HexMap};
@@ -72,6 +72,7 @@ pub fn change_button_system(
     >,
     mut map_editor_query: Query<&mut MapEditor>,
     mut info_box_mode: ResMut<InfoBoxMode>,
+    mut event_queue: ResMut<EventQueue>,
     // mut select_modifier: ResMut<SelectModifier>,
 ) {
     for (ui_button, interaction) in interaction_query.iter_mut() {
@@ -104,6 +105,14 @@ pub fn change_button_system(
                 UiButtonType::SaveMap => {
                     commands.add(SaveMapCommand);
                 }
+                UiButtonType::EventChoice(id, choice) => {
+                    if let Some(pending) = event_queue.take(id) {
+                        commands.add(ResolveEventCommand {
+                            pending,
+                            choice: Some(choice),
+                        });
+                    }
+                }
             }
             // for (map_editor_entity, _) in map_editor_query.iter() {
             //     if *change_tile_type != ChangeTileType(MapTileType::None) {
@@ -151,6 +160,8 @@ pub enum UiButtonType {
     BrushSizeType(isize),
     AddRiver,
     SaveMap,
+    // event id, choice
+    EventChoice(usize, usize),
     // SelectModifier(ModifierType),
  }
 pub struct UiButton(UiButtonType);
@@ -238,6 +249,29 @@ impl <'a> UiBuilder<'a> {
             ..Default::default()
         }
     }
+    pub fn modal(&self) -> NodeBundle {
+        NodeBundle {
+            style: Style {
+                size: Size {
+                    width: Val::Px(300.0),
+                    height: Val::Auto,
+                },
+                padding: Rect::all(Val::Px(5.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Stretch,
+                justify_content: JustifyContent::FlexStart,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Percent(40.0),
+                    top: Val::Percent(30.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            material: self.materials.from_material_type(UiMaterialType::BackgroundInfo),
+            ..Default::default()
+        }
+    }
     pub fn button(&self) -> ButtonBundle {
         self.button_material(UiMaterialType::DefaultButton)
     }
@@ -326,6 +360,9 @@ pub enum InfoBoxMode {
     ProvinceInfoMode,
     ModifierSelectList,
     ProvincePopList,
+    DiplomacyMode,
+    LedgerMode,
+    SettlementMode,
 }
 
 pub fn map_painting_box(
@@ -415,6 +452,7 @@ pub fn province_info_box(
             println!("bundle: {:?}", builder.info_tag(InfoTag::SelectedProvinceName));
             parent.spawn_bundle(builder.info_tag(InfoTag::SelectedProvinceName));
             parent.spawn_bundle(builder.info_tag(InfoTag::SelectedProvincePopulation));
+            parent.spawn_bundle(builder.info_tag(InfoTag::SelectedAgentDebug));
         })
         ;
     province_info_box.id()
@@ -441,6 +479,51 @@ pub fn modifier_select_box(
         .id()
 }
 
+pub fn diplomacy_box(
+    commands: &mut Commands,
+    builder: &UiBuilder,
+) -> Entity {
+    let mut info_box = commands
+        .spawn_bundle(builder.info_box());
+    info_box
+        .insert(UiContainer)
+        .insert(InfoBoxMode::DiplomacyMode)
+        .with_children(|parent| {
+            parent.spawn_bundle(builder.info_tag(InfoTag::PlayerDiplomacy));
+        })
+        .id()
+}
+
+pub fn ledger_box(
+    commands: &mut Commands,
+    builder: &UiBuilder,
+) -> Entity {
+    let mut info_box = commands
+        .spawn_bundle(builder.info_box());
+    info_box
+        .insert(UiContainer)
+        .insert(InfoBoxMode::LedgerMode)
+        .with_children(|parent| {
+            parent.spawn_bundle(builder.info_tag(InfoTag::PolityLedger));
+        })
+        .id()
+}
+
+pub fn settlement_box(
+    commands: &mut Commands,
+    builder: &UiBuilder,
+) -> Entity {
+    let mut info_box = commands
+        .spawn_bundle(builder.info_box());
+    info_box
+        .insert(UiContainer)
+        .insert(InfoBoxMode::SettlementMode)
+        .with_children(|parent| {
+            parent.spawn_bundle(builder.info_tag(InfoTag::SelectedSettlement));
+        })
+        .id()
+}
+
 pub fn pop_list_box(
     commands: &mut Commands,
     builder: &UiBuilder,
@@ -482,6 +565,15 @@ fn info_box_system(
                 InfoBoxMode::ProvincePopList => {
                     pop_list_box(&mut commands, &builder);
                 },
+                InfoBoxMode::DiplomacyMode => {
+                    diplomacy_box(&mut commands, &builder);
+                },
+                InfoBoxMode::LedgerMode => {
+                    ledger_box(&mut commands, &builder);
+                },
+                InfoBoxMode::SettlementMode => {
+                    settlement_box(&mut commands, &builder);
+                },
                 InfoBoxMode::AddRiverMode => {
                     add_river_box(&mut commands, &builder);
                 },
@@ -499,7 +591,12 @@ pub fn info_tag_system(
     date: Res<CurrentDate>,
     game_speed: Res<GameSpeed>,
     game_paused: Res<GamePaused>,
+    dates_per_second: Res<DatesPerSecond>,
     global_population: Res<GlobalPopulation>,
+    notifications: Res<Notifications>,
+    province_settlement_query: Query<&SettlementRef, With<Province>>,
+    settlement_query: Query<(&SettlementPops, &PolityRef)>,
+    agent_debug_query: Query<(&AgentDebug, Option<&Pop>)>,
 ) {
     for (info_tag, mut text) in info_tag_query.iter_mut() {
         let info_string = match info_tag {
@@ -524,13 +621,47 @@ pub fn info_tag_system(
                     "".to_string()
                 }
             },
+            // what the selected settlement's polity and biggest pop were last thinking
+            &InfoTag::SelectedAgentDebug => {
+                let selected = selected_query
+                    .iter()
+                    .next()
+                    .and_then(|(coord, _, _)| province_map.0.get(coord))
+                    .and_then(|province| province_settlement_query.get(province.0).ok())
+                    .and_then(|settlement| settlement_query.get(settlement.0).ok());
+                if let Some((pops, polity)) = selected {
+                    let pop_debug = pops
+                        .0
+                        .iter()
+                        .filter_map(|pop| agent_debug_query.get(pop.0).ok())
+                        .max_by_key(|(_, pop)| pop.map(|p| p.size).unwrap_or(0))
+                        .map(|(debug, _)| debug.0.join("\n"))
+                        .unwrap_or_default();
+                    let polity_debug = agent_debug_query
+                        .get(polity.0)
+                        .map(|(debug, _)| debug.0.join("\n"))
+                        .unwrap_or_default();
+                    format!("polity:\n{}\npop:\n{}", polity_debug, pop_debug)
+                } else {
+                    "".to_string()
+                }
+            },
             // &InfoTag::PopFactor(pop_ref, factor) => {
             //     let (pop, factors) = pop_query.get(pop_ref.0).unwrap();
             //     format!("{:?}: {}", factor, factors.factor(factor))
             // },
             &InfoTag::BrushSize => format!("{}", map_editor_query.iter().next().map(|me| me.brush_size).unwrap_or(0)),
-            &InfoTag::DateDisplay => format!("({}) {}", game_paused.0.then(|| "p").unwrap_or(format!("{}", game_speed.0).as_str()), *date),
+            &InfoTag::DateDisplay => format!("({}) {}", game_speed.label(&game_paused, &dates_per_second), *date),
             &InfoTag::GlobalPopulation => format!("total population: {}", global_population.0),
+            // filled in by the diplomacy plugin
+            &InfoTag::PlayerDiplomacy => continue,
+            // filled in by the treasury plugin
+            &InfoTag::PolityLedger => continue,
+            &InfoTag::SelectedSettlement => continue,
+            &InfoTag::LatestNotification => notifications
+                .latest()
+                .map(|(date, text)| format!("{}: {}", date, text))
+                .unwrap_or_default(),
             t => format!("{:?}", t),
         };
         text.sections[0].value = info_string;
@@ -544,12 +675,70 @@ pub enum InfoTag {
     DateDisplay,
     SelectedProvinceName,
     SelectedProvincePopulation,
+    SelectedAgentDebug,
+    PlayerDiplomacy,
+    PolityLedger,
+    SelectedSettlement,
     // PopFactor(PopRef, PopFactor),
     GlobalPopulation,
+    LatestNotification,
     BrushSize,
     Text(String),
 }
 
+// the player's event on screen, by event id
+pub struct EventModal(pub usize);
+
+pub fn event_modal(
+    commands: &mut Commands,
+    builder: &UiBuilder,
+    id: usize,
+    description: &str,
+    choice_names: &[String],
+) -> Entity {
+    commands
+        .spawn_bundle(builder.modal())
+        .insert(EventModal(id))
+        .with_children(|parent| {
+            parent.spawn_bundle(builder.text_info(description));
+            for (choice, name) in choice_names.iter().enumerate() {
+                parent.spawn_bundle(builder.button())
+                    .insert(UiButton(UiButtonType::EventChoice(id, choice)))
+                    .with_children(|parent| {
+                        parent.spawn_bundle(builder.text_info(name.as_str()));
+                    });
+            }
+        })
+        .id()
+}
+
+// show the oldest event waiting on the player, and pause until it's answered
+fn event_modal_system(
+    mut commands: Commands,
+    modal_query: Query<(Entity, &EventModal)>,
+    ui_materials: Res<UiMaterials>,
+    event_queue: Res<EventQueue>,
+    player: Res<PlayerPolity>,
+    mut game_paused: ResMut<GamePaused>,
+) {
+    let current = event_queue.player_event(&player);
+    let mut shown = false;
+    for (ent, modal) in modal_query.iter() {
+        if current.map(|pending| pending.id) == Some(modal.0) {
+            shown = true;
+        } else {
+            commands.entity(ent).despawn_recursive();
+        }
+    }
+    if let (false, Some(pending)) = (shown, current) {
+        let builder = UiBuilder {
+            materials: ui_materials,
+        };
+        event_modal(&mut commands, &builder, pending.id, &pending.description, &pending.choice_names);
+        game_paused.0 = true;
+    }
+}
+
 pub struct InfoBoxChangeCommand;
 
 pub fn ui_info_bar(
@@ -568,6 +757,9 @@ pub fn ui_info_bar(
             parent.spawn_bundle(builder.text_info(" | "));
             parent.spawn_bundle(builder.text_info(""))
                 .insert(InfoTag::GlobalPopulation);
+            parent.spawn_bundle(builder.text_info(" | "));
+            parent.spawn_bundle(builder.text_info(""))
+                .insert(InfoTag::LatestNotification);
         });
 
     info_bar.id()
@@ -620,8 +812,11 @@ impl Plugin for UiPlugin {
             .add_startup_system(setup_ui_assets.system())
             .add_startup_stage("ui_setup", ui_setup)
             .insert_resource(InfoBoxMode::ProvinceInfoMode)
+            .init_resource::<Notifications>()
+            .register_command::<NotifyCommand>()
             // .init_resource::<SelectModifier>()
             .add_system(info_tag_system.system())
+            .add_system(event_modal_system.system())
             .add_system(change_button_system.system())
             .add_system(info_box_system.system())
             .add_system(issue_1135_system.system())
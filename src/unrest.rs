@@ -0,0 +1,306 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{ecs::system::Command, prelude::*};
+use strum::{EnumIter, IntoEnumIterator};
+
+use crate::prelude::*;
+use crate::agent::ValueAgent;
+use crate::buildings::Buildings;
+use crate::decision::{GameEvent, PostEventCommand};
+use crate::factor::{AddFactorCommand, FST};
+use crate::formula::FormulaSystem;
+use crate::notification::notify;
+use crate::polity::{secede, PolityCapital, PolityHierarchy};
+use crate::pops::{GoodStorage, Hunger, MONTHLY_CALORIE_NEED};
+use crate::settlement::{Settlement, SettlementPops};
+use crate::treasury::{Taxation, FAIR_TITHE};
+use crate::war::{DeclareWarCommand, WarGoal};
+
+// how much each grievance adds to unrest
+const TAX_UNREST: f32 = 2.0;
+const HUNGER_UNREST: f32 = 1.5;
+const CULTURE_UNREST: f32 = 0.8;
+// per hex past what the polity can govern
+const DISTANCE_UNREST: f32 = 0.1;
+// share of the way unrest moves toward its causes each month
+const UNREST_DRIFT: f32 = 0.2;
+const MAX_UNREST: f32 = 2.0;
+// the owner hears about it past this
+const UNREST_NOTICE: f32 = 0.5;
+// past this the settlement may rise, more likely the higher it goes
+const REVOLT_THRESHOLD: f32 = 1.0;
+const REVOLT_CHANCE: f32 = 0.05;
+// months of the settlement's food handed out as concessions
+const CONCESSION_MONTHS: f32 = 1.0;
+// loyalty bought by concessions paid in full
+const CONCESSION_LOYALTY: f32 = 0.2;
+
+// what a settlement has against its polity, worked out fresh each month
+#[derive(Debug, Default)]
+pub struct Grievances {
+    pub taxes: f32,
+    pub hunger: f32,
+    pub culture: f32,
+    pub distance: f32,
+    pub loyalty: f32,
+}
+
+impl Grievances {
+    pub fn total(&self) -> f32 {
+        (self.taxes + self.hunger + self.culture + self.distance - self.loyalty).max(0.0).min(MAX_UNREST)
+    }
+
+    pub fn worst(&self) -> &'static str {
+        let causes = [
+            (self.taxes, "taxes"),
+            (self.hunger, "hunger"),
+            (self.culture, "foreign rulers"),
+            (self.distance, "a distant capital"),
+        ];
+        causes
+            .iter()
+            .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+            .map(|(_, cause)| *cause)
+            .unwrap()
+    }
+}
+
+// a settlement grumbles, the owner decides what to do about it
+pub struct UnrestEvent {
+    pub settlement: SettlementRef,
+    pub polity: PolityRef,
+}
+
+#[derive(EnumIter, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnrestChoice {
+    CrackDown,
+    MakeConcessions,
+    Ignore,
+}
+
+impl GameEvent for UnrestEvent {
+    type Choice = UnrestChoice;
+
+    fn description(&self, world: &World) -> String {
+        let cause = self.settlement.try_get::<Grievances>(world).map(|g| g.worst()).unwrap_or("nothing");
+        format!(
+            "{} is restless over {}",
+            self.settlement.get::<Settlement>(world).name,
+            cause,
+        )
+    }
+
+    fn choices(&self) -> Vec<UnrestChoice> {
+        UnrestChoice::iter().collect()
+    }
+
+    fn weigh_choice(&self, agent: &ValueAgent, world: &World, choice: UnrestChoice) -> f32 {
+        let unrest = self.settlement.get_factor(world, FactorType::SettlementUnrest);
+        let cost = concession_cost(world, self.settlement);
+        let affordable = (self.polity.treasury_calories(world) / cost.max(1.0)).min(1.0);
+        match choice {
+            UnrestChoice::CrackDown => unrest * agent.security - 0.2 * agent.tradition,
+            UnrestChoice::MakeConcessions => unrest * affordable * agent.tradition - 0.3 * agent.prosperity,
+            UnrestChoice::Ignore => (REVOLT_THRESHOLD - unrest) * agent.prosperity,
+        }
+    }
+
+    fn effects(&self, world: &World, choice: UnrestChoice) -> Vec<Box<dyn Command>> {
+        let unrest = self.settlement.fst(FactorType::SettlementUnrest);
+        let pops = self.settlement.get::<SettlementPops>(world).0.clone();
+        let loyalty = |amt: f32| {
+            pops.iter()
+                .map(|pop| Box::new(AddFactorCommand { target: pop.fst(FactorType::PopLoyalty), amt }) as Box<dyn Command>)
+                .collect::<Vec<_>>()
+        };
+        match choice {
+            // quiet for now, but they'll remember
+            UnrestChoice::CrackDown => {
+                let mut effects = loyalty(-0.1);
+                effects.push(Box::new(AddFactorCommand { target: unrest, amt: -0.5 }));
+                effects
+            },
+            UnrestChoice::MakeConcessions => vec![Box::new(ConcessionsCommand {
+                settlement: self.settlement,
+                polity: self.polity,
+            })],
+            UnrestChoice::Ignore => Vec::new(),
+        }
+    }
+
+    fn is_valid(&self, world: &World) -> bool {
+        self.settlement.try_get::<PolityRef>(world) == Some(&self.polity)
+    }
+
+    fn polities(&self) -> Vec<PolityRef> {
+        vec![self.polity]
+    }
+}
+
+fn concession_cost(world: &World, settlement: SettlementRef) -> f32 {
+    settlement.get::<Settlement>(world).population as f32 * MONTHLY_CALORIE_NEED * CONCESSION_MONTHS
+}
+
+// food from the treasury and a lighter hand, loyalty bought in proportion to what's paid
+pub struct ConcessionsCommand {
+    pub settlement: SettlementRef,
+    pub polity: PolityRef,
+}
+
+impl Command for ConcessionsCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let cost = concession_cost(world, self.settlement);
+        let mut gifts = self.polity.spend_calories(world, cost, "concessions");
+        let total = gifts.calories();
+        let paid = if cost > 0.0 { total / cost } else { 1.0 };
+        let pops = self.settlement.get::<SettlementPops>(world).0.clone();
+        let population = self.settlement.get::<Settlement>(world).population.max(1) as f32;
+        for pop in pops.into_iter() {
+            let share = pop.get::<Pop>(world).size as f32 / population;
+            let gift = gifts.take_calories(total * share);
+            pop.get_mut::<GoodStorage>(world).merge(gift);
+            world
+                .get_resource::<FormulaSystem<FST>>()
+                .unwrap()
+                .add_factor(&pop.fst(FactorType::PopLoyalty), CONCESSION_LOYALTY * paid);
+        }
+        let text = format!("{} makes concessions to {}", self.polity.name(world), self.settlement.get::<Settlement>(world).name);
+        notify(world, text);
+    }
+}
+
+// the settlement throws off its rulers, who march to take it back
+pub struct RevoltCommand {
+    pub settlement: SettlementRef,
+}
+
+impl Command for RevoltCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let from = *self.settlement.get::<PolityRef>(world);
+        if from.capital(world) == Some(self.settlement) {
+            return;
+        }
+        let rebels = match secede(world, self.settlement) {
+            Some(rebels) => rebels,
+            None => return,
+        };
+        self.settlement.set_factor(world, FactorType::SettlementUnrest, 0.0);
+        let text = format!(
+            "{} rises in revolt against {} as {}",
+            self.settlement.get::<Settlement>(world).name,
+            from.name(world),
+            rebels.name(world),
+        );
+        notify(world, text);
+        Box::new(DeclareWarCommand {
+            attacker: from,
+            defender: rebels,
+            goal: WarGoal::Conquest,
+        }).write(world);
+    }
+}
+
+// unrest follows a settlement's grievances, and boils over into revolt
+fn unrest_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    formula_system: Res<FormulaSystem<FST>>,
+    mut noticed: Local<HashSet<Entity>>,
+    mut settlement_query: Query<(Entity, &SettlementPops, &PolityRef, &MapCoordinate, &Buildings, &mut Grievances)>,
+    pop_query: Query<(&Pop, &Hunger, &CultureRef)>,
+    polity_query: Query<(&Taxation, &PolityHierarchy, Option<&PolityCapital>)>,
+    coordinate_query: Query<&MapCoordinate, With<Settlement>>,
+) {
+    if !date.is_month {
+        return;
+    }
+    // the culture of the capital is the culture of the realm
+    let mut core_cultures: HashMap<PolityRef, CultureRef> = HashMap::new();
+    for (ent, pops, &polity, _, _, _) in settlement_query.iter_mut() {
+        let is_capital = polity_query
+            .get(polity.entity())
+            .ok()
+            .and_then(|(_, _, capital)| capital)
+            .map(|capital| capital.0.entity() == ent)
+            .unwrap_or(false);
+        if !is_capital {
+            continue;
+        }
+        let mut sizes: HashMap<CultureRef, isize> = HashMap::new();
+        for pop in pops.0.iter() {
+            if let Ok((info, _, &culture)) = pop_query.get(pop.entity()) {
+                *sizes.entry(culture).or_insert(0) += info.size;
+            }
+        }
+        if let Some((culture, _)) = sizes.into_iter().max_by_key(|(_, size)| *size) {
+            core_cultures.insert(polity, culture);
+        }
+    }
+
+    for (ent, pops, &polity, &coordinate, buildings, mut grievances) in settlement_query.iter_mut() {
+        let settlement = SettlementRef(ent);
+        let (taxation, hierarchy, capital) = match polity_query.get(polity.entity()) {
+            Ok(polity) => polity,
+            Err(_) => continue,
+        };
+        let mut population = 0;
+        let mut hungry = 0.0;
+        let mut foreign = 0;
+        let mut loyalty = 0.0;
+        for pop in pops.0.iter() {
+            let (info, hunger, culture) = match pop_query.get(pop.entity()) {
+                Ok(pop) => pop,
+                Err(_) => continue,
+            };
+            population += info.size;
+            hungry += hunger.shortfall * info.size as f32;
+            if core_cultures.get(&polity).map(|core| core != culture).unwrap_or(false) {
+                foreign += info.size;
+            }
+            loyalty += formula_system.get_factor(&pop.fst(FactorType::PopLoyalty)) * info.size as f32;
+        }
+        if population <= 0 {
+            continue;
+        }
+        let distance = capital
+            .and_then(|capital| coordinate_query.get(capital.0.entity()).ok())
+            .map(|&capital| coordinate.distance(capital) - hierarchy.tier.reach())
+            .unwrap_or(0)
+            .max(0);
+        *grievances = Grievances {
+            taxes: (taxation.tithe - FAIR_TITHE) * TAX_UNREST,
+            hunger: hungry / population as f32 * HUNGER_UNREST,
+            culture: foreign as f32 / population as f32 * CULTURE_UNREST,
+            distance: distance as f32 * DISTANCE_UNREST,
+            loyalty: loyalty / population as f32 + buildings.calm(),
+        };
+        let unrest = formula_system.get_factor(&settlement.fst(FactorType::SettlementUnrest));
+        let unrest = unrest + (grievances.total() - unrest) * UNREST_DRIFT;
+        formula_system.set_factor(&settlement.fst(FactorType::SettlementUnrest), unrest);
+
+        if unrest < UNREST_NOTICE {
+            noticed.remove(&ent);
+            continue;
+        }
+        // the owner only hears about it when it starts
+        if noticed.insert(ent) {
+            commands.add(PostEventCommand {
+                owner: polity,
+                event: Box::new(UnrestEvent { settlement, polity }),
+            });
+        }
+        let is_capital = capital.map(|capital| capital.0 == settlement).unwrap_or(false);
+        if !is_capital && unrest > REVOLT_THRESHOLD && individual_event(REVOLT_CHANCE * (unrest - REVOLT_THRESHOLD + 0.1)) {
+            commands.add(RevoltCommand { settlement });
+        }
+    }
+}
+
+pub struct UnrestPlugin;
+
+impl Plugin for UnrestPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_system_to_day(unrest_system.system());
+    }
+}
@@ -0,0 +1,193 @@
+use bevy::prelude::*;
+
+use crate::prelude::*;
+use crate::buildings::{BuildingKind, Buildings};
+use crate::culture::CultureTrait;
+use crate::notification::Notifications;
+use crate::pops::Culture;
+use crate::settlement::{Settlement, SettlementPops};
+
+// yearly share of the way a pop's trades move toward what its settlement supports
+const OCCUPATION_DRIFT: f32 = 0.2;
+// merchant cultures send more of their people to the markets
+const MERCANTILE_MERCHANTS: f32 = 1.5;
+// harvest bonus from a pop made up entirely of artisans
+const ARTISAN_TOOLS: f32 = 1.0;
+// extra trade from a pop made up entirely of merchants
+const MERCHANT_TRADE: f32 = 5.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SettlementTier {
+    Hamlet,
+    Village,
+    Town,
+    City,
+}
+
+impl Default for SettlementTier {
+    fn default() -> Self {
+        SettlementTier::Hamlet
+    }
+}
+
+impl SettlementTier {
+    // towns need a market to grow around, cities a good deal more
+    pub fn of(population: isize, buildings: &Buildings) -> Self {
+        let market = buildings.has(BuildingKind::Market);
+        if population >= 3000 && market && buildings.built.len() >= 3 {
+            SettlementTier::City
+        } else if population >= 1000 && market {
+            SettlementTier::Town
+        } else if population >= 200 {
+            SettlementTier::Village
+        } else {
+            SettlementTier::Hamlet
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SettlementTier::Hamlet => "hamlet",
+            SettlementTier::Village => "village",
+            SettlementTier::Town => "town",
+            SettlementTier::City => "city",
+        }
+    }
+
+    // how many hexes of countryside around it answer to it
+    pub fn hinterland(&self) -> isize {
+        match self {
+            SettlementTier::Hamlet | SettlementTier::Village => 1,
+            SettlementTier::Town => 2,
+            SettlementTier::City => 3,
+        }
+    }
+
+    // shares of working people the settlement can keep in a trade, off the fields
+    pub fn artisans(&self) -> f32 {
+        match self {
+            SettlementTier::Hamlet => 0.0,
+            SettlementTier::Village => 0.05,
+            SettlementTier::Town => 0.15,
+            SettlementTier::City => 0.25,
+        }
+    }
+
+    pub fn merchants(&self) -> f32 {
+        match self {
+            SettlementTier::Hamlet | SettlementTier::Village => 0.0,
+            SettlementTier::Town => 0.05,
+            SettlementTier::City => 0.1,
+        }
+    }
+
+    fn icon_size(&self) -> f32 {
+        match self {
+            SettlementTier::Hamlet => 4.0,
+            SettlementTier::Village => 6.0,
+            SettlementTier::Town => 9.0,
+            SettlementTier::City => 12.0,
+        }
+    }
+}
+
+// shares of a pop's working people who don't farm
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Occupations {
+    // make tools that help the farmers along
+    pub artisans: f32,
+    // bring trade, and the tolls that go with it
+    pub merchants: f32,
+}
+
+impl Occupations {
+    pub fn farmers(&self) -> f32 {
+        (1.0 - self.artisans - self.merchants).max(0.0)
+    }
+
+    pub fn tools(&self) -> f32 {
+        self.artisans * ARTISAN_TOOLS
+    }
+
+    pub fn trade(&self) -> f32 {
+        1.0 + self.merchants * MERCHANT_TRADE
+    }
+}
+
+pub struct SettlementIconMaterial(pub Handle<ColorMaterial>);
+
+// settlements move up and down the tiers as they grow and build, and get a bigger mark on the map,
+// new ones get theirs straight away
+fn tier_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    material: Res<SettlementIconMaterial>,
+    mut notifications: ResMut<Notifications>,
+    mut settlement_query: Query<(Entity, &Settlement, &Buildings, &mut SettlementTier, Option<&Sprite>)>,
+) {
+    if !date.is_day {
+        return;
+    }
+    for (ent, info, buildings, mut tier, sprite) in settlement_query.iter_mut() {
+        let new_tier = if date.is_year || sprite.is_none() { SettlementTier::of(info.population, buildings) } else { *tier };
+        if new_tier == *tier && sprite.is_some() {
+            continue;
+        }
+        if sprite.is_some() && new_tier > *tier && new_tier >= SettlementTier::Town {
+            notifications.push(date.date, format!("{} has grown into a {}", info.name, new_tier.name()));
+        }
+        *tier = new_tier;
+        let size = new_tier.icon_size();
+        commands.entity(ent).insert_bundle(SpriteBundle {
+            material: material.0.clone(),
+            sprite: Sprite::new(Vec2::new(size, size)),
+            transform: Transform::from_xyz(0.0, 0.0, 2.0),
+            ..Default::default()
+        });
+    }
+}
+
+// people take up trades as their settlement grows big enough to support them
+fn occupation_system(
+    date: Res<CurrentDate>,
+    settlement_query: Query<(&SettlementTier, &SettlementPops)>,
+    mut pop_query: Query<(&CultureRef, &mut Occupations)>,
+    culture_query: Query<&Culture>,
+) {
+    if !date.is_year {
+        return;
+    }
+    for (tier, pops) in settlement_query.iter() {
+        for pop in pops.0.iter() {
+            let (culture, mut occupations) = match pop_query.get_mut(pop.entity()) {
+                Ok(pop) => pop,
+                Err(_) => continue,
+            };
+            let mercantile = culture_query
+                .get(culture.entity())
+                .map(|c| c.has_trait(CultureTrait::Mercantile))
+                .unwrap_or(false);
+            let merchants = tier.merchants() * if mercantile { MERCANTILE_MERCHANTS } else { 1.0 };
+            occupations.artisans += (tier.artisans() - occupations.artisans) * OCCUPATION_DRIFT;
+            occupations.merchants += (merchants - occupations.merchants) * OCCUPATION_DRIFT;
+        }
+    }
+}
+
+fn setup_settlement_icon_material(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    commands.insert_resource(SettlementIconMaterial(materials.add(Color::rgb(0.95, 0.9, 0.8).into())));
+}
+
+pub struct UrbanizationPlugin;
+
+impl Plugin for UrbanizationPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_startup_system(setup_settlement_icon_material.system())
+            .add_system_to_day(tier_system.system())
+            .add_system_to_day(occupation_system.system());
+    }
+}
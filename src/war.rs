@@ -0,0 +1,899 @@
+use std::collections::HashMap;
+
+use bevy::{ecs::system::Command, prelude::*};
+use rand::{thread_rng, Rng};
+
+use crate::prelude::*;
+use crate::agent::{AgentOption, Value};
+use crate::decision::{CalledToArmsEvent, PostEventCommand};
+use crate::diplomacy::{BreakTreatyCommand, ChangeOpinionCommand, Relations, SignTreatyCommand, TreatyKind};
+use crate::fortification::{Fortifications, Siege};
+use crate::map::{MapTile, MapTileType};
+use crate::migration::land_path;
+use crate::notification::notify;
+use crate::polity::{transfer_settlement, PolityBorders, ProvinceOwner};
+use crate::pops::{AgeCohorts, GoodStorage, Polity, PopLanguage, MONTHLY_CALORIE_NEED};
+use crate::province::ProvinceMap;
+use crate::settlement::Settlement;
+use crate::technology::Technology;
+use crate::treasury::{Ledger, Treasury};
+
+// share of a pop's working people called up when its polity goes to war
+const LEVY_SHARE: f32 = 0.1;
+// armies are slower than migrants, they have to keep together
+pub const ARMY_DAYS_PER_HEX: usize = 15;
+// monthly supply regained in friendly land and lost everywhere else
+const SUPPLY_GAIN: f32 = 0.3;
+const SUPPLY_LOSS: f32 = 0.15;
+// share of a month's food an army abroad needs sent from home, it forages the rest
+const ARMY_UPKEEP: f32 = 0.5;
+// share of an army lost each month with no supply at all
+const ATTRITION: f32 = 0.05;
+// defending your own land, and defending it from the hills
+const HOME_DEFENSE: f32 = 1.1;
+const MOUNTAIN_DEFENSE: f32 = 1.5;
+// levies with iron weapons fight this much better
+const IRON_STRENGTH: f32 = 1.3;
+// share of the losing army lost in a battle
+const BATTLE_LOSSES: f32 = 0.3;
+// war score for winning a battle
+const BATTLE_SCORE: f32 = 10.0;
+// war score for every enemy settlement held
+const OCCUPATION_SCORE: f32 = 10.0;
+// war score one side needs to force a peace
+const VICTORY_SCORE: f32 = 50.0;
+// wars that drag on this long end with whoever's ahead, in years
+const MAX_WAR_YEARS: usize = 5;
+// opinion lost on both sides when war is declared
+const WAR_OPINION: f32 = -50.0;
+// how much a refused call to arms is resented
+pub const REFUSED_CALL_OPINION: f32 = -30.0;
+// how much bigger a polity wants to be before picking a fight
+const WAR_SIZE_RATIO: f32 = 1.2;
+// and how much it has to dislike them
+const WAR_OPINION_THRESHOLD: f32 = -20.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WarGoal {
+    // the winner keeps the settlements it holds
+    Conquest,
+    // the loser pays tribute
+    Tribute,
+}
+
+#[derive(Debug, Clone)]
+pub struct War {
+    pub id: usize,
+    // the first of each is the one who started it or was attacked
+    pub attackers: Vec<PolityRef>,
+    pub defenders: Vec<PolityRef>,
+    pub goal: WarGoal,
+    pub started: Date,
+    // from battles, positive favours the attackers
+    pub battle_score: f32,
+}
+
+impl War {
+    pub fn attacker(&self) -> PolityRef {
+        self.attackers[0]
+    }
+
+    pub fn defender(&self) -> PolityRef {
+        self.defenders[0]
+    }
+
+    // Some(true) for the attacking side
+    pub fn side(&self, polity: PolityRef) -> Option<bool> {
+        if self.attackers.contains(&polity) {
+            Some(true)
+        } else if self.defenders.contains(&polity) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    pub fn hostile(&self, a: PolityRef, b: PolityRef) -> bool {
+        matches!((self.side(a), self.side(b)), (Some(x), Some(y)) if x != y)
+    }
+
+    pub fn friendly(&self, a: PolityRef, b: PolityRef) -> bool {
+        matches!((self.side(a), self.side(b)), (Some(x), Some(y)) if x == y)
+    }
+}
+
+#[derive(Default)]
+pub struct Wars {
+    pub wars: Vec<War>,
+    next_id: usize,
+}
+
+impl Wars {
+    pub fn get(&self, id: usize) -> Option<&War> {
+        self.wars.iter().find(|war| war.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut War> {
+        self.wars.iter_mut().find(|war| war.id == id)
+    }
+
+    pub fn of(&self, polity: PolityRef) -> impl Iterator<Item = &War> {
+        self.wars.iter().filter(move |war| war.side(polity).is_some())
+    }
+
+    pub fn at_war(&self, a: PolityRef, b: PolityRef) -> bool {
+        self.wars.iter().any(|war| war.hostile(a, b))
+    }
+
+    pub fn start(&mut self, attacker: PolityRef, defender: PolityRef, goal: WarGoal, started: Date) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.wars.push(War {
+            id,
+            attackers: vec![attacker],
+            defenders: vec![defender],
+            goal,
+            started,
+            battle_score: 0.0,
+        });
+        id
+    }
+
+    pub fn end(&mut self, id: usize) -> Option<War> {
+        let idx = self.wars.iter().position(|war| war.id == id)?;
+        Some(self.wars.remove(idx))
+    }
+}
+
+// a settlement held by the enemy, it changes hands for good only at the peace
+pub struct Occupied {
+    pub by: PolityRef,
+    pub war: usize,
+}
+
+// people called up from one pop, they go back there when the war's over
+pub struct Levy {
+    pub pop: PopRef,
+    pub settlement: SettlementRef,
+    pub culture: CultureRef,
+    pub language: LanguageRef,
+    pub religion: ReligionRef,
+    pub ages: AgeCohorts,
+    // raised where they know how to forge iron
+    pub iron: bool,
+}
+
+pub struct Army {
+    pub polity: PolityRef,
+    pub war: usize,
+    pub levies: Vec<Levy>,
+    pub path: Vec<MapCoordinate>,
+    pub step: usize,
+    pub departed: Date,
+    // 1.0 is well fed, 0.0 is living off nothing
+    pub supply: f32,
+    pub retreating: bool,
+}
+
+impl Army {
+    pub fn size(&self) -> isize {
+        self.levies.iter().map(|levy| levy.ages.size()).sum()
+    }
+
+    pub fn strength(&self) -> f32 {
+        let armed: f32 = self
+            .levies
+            .iter()
+            .map(|levy| levy.ages.size() as f32 * if levy.iron { IRON_STRENGTH } else { 1.0 })
+            .sum();
+        armed * (0.5 + 0.5 * self.supply)
+    }
+
+    // losses are spread over the levies by size, returns how many died
+    pub fn kill(&mut self, amount: isize) -> isize {
+        let total = self.size();
+        if total <= 0 {
+            return 0;
+        }
+        let amount = amount.min(total).max(0);
+        self.levies
+            .iter_mut()
+            .map(|levy| {
+                let share = amount * levy.ages.size() / total;
+                levy.ages.kill(share)
+            })
+            .sum()
+    }
+
+    pub fn set_route(&mut self, path: Vec<MapCoordinate>, today: Date) {
+        self.departed = today;
+        self.path = path;
+        self.step = 0;
+    }
+
+    // nowhere left to march to
+    pub fn idle(&self) -> bool {
+        self.step + 1 >= self.path.len()
+    }
+}
+
+pub struct ArmyMaterial(pub Handle<ColorMaterial>);
+
+pub fn spawn_army(world: &mut World, polity: PolityRef, war: usize, levies: Vec<Levy>, at: MapCoordinate) -> Entity {
+    let today = world.get_resource::<CurrentDate>().unwrap().date;
+    let material = world.get_resource::<ArmyMaterial>().map(|m| m.0.clone());
+    let mut army = world.spawn();
+    army
+        .insert(Army {
+            polity,
+            war,
+            levies,
+            path: vec![at],
+            step: 0,
+            departed: today,
+            supply: 1.0,
+            retreating: false,
+        })
+        .insert(at);
+    if let Some(material) = material {
+        army.insert_bundle(SpriteBundle {
+            material,
+            sprite: Sprite::new(Vec2::new(10.0, 10.0)),
+            transform: Transform::from_xyz(0.0, 0.0, 2.5),
+            ..Default::default()
+        });
+    }
+    army.id()
+}
+
+// send everyone home, to their own pop if it's still around
+pub fn disband_army(world: &mut World, army: Entity) {
+    if world.get_entity(army).is_none() {
+        return;
+    }
+    let army_c = match world.entity_mut(army).remove::<Army>() {
+        Some(army_c) => army_c,
+        None => return,
+    };
+    world.despawn(army);
+    for levy in army_c.levies.into_iter() {
+        if levy.ages.size() <= 0 {
+            continue;
+        }
+        if world.get_entity(levy.pop.entity()).is_some() {
+            levy.pop.add_people(world, &levy.ages, GoodStorage(HashMap::new()), None);
+        } else {
+            levy.settlement.settle_people(world, levy.culture, levy.language, levy.religion, levy.ages, GoodStorage(HashMap::new()), None);
+        }
+    }
+}
+
+// a polity that's gone loses the wars it leads and drops out of the rest, taking its armies home
+pub fn leave_wars(world: &mut World, polity: PolityRef) {
+    let wars = world
+        .get_resource::<Wars>()
+        .unwrap()
+        .of(polity)
+        .map(|war| (war.id, war.side(polity) == Some(true), war.attacker() == polity || war.defender() == polity))
+        .collect::<Vec<_>>();
+    for (id, attacking, leads) in wars.into_iter() {
+        if leads {
+            Box::new(PeaceCommand { war: id, victor: Some(!attacking) }).write(world);
+            continue;
+        }
+        if let Some(war) = world.get_resource_mut::<Wars>().unwrap().get_mut(id) {
+            war.attackers.retain(|p| *p != polity);
+            war.defenders.retain(|p| *p != polity);
+        }
+        let armies = world
+            .query::<(Entity, &Army)>()
+            .iter(world)
+            .filter(|(_, army)| army.war == id && army.polity == polity)
+            .map(|(ent, _)| ent)
+            .collect::<Vec<_>>();
+        for army in armies.into_iter() {
+            disband_army(world, army);
+        }
+        let occupied = world
+            .query::<(Entity, &Occupied)>()
+            .iter(world)
+            .filter(|(_, occupied)| occupied.war == id && occupied.by == polity)
+            .map(|(ent, _)| ent)
+            .collect::<Vec<_>>();
+        for settlement in occupied.into_iter() {
+            world.entity_mut(settlement).remove::<Occupied>();
+        }
+        let sieges = world
+            .query::<(Entity, &Siege)>()
+            .iter(world)
+            .filter(|(_, siege)| siege.war == id && siege.by == polity)
+            .map(|(ent, _)| ent)
+            .collect::<Vec<_>>();
+        for settlement in sieges.into_iter() {
+            world.entity_mut(settlement).remove::<Siege>();
+        }
+    }
+}
+
+fn province_at(world: &World, coordinate: MapCoordinate) -> Option<ProvinceRef> {
+    world.get_resource::<ProvinceMap>().unwrap().0.get(&coordinate).cloned()
+}
+
+pub struct RaiseLeviesCommand {
+    pub polity: PolityRef,
+    pub war: usize,
+}
+
+impl Command for RaiseLeviesCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let at = match self.polity.capital(world) {
+            Some(capital) => *capital.get::<MapCoordinate>(world),
+            None => return,
+        };
+        let mut levies = Vec::new();
+        for pop in self.polity.pops(world).into_iter() {
+            // few can be called away from sowing or harvest
+            let amount = (pop.get::<AgeCohorts>(world).working() as f32 * LEVY_SHARE * pop.spare_labor(world)) as isize;
+            if amount <= 0 {
+                continue;
+            }
+            let settlement = *pop.get::<SettlementRef>(world);
+            let culture = *pop.get::<CultureRef>(world);
+            let language = pop.get::<PopLanguage>(world).language;
+            let religion = *pop.get::<ReligionRef>(world);
+            let iron = settlement.knows(world, Technology::IronWorking);
+            let ages = pop.take_levy(world, amount);
+            levies.push(Levy {
+                pop,
+                settlement,
+                culture,
+                language,
+                religion,
+                ages,
+                iron,
+            });
+        }
+        if levies.is_empty() {
+            return;
+        }
+        let army = spawn_army(world, self.polity, self.war, levies, at);
+        let size = world.get::<Army>(army).unwrap().size();
+        let text = format!("{} raises an army of {}", self.polity.name(world), size);
+        notify(world, text);
+    }
+}
+
+pub struct DeclareWarCommand {
+    pub attacker: PolityRef,
+    pub defender: PolityRef,
+    pub goal: WarGoal,
+}
+
+impl Command for DeclareWarCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        if self.attacker == self.defender || world.get_resource::<Wars>().unwrap().at_war(self.attacker, self.defender) {
+            return;
+        }
+        for kind in [TreatyKind::Alliance, TreatyKind::NonAggression, TreatyKind::TradeAccess].iter() {
+            Box::new(BreakTreatyCommand { kind: *kind, a: self.attacker, b: self.defender }).write(world);
+        }
+        for (of, toward) in [(self.attacker, self.defender), (self.defender, self.attacker)].iter() {
+            Box::new(ChangeOpinionCommand { of: *of, toward: *toward, amount: WAR_OPINION }).write(world);
+        }
+        let today = world.get_resource::<CurrentDate>().unwrap().date;
+        let war = world.get_resource_mut::<Wars>().unwrap().start(self.attacker, self.defender, self.goal, today);
+        let text = format!("{} declares war on {}", self.attacker.name(world), self.defender.name(world));
+        notify(world, text);
+        Box::new(RaiseLeviesCommand { polity: self.attacker, war }).write(world);
+        Box::new(RaiseLeviesCommand { polity: self.defender, war }).write(world);
+
+        // both sides call on their friends
+        for (caller, enemy) in [(self.attacker, self.defender), (self.defender, self.attacker)].iter() {
+            let allies = world.get_resource::<Relations>().unwrap().allies(*caller);
+            for ally in allies.into_iter().filter(|ally| ally != enemy) {
+                Box::new(PostEventCommand {
+                    owner: ally,
+                    event: Box::new(CalledToArmsEvent {
+                        war,
+                        caller: *caller,
+                        callee: ally,
+                        enemy: *enemy,
+                    }),
+                }).write(world);
+            }
+        }
+    }
+}
+
+pub struct JoinWarCommand {
+    pub war: usize,
+    pub polity: PolityRef,
+    pub attacking: bool,
+}
+
+impl Command for JoinWarCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        {
+            let mut wars = world.get_resource_mut::<Wars>().unwrap();
+            let war = match wars.get_mut(self.war) {
+                Some(war) if war.side(self.polity).is_none() => war,
+                _ => return,
+            };
+            if self.attacking {
+                war.attackers.push(self.polity);
+            } else {
+                war.defenders.push(self.polity);
+            }
+        }
+        let (attacker, defender) = {
+            let war = world.get_resource::<Wars>().unwrap().get(self.war).unwrap();
+            (war.attacker(), war.defender())
+        };
+        let text = format!(
+            "{} joins the war between {} and {}",
+            self.polity.name(world),
+            attacker.name(world),
+            defender.name(world),
+        );
+        notify(world, text);
+        Box::new(RaiseLeviesCommand { polity: self.polity, war: self.war }).write(world);
+    }
+}
+
+// the war's over: hand over what was won, send the levies home
+pub struct PeaceCommand {
+    pub war: usize,
+    // Some(true) when the attackers won, None for a white peace
+    pub victor: Option<bool>,
+}
+
+impl Command for PeaceCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let war = match world.get_resource_mut::<Wars>().unwrap().end(self.war) {
+            Some(war) => war,
+            None => return,
+        };
+        let occupied = world
+            .query::<(Entity, &Occupied)>()
+            .iter(world)
+            .filter(|(_, occupied)| occupied.war == war.id)
+            .map(|(ent, occupied)| (SettlementRef(ent), occupied.by))
+            .collect::<Vec<_>>();
+        for &(settlement, _) in occupied.iter() {
+            world.entity_mut(settlement.entity()).remove::<Occupied>();
+        }
+        let sieges = world
+            .query::<(Entity, &Siege)>()
+            .iter(world)
+            .filter(|(_, siege)| siege.war == war.id)
+            .map(|(ent, _)| ent)
+            .collect::<Vec<_>>();
+        for settlement in sieges.into_iter() {
+            world.entity_mut(settlement).remove::<Siege>();
+        }
+        let armies = world
+            .query::<(Entity, &Army)>()
+            .iter(world)
+            .filter(|(_, army)| army.war == war.id)
+            .map(|(ent, _)| ent)
+            .collect::<Vec<_>>();
+        for army in armies.into_iter() {
+            disband_army(world, army);
+        }
+
+        let text = match self.victor {
+            Some(attackers_won) => {
+                let (winner, loser) = if attackers_won {
+                    (war.attacker(), war.defender())
+                } else {
+                    (war.defender(), war.attacker())
+                };
+                match war.goal {
+                    WarGoal::Conquest => {
+                        // everyone on the winning side keeps what they hold
+                        for (settlement, by) in occupied.into_iter() {
+                            if war.side(by) == Some(attackers_won) && world.get_entity(by.entity()).is_some() {
+                                transfer_settlement(world, settlement, by);
+                            }
+                        }
+                    },
+                    WarGoal::Tribute => {
+                        Box::new(SignTreatyCommand { kind: TreatyKind::Tributary, a: loser, b: winner }).write(world);
+                    },
+                }
+                format!("{} wins the war against {}", winner.name(world), loser.name(world))
+            },
+            None => format!("{} and {} make peace", war.attacker().name(world), war.defender().name(world)),
+        };
+        notify(world, text);
+    }
+}
+
+pub struct BattleCommand {
+    pub a: Entity,
+    pub b: Entity,
+    pub coordinate: MapCoordinate,
+}
+
+impl BattleCommand {
+    fn strength(world: &World, army: &Army, province: Option<ProvinceRef>) -> f32 {
+        let mut strength = army.strength();
+        if let Some(province) = province {
+            let wars = world.get_resource::<Wars>().unwrap();
+            let home = province
+                .try_get::<ProvinceOwner>(world)
+                .map(|owner| {
+                    owner.0 == army.polity || wars.get(army.war).map(|war| war.friendly(owner.0, army.polity)).unwrap_or(false)
+                })
+                .unwrap_or(false);
+            if home {
+                strength *= HOME_DEFENSE;
+                if province.get::<MapTile>(world).tile_type == MapTileType::Mountain {
+                    strength *= MOUNTAIN_DEFENSE;
+                }
+            }
+        }
+        strength * thread_rng().gen_range(0.8..1.2)
+    }
+}
+
+impl Command for BattleCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        if world.get::<Army>(self.a).is_none() || world.get::<Army>(self.b).is_none() {
+            return;
+        }
+        let province = province_at(world, self.coordinate);
+        let strength_a = Self::strength(world, world.get::<Army>(self.a).unwrap(), province);
+        let strength_b = Self::strength(world, world.get::<Army>(self.b).unwrap(), province);
+        let (winner, loser, ratio) = if strength_a >= strength_b {
+            (self.a, self.b, strength_b / strength_a.max(1.0))
+        } else {
+            (self.b, self.a, strength_a / strength_b.max(1.0))
+        };
+
+        // a close fight costs the winner nearly as much
+        let loser_killed = {
+            let mut army = world.get_mut::<Army>(loser).unwrap();
+            let losses = (army.size() as f32 * BATTLE_LOSSES) as isize;
+            army.kill(losses)
+        };
+        let winner_killed = world.get_mut::<Army>(winner).unwrap().kill((loser_killed as f32 * ratio) as isize);
+        let (winner_polity, loser_polity, war) = {
+            let (winner, loser) = (world.get::<Army>(winner).unwrap(), world.get::<Army>(loser).unwrap());
+            (winner.polity, loser.polity, winner.war)
+        };
+        if let Some(war) = world.get_resource_mut::<Wars>().unwrap().get_mut(war) {
+            match war.side(winner_polity) {
+                Some(true) => war.battle_score += BATTLE_SCORE,
+                Some(false) => war.battle_score -= BATTLE_SCORE,
+                None => {},
+            }
+        }
+        let text = format!(
+            "{} beats {} in battle, {} and {} dead",
+            winner_polity.name(world),
+            loser_polity.name(world),
+            winner_killed,
+            loser_killed,
+        );
+        notify(world, text);
+
+        // the losers fall back home
+        let home = loser_polity.capital(world).map(|capital| *capital.get::<MapCoordinate>(world));
+        let path = home.and_then(|home| land_path(world, self.coordinate, home));
+        match path {
+            Some(path) if world.get::<Army>(loser).unwrap().size() > 0 => {
+                let today = world.get_resource::<CurrentDate>().unwrap().date;
+                let mut army = world.get_mut::<Army>(loser).unwrap();
+                army.set_route(path, today);
+                army.retreating = true;
+            },
+            // nowhere to run, the survivors scatter home
+            _ => disband_army(world, loser),
+        }
+    }
+}
+
+pub fn occupy_settlement(world: &mut World, settlement: SettlementRef, polity: PolityRef, war: usize) {
+    world.entity_mut(settlement.entity()).insert(Occupied { by: polity, war });
+    let text = format!("{} occupies {}", polity.name(world), settlement.get::<Settlement>(world).name);
+    notify(world, text);
+}
+
+// an army with nowhere to go: take the place it's standing in if it can, then pick somewhere new
+pub struct ArmyOrdersCommand(pub Entity);
+
+impl ArmyOrdersCommand {
+    fn occupy(&self, world: &mut World, war: &War, polity: PolityRef, here: MapCoordinate) {
+        let settlement = match province_at(world, here).and_then(|p| p.try_get::<SettlementRef>(world).cloned()) {
+            Some(settlement) => settlement,
+            None => return,
+        };
+        // can't take it while it's still defended
+        let defended = world
+            .query::<(&Army, &MapCoordinate)>()
+            .iter(world)
+            .any(|(army, &coordinate)| coordinate == here && war.hostile(army.polity, polity));
+        if defended {
+            return;
+        }
+        let owner = *settlement.get::<PolityRef>(world);
+        let occupier = settlement.try_get::<Occupied>(world).map(|occupied| occupied.by);
+        if war.hostile(owner, polity) && occupier.map(|by| !war.friendly(by, polity)).unwrap_or(true) {
+            // walls have to be starved or worn down first
+            if settlement.get::<Fortifications>(world).level > 0 {
+                if settlement.try_get::<Siege>(world).is_none() {
+                    let started = world.get_resource::<CurrentDate>().unwrap().date;
+                    world.entity_mut(settlement.entity()).insert(Siege {
+                        army: self.0,
+                        by: polity,
+                        war: war.id,
+                        started,
+                        progress: 0.0,
+                    });
+                    let text = format!("{} besieges {}", polity.name(world), settlement.get::<Settlement>(world).name);
+                    notify(world, text);
+                }
+                return;
+            }
+            occupy_settlement(world, settlement, polity, war.id);
+        } else if war.friendly(owner, polity) && occupier.map(|by| war.hostile(by, polity)).unwrap_or(false) {
+            world.entity_mut(settlement.entity()).remove::<Occupied>();
+            let text = format!("{} liberates {}", polity.name(world), settlement.get::<Settlement>(world).name);
+            notify(world, text);
+        }
+    }
+
+    // the closest settlement that's the enemy's, or ours in enemy hands
+    fn target(world: &mut World, war: &War, polity: PolityRef, here: MapCoordinate) -> Option<MapCoordinate> {
+        world
+            .query::<(&Settlement, &PolityRef, &MapCoordinate, Option<&Occupied>)>()
+            .iter(world)
+            .filter(|&(_, &owner, _, occupied)| {
+                let occupier = occupied.map(|occupied| occupied.by);
+                (war.hostile(owner, polity) && occupier.map(|by| !war.friendly(by, polity)).unwrap_or(true))
+                    || (war.friendly(owner, polity) && occupier.map(|by| war.hostile(by, polity)).unwrap_or(false))
+            })
+            .map(|(_, _, &coordinate, _)| coordinate)
+            .min_by_key(|coordinate| here.distance(*coordinate))
+    }
+}
+
+impl Command for ArmyOrdersCommand {
+    fn write(self: Box<Self>, world: &mut World) {
+        let (polity, war, here, retreating) = match world.get::<Army>(self.0) {
+            Some(army) => (army.polity, army.war, *world.get::<MapCoordinate>(self.0).unwrap(), army.retreating),
+            None => return,
+        };
+        let war = match world.get_resource::<Wars>().unwrap().get(war).cloned() {
+            Some(war) => war,
+            None => {
+                disband_army(world, self.0);
+                return;
+            },
+        };
+        // a beaten army gets some rest before it marches again
+        if retreating {
+            world.get_mut::<Army>(self.0).unwrap().retreating = false;
+            return;
+        }
+        self.occupy(world, &war, polity, here);
+        let path = Self::target(world, &war, polity, here).and_then(|target| land_path(world, here, target));
+        if let Some(path) = path {
+            let today = world.get_resource::<CurrentDate>().unwrap().date;
+            world.get_mut::<Army>(self.0).unwrap().set_route(path, today);
+        }
+    }
+}
+
+// wars a polity's council could start with its neighbours
+pub fn war_options(world: &World, polity: PolityRef) -> Vec<AgentOption> {
+    let wars = world.get_resource::<Wars>().unwrap();
+    // one war at a time
+    if wars.of(polity).next().is_some() {
+        return Vec::new();
+    }
+    let relations = world.get_resource::<Relations>().unwrap();
+    let borders = world.get_resource::<PolityBorders>().unwrap();
+    let own_population = polity.population(world).max(1) as f32;
+    let target = borders
+        .neighbors(polity)
+        .into_iter()
+        .filter(|other| relations.opinion(polity, *other) < WAR_OPINION_THRESHOLD)
+        .filter(|other| {
+            !relations.has_treaty(TreatyKind::Alliance, polity, *other)
+                && !relations.has_treaty(TreatyKind::NonAggression, polity, *other)
+        })
+        .map(|other| (other, other.population(world) as f32 / own_population))
+        .filter(|(_, ratio)| *ratio * WAR_SIZE_RATIO < 1.0)
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+    let (other, ratio) = match target {
+        Some(target) => target,
+        None => return Vec::new(),
+    };
+    let opinion = relations.opinion(polity, other);
+    let declare = |goal: WarGoal, name: &'static str, value: Value, score: f32| {
+        AgentOption::new(name)
+            .consider(value, score, format!("{:.0}% our size", ratio * 100.0))
+            .consider(Value::Security, -0.3 - ratio / 2.0, "war is a gamble".to_owned())
+            .consider(Value::Tradition, -opinion / 200.0, format!("{} thinks {:.0} of them", polity.name(world), opinion))
+            .command(Box::new(DeclareWarCommand {
+                attacker: polity,
+                defender: other,
+                goal,
+            }))
+    };
+    vec![
+        declare(WarGoal::Conquest, "Declare war of conquest", Value::Expansion, 0.8 - ratio),
+        declare(WarGoal::Tribute, "Declare war for tribute", Value::Prosperity, 0.6 - ratio),
+    ]
+}
+
+fn army_movement_system(
+    date: Res<CurrentDate>,
+    mut army_query: Query<(&mut Army, &mut MapCoordinate)>,
+) {
+    if !date.is_day {
+        return;
+    }
+    for (mut army, mut coordinate) in army_query.iter_mut() {
+        let elapsed = date.date.abs_day().saturating_sub(army.departed.abs_day());
+        let step = (elapsed / ARMY_DAYS_PER_HEX).min(army.path.len().max(1) - 1);
+        if step != army.step {
+            army.step = step;
+            *coordinate = army.path[step];
+        }
+    }
+}
+
+fn army_orders_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    army_query: Query<(Entity, &Army)>,
+) {
+    if !date.is_week {
+        return;
+    }
+    for (ent, army) in army_query.iter() {
+        if army.idle() {
+            commands.add(ArmyOrdersCommand(ent));
+        }
+    }
+}
+
+// hostile armies in the same hex fight, one battle per hex a day
+fn battle_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    wars: Res<Wars>,
+    army_query: Query<(Entity, &Army, &MapCoordinate)>,
+) {
+    if !date.is_day {
+        return;
+    }
+    let mut armies_at: HashMap<MapCoordinate, Vec<(Entity, PolityRef)>> = HashMap::new();
+    for (ent, army, &coordinate) in army_query.iter() {
+        armies_at.entry(coordinate).or_insert_with(Vec::new).push((ent, army.polity));
+    }
+    for (coordinate, armies) in armies_at.into_iter() {
+        let battle = armies
+            .iter()
+            .enumerate()
+            .flat_map(|(i, a)| armies[i + 1..].iter().map(move |b| (*a, *b)))
+            .find(|((_, a), (_, b))| wars.at_war(*a, *b));
+        if let Some(((a, _), (b, _))) = battle {
+            commands.add(BattleCommand { a, b, coordinate });
+        }
+    }
+}
+
+// armies live off friendly land, anywhere else they need the treasury to send food
+fn supply_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    wars: Res<Wars>,
+    relations: Res<Relations>,
+    province_map: Res<ProvinceMap>,
+    owner_query: Query<&ProvinceOwner>,
+    mut treasury_query: Query<(&mut Treasury, &mut Ledger)>,
+    mut army_query: Query<(Entity, &mut Army, &MapCoordinate)>,
+) {
+    if !date.is_month {
+        return;
+    }
+    for (ent, mut army, coordinate) in army_query.iter_mut() {
+        let owner = province_map.0.get(coordinate).and_then(|p| owner_query.get(p.entity()).ok()).map(|owner| owner.0);
+        let friendly = owner
+            .map(|owner| {
+                owner == army.polity
+                    || relations.allies(army.polity).contains(&owner)
+                    || wars.get(army.war).map(|war| war.friendly(owner, army.polity)).unwrap_or(false)
+            })
+            .unwrap_or(false);
+        army.supply = if friendly {
+            (army.supply + SUPPLY_GAIN).min(1.0)
+        } else {
+            let need = army.size() as f32 * MONTHLY_CALORIE_NEED * ARMY_UPKEEP;
+            let paid = match treasury_query.get_mut(army.polity.entity()) {
+                Ok((mut treasury, mut ledger)) if need > 0.0 => {
+                    let paid = treasury.0.take_calories(need).calories();
+                    ledger.record(date.date, "army upkeep", -paid);
+                    paid / need
+                },
+                _ => 0.0,
+            };
+            (army.supply + SUPPLY_GAIN * paid - SUPPLY_LOSS * (1.0 - paid)).max(0.0).min(1.0)
+        };
+        let losses = (army.size() as f32 * ATTRITION * (1.0 - army.supply)) as isize;
+        army.kill(losses);
+        if army.size() <= 0 {
+            commands.entity(ent).despawn();
+        }
+    }
+}
+
+// wars end when one side is far enough ahead, one side is gone, or everyone's had enough
+fn war_resolution_system(
+    mut commands: Commands,
+    date: Res<CurrentDate>,
+    wars: Res<Wars>,
+    polity_query: Query<&Polity>,
+    settlement_query: Query<(&PolityRef, Option<&Occupied>), With<Settlement>>,
+) {
+    if !date.is_month {
+        return;
+    }
+    let mut settlements: HashMap<PolityRef, usize> = HashMap::new();
+    for (&owner, _) in settlement_query.iter() {
+        *settlements.entry(owner).or_insert(0) += 1;
+    }
+    let alive = |polity: PolityRef| {
+        polity_query.get(polity.entity()).is_ok() && settlements.get(&polity).cloned().unwrap_or(0) > 0
+    };
+    for war in wars.wars.iter() {
+        let occupation_score: f32 = settlement_query
+            .iter()
+            .filter_map(|(&owner, occupied)| occupied.filter(|o| o.war == war.id).map(|o| (owner, o.by)))
+            .map(|(owner, by)| match (war.side(owner), war.side(by)) {
+                (Some(false), Some(true)) => OCCUPATION_SCORE,
+                (Some(true), Some(false)) => -OCCUPATION_SCORE,
+                _ => 0.0,
+            })
+            .sum();
+        let score = war.battle_score + occupation_score;
+        let outcome = if !alive(war.defender()) || score >= VICTORY_SCORE {
+            Some(Some(true))
+        } else if !alive(war.attacker()) || score <= -VICTORY_SCORE {
+            Some(Some(false))
+        } else if date.date.years_since(war.started) >= MAX_WAR_YEARS {
+            Some(if score.abs() < OCCUPATION_SCORE { None } else { Some(score > 0.0) })
+        } else {
+            None
+        };
+        if let Some(victor) = outcome {
+            commands.add(PeaceCommand { war: war.id, victor });
+        }
+    }
+}
+
+fn setup_army_material(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    commands.insert_resource(ArmyMaterial(materials.add(Color::rgb(0.8, 0.1, 0.1).into())));
+}
+
+pub struct WarPlugin;
+
+impl Plugin for WarPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .init_resource::<Wars>()
+            .add_startup_system(setup_army_material.system())
+            .add_system_to_day(army_movement_system.system())
+            .add_system_to_day(army_orders_system.system())
+            .add_system_to_day(battle_system.system())
+            .add_system_to_day(supply_system.system())
+            .add_system_to_day(war_resolution_system.system());
+    }
+}